@@ -0,0 +1,661 @@
+use crate::error::{Result, SortError};
+use crate::multi::RunWindow;
+use crate::probe::OpenClVersion;
+use crate::sorter::{check_window, GpuSorter, AUTO_ATOMIC_MAX_LEN};
+use crate::stable::SortOrder;
+use opencl3::device::Device;
+use std::fmt;
+use std::mem;
+use std::ops::Range;
+
+/// Fraction of the device's global memory, as a divisor, that
+/// [`GpuSorter::choose_algorithm`] leaves free for the driver and other
+/// users of the device.
+const MEMORY_MARGIN_DIVISOR: u64 = 8;
+
+/// Number of evenly spaced elements [`GpuSorter::sort_auto`] checks for
+/// inversions before trying the insertion sort.
+const SORTEDNESS_SAMPLE_LEN: usize = 256;
+
+/// Element shifts per input element the insertion sort may make before
+/// [`GpuSorter::sort_auto`] gives up on it and sorts on the device.
+const INSERTION_SHIFTS_PER_ELEMENT: usize = 8;
+
+/// An algorithm [`GpuSorter::sort_auto`] can pick.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortAlgorithm {
+    /// [`GpuSorter::sort`].
+    Rank,
+    /// [`GpuSorter::sort_counting`].
+    Counting,
+    /// [`GpuSorter::sort_radix`].
+    Radix,
+    /// `slice::sort_unstable` on the host, used when no device algorithm's
+    /// buffers fit in device memory.
+    Host,
+    /// Insertion sort on the host, used when a sample of the input shows it
+    /// is already nearly sorted.
+    Insertion,
+    /// [`GpuSorter::reverse_gpu`], used when the input is in descending
+    /// order, so reversing it sorts it.
+    Reverse,
+    /// [`GpuSorter::sort_bitonic`], used for large inputs when the radix
+    /// sort's scratch buffers don't fit in device memory.
+    Bitonic,
+}
+
+impl SortAlgorithm {
+    /// The OpenCL C version the algorithm's kernels are written against.
+    pub fn required_opencl_version(self) -> OpenClVersion {
+        // Every kernel is built with BUILD_OPTIONS, -cl-std=CL1.2, so far.
+        OpenClVersion::new(1, 2)
+    }
+
+    /// Peak device memory in bytes the algorithm allocates to sort `n`
+    /// elements spanning `range` distinct values.
+    pub fn device_memory(self, n: usize, range: u64) -> u64 {
+        let element = mem::size_of::<i32>() as u64;
+        let n = n as u64;
+        match self {
+            // Input, counts and result.
+            SortAlgorithm::Rank => 3 * n * element,
+            // Input and result, plus bins and two scan buffers per value.
+            SortAlgorithm::Counting => (2 * n + 3 * range) * element,
+            // Two key buffers, flags and two scan buffers.
+            SortAlgorithm::Radix => 5 * n * element,
+            // The reversal works in place on one buffer.
+            SortAlgorithm::Reverse => n * element,
+            // One buffer padded to a power of two, at most twice the input.
+            SortAlgorithm::Bitonic => 2 * n * element,
+            SortAlgorithm::Host | SortAlgorithm::Insertion => 0,
+        }
+    }
+}
+
+impl fmt::Display for SortAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SortAlgorithm::Rank => write!(f, "rank"),
+            SortAlgorithm::Counting => write!(f, "counting"),
+            SortAlgorithm::Radix => write!(f, "radix"),
+            SortAlgorithm::Host => write!(f, "host"),
+            SortAlgorithm::Insertion => write!(f, "insertion"),
+            SortAlgorithm::Reverse => write!(f, "reverse"),
+            SortAlgorithm::Bitonic => write!(f, "bitonic"),
+        }
+    }
+}
+
+/// The largest number of elements `algorithm` can sort in one piece on
+/// `device`: enough that its [`SortAlgorithm::device_memory`] stays within
+/// the device's global memory less a margin, and that a single buffer of
+/// them, padded to a power of two for the bitonic sort, stays within the
+/// per-allocation cap. This is the size to split larger inputs into. The counting sort's bins are not counted, as they
+/// depend on the range rather than the length. Returns `usize::MAX` for the
+/// host algorithms, which allocate nothing on the device.
+pub fn max_chunk_elements(device: &Device, algorithm: SortAlgorithm) -> Result<usize> {
+    Ok(chunk_elements(
+        device.global_mem_size()?,
+        device.max_mem_alloc_size()?,
+        algorithm,
+    ))
+}
+
+/// [`max_chunk_elements`] for a device with `global_memory` bytes of global
+/// memory and allocations of at most `max_alloc` bytes.
+fn chunk_elements(global_memory: u64, max_alloc: u64, algorithm: SortAlgorithm) -> usize {
+    let per_element = algorithm.device_memory(1, 0);
+    if per_element == 0 {
+        return usize::MAX;
+    }
+    let budget = global_memory - global_memory / MEMORY_MARGIN_DIVISOR;
+    let mut per_buffer = max_alloc / mem::size_of::<i32>() as u64;
+    if algorithm == SortAlgorithm::Bitonic && per_buffer > 0 {
+        // Any length up to the largest power of two that fits pads to it.
+        per_buffer = 1 << per_buffer.ilog2();
+    }
+    let elements = (budget / per_element).min(per_buffer);
+    usize::try_from(elements).unwrap_or(usize::MAX)
+}
+
+/// [`GpuSorter::choose_algorithm`] for `n` elements spanning `range` values
+/// on a device of `version` with `budget` bytes to spare, where the counting
+/// sort may use at most `counting_bin_cap` bins.
+fn pick_algorithm(
+    n: usize,
+    range: u64,
+    counting_bin_cap: u64,
+    version: OpenClVersion,
+    budget: u64,
+) -> SortAlgorithm {
+    let counting_fits = range <= counting_bin_cap;
+    let preferred = if range <= n as u64 && counting_fits {
+        SortAlgorithm::Counting
+    } else if n <= AUTO_ATOMIC_MAX_LEN {
+        SortAlgorithm::Rank
+    } else {
+        SortAlgorithm::Radix
+    };
+    [
+        preferred,
+        SortAlgorithm::Radix,
+        SortAlgorithm::Bitonic,
+        SortAlgorithm::Rank,
+        SortAlgorithm::Counting,
+    ]
+    .into_iter()
+    .filter(|&algorithm| algorithm != SortAlgorithm::Counting || counting_fits)
+    // The rank sort's atomic kernels slow to a crawl past this length, so it
+    // is no fallback for larger inputs however little memory it needs.
+    .filter(|&algorithm| algorithm != SortAlgorithm::Rank || n <= AUTO_ATOMIC_MAX_LEN)
+    .filter(|algorithm| algorithm.required_opencl_version() <= version)
+    .find(|algorithm| algorithm.device_memory(n, range) <= budget)
+    .unwrap_or(SortAlgorithm::Host)
+}
+
+/// Counts the inversions among up to `SORTEDNESS_SAMPLE_LEN` evenly spaced
+/// elements of `input`, in order.
+fn sample_inversions(input: &[i32]) -> usize {
+    let step = input.len().div_ceil(SORTEDNESS_SAMPLE_LEN).max(1);
+    let sample: Vec<i32> = input.iter().step_by(step).copied().collect();
+    sample
+        .iter()
+        .enumerate()
+        .map(|(i, a)| sample[i + 1..].iter().filter(|b| a > b).count())
+        .sum()
+}
+
+/// Whether the same sample as [`sample_inversions`] is in descending order
+/// with at least one strict descent, which suggests `input` is reverse
+/// sorted.
+fn sample_descending(input: &[i32]) -> bool {
+    let step = input.len().div_ceil(SORTEDNESS_SAMPLE_LEN).max(1);
+    let sample: Vec<i32> = input.iter().step_by(step).copied().collect();
+    sample.windows(2).all(|pair| pair[0] >= pair[1]) && sample.first() > sample.last()
+}
+
+/// Insertion-sorts `values` unless that takes more than `budget` element
+/// shifts, returning whether it finished. On giving up, `values` is left
+/// as a permutation of its original contents.
+fn insertion_sort_bounded(values: &mut [i32], mut budget: usize) -> bool {
+    for i in 1..values.len() {
+        let value = values[i];
+        let mut j = i;
+        while j > 0 && values[j - 1] > value {
+            if budget == 0 {
+                values[j] = value;
+                return false;
+            }
+            budget -= 1;
+            values[j] = values[j - 1];
+            j -= 1;
+        }
+        values[j] = value;
+    }
+    true
+}
+
+impl GpuSorter {
+    /// Picks an algorithm for `input` from its length and, through the min
+    /// and max reductions, its range: the counting sort when the range is
+    /// smaller than the length, the rank sort for other small arrays, and
+    /// the radix sort otherwise. The counting sort is never picked when the
+    /// range exceeds [`GpuSorter::counting_bin_cap`].
+    ///
+    /// Algorithms whose [`SortAlgorithm::device_memory`] exceeds the device's
+    /// global memory, less a margin and the bytes held by the
+    /// [`GpuSorter::buffer_pool`], are passed over for the next that fits:
+    /// the radix sort, then the bitonic sort, which needs under half its
+    /// memory, then the others, falling back to [`SortAlgorithm::Host`] if
+    /// none does. The rank sort is only a fallback for arrays of up to 2048
+    /// elements. So are those whose
+    /// [`SortAlgorithm::required_opencl_version`] is newer than the device's.
+    pub fn choose_algorithm(&self, input: &[i32]) -> Result<SortAlgorithm> {
+        let n = input.len();
+        if n < 2 {
+            return Ok(SortAlgorithm::Rank);
+        }
+
+        let min = self.reduce_min(input)?.unwrap_or_default();
+        let max = self.reduce_max(input)?.unwrap_or_default();
+        let range = (max as i64 - min as i64) as u64 + 1;
+        let device = Device::new(self.context.default_device());
+        let version = OpenClVersion::of_device(&device)?;
+        let global_memory = device.global_mem_size()?;
+        let budget = (global_memory - global_memory / MEMORY_MARGIN_DIVISOR)
+            .saturating_sub(self.pool.pooled_bytes() as u64);
+        Ok(pick_algorithm(
+            n,
+            range,
+            self.counting_bin_cap as u64,
+            version,
+            budget,
+        ))
+    }
+
+    /// Sorts `input` with the algorithm [`GpuSorter::choose_algorithm`]
+    /// picks, returning which one was used.
+    ///
+    /// If a sample of `input` holds no inversions, an insertion sort on the
+    /// host is tried first and [`SortAlgorithm::Insertion`] is returned if it
+    /// finishes within a few shifts per element; otherwise the partly sorted
+    /// data is handed to the chosen algorithm. Constant input has no
+    /// inversions and needs no shifts, so it always takes this path and is
+    /// returned after one pass on the host, without touching the device.
+    ///
+    /// If instead the sample is in descending order, a pass over the whole
+    /// input on the device confirms it, and the input is reversed on the
+    /// device rather than sorted, returning [`SortAlgorithm::Reverse`]. The
+    /// sample costs a pass over at most 256 elements on the host; the
+    /// confirming pass is only made for a descending sample, and costs an
+    /// upload and one launch, which a failed guess wastes.
+    ///
+    /// When no device algorithm fits, the input is sorted on the host and
+    /// [`SortAlgorithm::Host`] returned; with the `tracing` feature, a
+    /// warning event is emitted too.
+    pub fn sort_auto(&self, input: &[i32]) -> Result<(Vec<i32>, SortAlgorithm)> {
+        self.sort_auto_window(input, 0..input.len())
+    }
+
+    /// Like [`GpuSorter::sort_auto`], but returns only `window` of the
+    /// sorted result. Whichever device algorithm is picked only transfers
+    /// that much of its result from the device.
+    ///
+    /// Returns [`SortError::InvalidRange`] if `window` doesn't fit in
+    /// `input`.
+    pub fn sort_auto_window(
+        &self,
+        input: &[i32],
+        window: Range<usize>,
+    ) -> Result<(Vec<i32>, SortAlgorithm)> {
+        check_window(&window, input.len())?;
+        let mut values = input.to_vec();
+        if sample_inversions(input) == 0
+            && insertion_sort_bounded(&mut values, input.len() * INSERTION_SHIFTS_PER_ELEMENT)
+        {
+            return Ok((values[window].to_vec(), SortAlgorithm::Insertion));
+        }
+        if sample_descending(input) && self.is_sorted_ordered_gpu(input, SortOrder::Descending)? {
+            let data = self.to_device(input)?;
+            let event = self.enqueue_reverse(data.buffer(), data.len())?;
+            let mut result = vec![0; window.len()];
+            self.read_result(data.buffer(), window.start, &mut result, &event)?;
+            return Ok((result, SortAlgorithm::Reverse));
+        }
+
+        let algorithm = self.choose_algorithm(&values)?;
+        #[cfg(feature = "tracing")]
+        if algorithm == SortAlgorithm::Host {
+            tracing::warn!(
+                n = values.len(),
+                "input doesn't fit in device memory, sorting on the host"
+            );
+        }
+        Ok((self.sort_with(algorithm, values, window)?, algorithm))
+    }
+
+    /// Sorts `values` with `algorithm`, returning `window` of the result,
+    /// which must fit in `values`. The host algorithms, and
+    /// [`SortAlgorithm::Reverse`], which only sorts descending input, sort
+    /// with `slice::sort_unstable`.
+    fn sort_with(
+        &self,
+        algorithm: SortAlgorithm,
+        mut values: Vec<i32>,
+        window: Range<usize>,
+    ) -> Result<Vec<i32>> {
+        Ok(match algorithm {
+            SortAlgorithm::Rank => self.sort_window(&values, window)?,
+            SortAlgorithm::Counting => self.sort_counting_window(&values, window)?,
+            SortAlgorithm::Radix => self.sort_radix_window(&values, window, None)?,
+            SortAlgorithm::Bitonic => self.sort_bitonic_profiled(&values, window, None)?.0,
+            SortAlgorithm::Host | SortAlgorithm::Insertion | SortAlgorithm::Reverse => {
+                values.sort_unstable();
+                values[window].to_vec()
+            }
+        })
+    }
+
+    /// Sorts `input` in pieces of `chunk_len` elements, the last perhaps
+    /// shorter, each with `algorithm` on the device in turn, then merges the
+    /// sorted pieces on the host. This sorts inputs too large for one piece:
+    /// [`max_chunk_elements`] gives the largest `chunk_len` that fits.
+    ///
+    /// Returns [`SortError::InvalidInput`] if `chunk_len` is 0.
+    pub fn sort_chunked(
+        &self,
+        input: &[i32],
+        algorithm: SortAlgorithm,
+        chunk_len: usize,
+    ) -> Result<Vec<i32>> {
+        self.sort_chunked_window(input, algorithm, chunk_len, 0..input.len())
+    }
+
+    /// Like [`GpuSorter::sort_chunked`], but returns only `window` of the
+    /// sorted result. Only the [`RunWindow`] of each sorted piece that the
+    /// window can come from is read back and merged.
+    ///
+    /// Returns [`SortError::InvalidInput`] if `chunk_len` is 0, and
+    /// [`SortError::InvalidRange`] if `window` doesn't fit in `input`.
+    ///
+    /// [`RunWindow`]: crate::multi::RunWindow
+    pub fn sort_chunked_window(
+        &self,
+        input: &[i32],
+        algorithm: SortAlgorithm,
+        chunk_len: usize,
+        window: Range<usize>,
+    ) -> Result<Vec<i32>> {
+        if chunk_len == 0 {
+            return Err(SortError::InvalidInput(
+                "the chunk size must be at least 1".to_string(),
+            ));
+        }
+        check_window(&window, input.len())?;
+        let span = RunWindow::new(&window, input.len());
+        let runs = input
+            .chunks(chunk_len)
+            .map(|chunk| self.sort_with(algorithm, chunk.to_vec(), span.of(chunk.len())))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(span.merge(&runs, window))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{assert_valid_sort, pseudo_random, test_sorter};
+
+    const CL_1_2: OpenClVersion = OpenClVersion::new(1, 2);
+
+    #[test]
+    fn device_memory_per_algorithm() {
+        let n = 1000;
+        assert_eq!(SortAlgorithm::Rank.device_memory(n, 0), 12_000);
+        assert_eq!(SortAlgorithm::Counting.device_memory(n, 10), 8_120);
+        assert_eq!(SortAlgorithm::Radix.device_memory(n, 0), 20_000);
+        assert_eq!(SortAlgorithm::Reverse.device_memory(n, 0), 4_000);
+        assert_eq!(SortAlgorithm::Bitonic.device_memory(n, 0), 8_000);
+        assert_eq!(SortAlgorithm::Host.device_memory(n, 10), 0);
+        assert_eq!(SortAlgorithm::Insertion.device_memory(n, 10), 0);
+    }
+
+    #[test]
+    fn device_memory_grows_with_length() {
+        for algorithm in [
+            SortAlgorithm::Rank,
+            SortAlgorithm::Counting,
+            SortAlgorithm::Radix,
+            SortAlgorithm::Reverse,
+            SortAlgorithm::Bitonic,
+        ] {
+            assert!(algorithm.device_memory(2000, 1) > algorithm.device_memory(1000, 1));
+        }
+    }
+
+    #[test]
+    fn chunk_elements_is_bounded_by_the_memory_budget() {
+        // 8 MiB less the eighth kept free is 7 MiB, so 7 Mi / 20 bytes per
+        // element for the radix sort's five buffers.
+        let global = 8 << 20;
+        assert_eq!(
+            chunk_elements(global, u64::MAX, SortAlgorithm::Radix),
+            (7 << 20) / 20
+        );
+        assert_eq!(
+            chunk_elements(global, u64::MAX, SortAlgorithm::Bitonic),
+            (7 << 20) / 8
+        );
+    }
+
+    #[test]
+    fn chunk_elements_is_bounded_by_the_allocation_cap() {
+        // One buffer may hold at most 1 MiB, 256 Ki elements.
+        assert_eq!(
+            chunk_elements(1 << 30, 1 << 20, SortAlgorithm::Rank),
+            1 << 18
+        );
+    }
+
+    #[test]
+    fn bitonic_chunks_pad_within_the_allocation_cap() {
+        // The padded buffer must fit too, so 3 MiB holds at most 512 Ki
+        // elements rather than 768 Ki.
+        let chunk = chunk_elements(1 << 30, 3 << 20, SortAlgorithm::Bitonic);
+        assert_eq!(chunk, 1 << 19);
+        assert!((chunk.next_power_of_two() * mem::size_of::<i32>()) as u64 <= 3 << 20);
+        // A power-of-two cap is used whole.
+        assert_eq!(
+            chunk_elements(1 << 30, 1 << 20, SortAlgorithm::Bitonic),
+            1 << 18
+        );
+        assert_eq!(chunk_elements(1 << 30, 3, SortAlgorithm::Bitonic), 0);
+    }
+
+    #[test]
+    fn chunk_elements_is_unbounded_on_the_host() {
+        assert_eq!(chunk_elements(0, 0, SortAlgorithm::Host), usize::MAX);
+        assert_eq!(chunk_elements(0, 0, SortAlgorithm::Insertion), usize::MAX);
+    }
+
+    #[test]
+    fn sort_chunked_rejects_empty_chunks() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        assert!(matches!(
+            sorter.sort_chunked(&[2, 1], SortAlgorithm::Radix, 0),
+            Err(SortError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn sort_chunked_sorts_across_chunks() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input = pseudo_random(1000, 9);
+        for algorithm in [SortAlgorithm::Radix, SortAlgorithm::Bitonic] {
+            for chunk_len in [1, 7, 256, 1000, 5000] {
+                let output = sorter.sort_chunked(&input, algorithm, chunk_len).unwrap();
+                assert_valid_sort(&input, &output, SortOrder::Ascending);
+            }
+        }
+    }
+
+    #[test]
+    fn sort_auto_sorts_random_input() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let descending: Vec<i32> = (0..3000).rev().collect();
+        let few_values: Vec<i32> = pseudo_random(3000, 5).iter().map(|v| v % 7).collect();
+        for input in [pseudo_random(3000, 4), descending, few_values] {
+            let (output, _) = sorter.sort_auto(&input).unwrap();
+            assert_valid_sort(&input, &output, SortOrder::Ascending);
+        }
+    }
+
+    #[test]
+    fn sort_auto_returns_constant_input_from_the_host() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let constant = vec![7; 1 << 20];
+        let (output, algorithm) = sorter.sort_auto(&constant).unwrap();
+        assert_eq!(algorithm, SortAlgorithm::Insertion);
+        assert_eq!(output, constant);
+    }
+
+    #[test]
+    fn sample_inversions_tell_sorted_from_shuffled_input() {
+        let increasing: Vec<i32> = (0..5000).collect();
+        assert_eq!(sample_inversions(&increasing), 0);
+        assert_eq!(sample_inversions(&[3; 5000]), 0);
+        assert!(sample_inversions(&pseudo_random(5000, 59)) > 0);
+        // The sample is strided, so a single element out of place between
+        // the sampled ones goes unseen; the bounded insertion sort fixes it.
+        let mut nearly = increasing.clone();
+        nearly.swap(1, 2);
+        assert_eq!(sample_inversions(&nearly), 0);
+        let budget = nearly.len();
+        assert!(insertion_sort_bounded(&mut nearly, budget));
+        assert_eq!(nearly, increasing);
+    }
+
+    #[test]
+    fn insertion_sort_gives_up_on_shuffled_input() {
+        let input = pseudo_random(5000, 61);
+        let mut values = input.clone();
+        assert!(!insertion_sort_bounded(
+            &mut values,
+            input.len() * INSERTION_SHIFTS_PER_ELEMENT
+        ));
+        let (mut left, mut right) = (values, input);
+        left.sort_unstable();
+        right.sort_unstable();
+        assert_eq!(left, right, "giving up lost or duplicated elements");
+    }
+
+    #[test]
+    fn sort_auto_takes_the_insertion_path_only_for_nearly_sorted_input() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let mut nearly: Vec<i32> = (0..5000).collect();
+        nearly.swap(1, 2);
+        nearly.swap(4000, 4001);
+        let (output, algorithm) = sorter.sort_auto(&nearly).unwrap();
+        assert_eq!(algorithm, SortAlgorithm::Insertion);
+        assert_valid_sort(&nearly, &output, SortOrder::Ascending);
+
+        let shuffled = pseudo_random(5000, 67);
+        let (output, algorithm) = sorter.sort_auto(&shuffled).unwrap();
+        assert_ne!(algorithm, SortAlgorithm::Insertion);
+        assert_valid_sort(&shuffled, &output, SortOrder::Ascending);
+    }
+
+    #[test]
+    fn pick_prefers_counting_for_small_ranges() {
+        let algorithm = pick_algorithm(10_000, 100, 1 << 16, CL_1_2, u64::MAX);
+        assert_eq!(algorithm, SortAlgorithm::Counting);
+    }
+
+    #[test]
+    fn pick_passes_over_counting_above_the_bin_cap() {
+        let algorithm = pick_algorithm(10_000, 100, 50, CL_1_2, u64::MAX);
+        assert_eq!(algorithm, SortAlgorithm::Radix);
+    }
+
+    #[test]
+    fn pick_falls_back_to_bitonic_when_radix_doesnt_fit() {
+        let n = 1 << 20;
+        let budget = SortAlgorithm::Radix.device_memory(n, 0) - 1;
+        let algorithm = pick_algorithm(n, u64::MAX, 0, CL_1_2, budget);
+        assert_eq!(algorithm, SortAlgorithm::Bitonic);
+    }
+
+    #[test]
+    fn pick_never_falls_back_to_rank_for_large_inputs() {
+        let n = AUTO_ATOMIC_MAX_LEN + 1;
+        // Room for the rank sort's buffers, but not the radix sort's.
+        let budget = SortAlgorithm::Rank.device_memory(n, 0);
+        let algorithm = pick_algorithm(n, u64::MAX, 0, CL_1_2, budget);
+        assert_eq!(algorithm, SortAlgorithm::Bitonic);
+        let algorithm = pick_algorithm(AUTO_ATOMIC_MAX_LEN, u64::MAX, 0, CL_1_2, budget);
+        assert_eq!(algorithm, SortAlgorithm::Rank);
+    }
+
+    #[test]
+    fn pick_falls_back_to_the_host_when_nothing_fits() {
+        let algorithm = pick_algorithm(1 << 20, u64::MAX, 0, CL_1_2, 0);
+        assert_eq!(algorithm, SortAlgorithm::Host);
+    }
+
+    #[test]
+    fn pick_passes_over_algorithms_the_device_version_lacks() {
+        let version = OpenClVersion::parse("OpenCL C 1.1 ").unwrap();
+        let algorithm = pick_algorithm(10_000, 100, 1 << 16, version, u64::MAX);
+        assert_eq!(algorithm, SortAlgorithm::Host);
+    }
+
+    #[test]
+    fn windowed_sorts_return_the_window_of_the_full_sort() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let random = pseudo_random(1000, 17);
+        let descending: Vec<i32> = (0..1000).rev().collect();
+        let narrow: Vec<i32> = random.iter().map(|value| value & 63).collect();
+        for input in [&random, &descending, &narrow] {
+            let mut expected = input.clone();
+            expected.sort_unstable();
+            for window in [0..5, 995..1000, 300..700, 0..0, 0..1000] {
+                let want = &expected[window.clone()];
+                assert_eq!(sorter.sort_window(input, window.clone()).unwrap(), want);
+                assert_eq!(
+                    sorter
+                        .sort_radix_window(input, window.clone(), None)
+                        .unwrap(),
+                    want
+                );
+                assert_eq!(
+                    sorter
+                        .sort_bitonic_profiled(input, window.clone(), None)
+                        .unwrap()
+                        .0,
+                    want
+                );
+                assert_eq!(
+                    sorter.sort_auto_window(input, window.clone()).unwrap().0,
+                    want
+                );
+                for algorithm in [SortAlgorithm::Radix, SortAlgorithm::Bitonic] {
+                    assert_eq!(
+                        sorter
+                            .sort_chunked_window(input, algorithm, 256, window.clone())
+                            .unwrap(),
+                        want
+                    );
+                }
+            }
+        }
+        let narrow_sorted = sorter.sort_counting(&narrow).unwrap();
+        assert_eq!(
+            sorter.sort_counting_window(&narrow, 10..20).unwrap(),
+            narrow_sorted[10..20]
+        );
+    }
+
+    #[test]
+    fn windowed_sorts_reject_windows_past_the_input() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input = [3, 1, 2];
+        assert!(matches!(
+            sorter.sort_auto_window(&input, 1..4),
+            Err(SortError::InvalidRange { end: 4, len: 3, .. })
+        ));
+        assert!(matches!(
+            sorter.sort_radix_window(&input, 0..4, None),
+            Err(SortError::InvalidRange { .. })
+        ));
+        assert!(matches!(
+            sorter.sort_chunked_window(&input, SortAlgorithm::Radix, 2, 0..4),
+            Err(SortError::InvalidRange { .. })
+        ));
+    }
+
+    #[test]
+    fn sort_auto_reverses_descending_input() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        // Ties included: equal neighbours don't stop a reversal.
+        let descending: Vec<i32> = (0..4096).rev().map(|i| i / 3).collect();
+        let (output, algorithm) = sorter.sort_auto(&descending).unwrap();
+        assert_eq!(algorithm, SortAlgorithm::Reverse);
+        assert_valid_sort(&descending, &output, SortOrder::Ascending);
+    }
+}
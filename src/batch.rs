@@ -0,0 +1,229 @@
+use crate::buffer::{fill_i32, read_into, upload};
+use crate::error::{Result, SortError};
+use crate::launch::SortKernel;
+use crate::sorter::{unfilled_slot, GpuSorter, UNFILLED};
+use opencl3::command_queue::{CommandQueue, CL_QUEUE_PROFILING_ENABLE};
+use opencl3::memory::{Buffer, CL_MEM_READ_WRITE, CL_MEM_WRITE_ONLY};
+use opencl3::types::cl_uint;
+use std::ptr;
+
+impl GpuSorter {
+    /// Sorts each of `arrays` with the compare and assign kernels, one array
+    /// after another on the sorter's queue. This is the serial baseline for
+    /// [`GpuSorter::sort_batch_pipelined`], which gives the same result.
+    pub fn sort_batch(&self, arrays: &[Vec<i32>]) -> Result<Vec<Vec<i32>>> {
+        arrays
+            .iter()
+            .map(|array| {
+                let mut sorted = array.clone();
+                if array.len() >= 2 {
+                    self.check_cancelled()?;
+                    self.sort_atomic(array, 0, &mut sorted)?;
+                }
+                Ok(sorted)
+            })
+            .collect()
+    }
+
+    /// Like [`GpuSorter::sort_batch`], but runs the compare kernels on the
+    /// sorter's queue and the assign kernels on a second queue, each assign
+    /// waiting only on its own array's compare. Nothing orders one array's
+    /// assign before the next array's compare, so a device that runs
+    /// commands from several queues at once can overlap them, and every
+    /// array is enqueued before any is read back.
+    ///
+    /// All of the arrays' buffers are held until the last one is read back,
+    /// so the batch needs device memory for the whole of it at once. The
+    /// second queue is created the first time it is needed.
+    pub fn sort_batch_pipelined(&self, arrays: &[Vec<i32>]) -> Result<Vec<Vec<i32>>> {
+        let assign_queue = self.assign_queue()?;
+
+        let mut pending = Vec::with_capacity(arrays.len());
+        for array in arrays {
+            let n = array.len();
+            if n < 2 {
+                pending.push(None);
+                continue;
+            }
+            self.check_cancelled()?;
+
+            let input_buffer = upload(&self.context, array)?;
+            let mut count_buffer = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+            let mut result_buffer = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+            let count_fill_event = fill_i32(&self.queue, &mut count_buffer, 0, n)?;
+            let result_fill_event = self
+                .checked
+                .then(|| fill_i32(&self.queue, &mut result_buffer, UNFILLED, n))
+                .transpose()?;
+
+            let compare_kernel_event =
+                self.enqueue_compare(&input_buffer, &count_buffer, n, &count_fill_event)?;
+
+            let mut assign = SortKernel::new(&self.assign_kernel, n)
+                .checked(self.checked)
+                .round_to(self.work_group_size)
+                .buffer(&input_buffer)?
+                .buffer(&count_buffer)?
+                .buffer(&result_buffer)?
+                .wait(&compare_kernel_event);
+            if let Some(event) = &result_fill_event {
+                assign = assign.wait(event);
+            }
+            let assign_kernel_event = assign.enqueue(assign_queue)?;
+
+            pending.push(Some((
+                input_buffer,
+                count_buffer,
+                result_buffer,
+                assign_kernel_event,
+            )));
+        }
+
+        let mut sorted = Vec::with_capacity(arrays.len());
+        let mut done = Vec::with_capacity(arrays.len());
+        for (array, pending) in arrays.iter().zip(pending) {
+            let mut out = array.clone();
+            if let Some((input_buffer, count_buffer, result_buffer, event)) = pending {
+                read_into(assign_queue, &result_buffer, 0, &mut out, &event)?;
+                done.push((array.len(), input_buffer, count_buffer, result_buffer));
+            }
+            sorted.push(out);
+        }
+
+        // The two queues don't order each other, so the buffers only go back
+        // to the pool once every read has completed.
+        for (n, _, count_buffer, result_buffer) in done {
+            self.pool.give(n, CL_MEM_READ_WRITE, count_buffer);
+            self.pool.give(n, CL_MEM_READ_WRITE, result_buffer);
+        }
+        if self.checked {
+            let unfilled = arrays
+                .iter()
+                .zip(&sorted)
+                .find_map(|(array, out)| unfilled_slot(array, 0, out));
+            if let Some(index) = unfilled {
+                return Err(SortError::IncompleteSort { index });
+            }
+        }
+        Ok(sorted)
+    }
+
+    /// The queue [`GpuSorter::sort_batch_pipelined`] runs its assign kernels
+    /// on, on the same device as the sorter's own.
+    fn assign_queue(&self) -> Result<&CommandQueue> {
+        if let Some(queue) = self.assign_queue.get() {
+            return Ok(queue);
+        }
+        let queue = CommandQueue::create(
+            &self.context,
+            self.context.default_device(),
+            CL_QUEUE_PROFILING_ENABLE,
+        )?;
+        Ok(self.assign_queue.get_or_init(|| queue))
+    }
+
+    /// Returns, for each of `arrays`, the permutation that sorts it, with
+    /// indices local to that array. Equal values keep their original order.
+    ///
+    /// All of the arrays are ranked in a single launch, each work-item only
+    /// comparing against the elements of its own segment.
+    pub fn argsort_batch(&self, arrays: &[Vec<i32>]) -> Result<Vec<Vec<u32>>> {
+        let n: usize = arrays.iter().map(Vec::len).sum();
+        if n == 0 {
+            return Ok(vec![Vec::new(); arrays.len()]);
+        }
+
+        let mut values = Vec::with_capacity(n);
+        let mut segments = Vec::with_capacity(n);
+        let mut starts = Vec::with_capacity(arrays.len() + 1);
+        for (segment, array) in arrays.iter().enumerate() {
+            starts.push(values.len() as cl_uint);
+            values.extend_from_slice(array);
+            segments.resize(values.len(), segment as cl_uint);
+        }
+        starts.push(n as cl_uint);
+
+        let value_buffer = upload(&self.context, &values)?;
+        let segment_buffer = upload(&self.context, &segments)?;
+        let start_buffer = upload(&self.context, &starts)?;
+        let index_buffer =
+            Buffer::<cl_uint>::create(&self.context, CL_MEM_WRITE_ONLY, n, ptr::null_mut())?;
+
+        let argsort_kernel_event = SortKernel::new(&self.segmented_argsort_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&value_buffer)?
+            .buffer(&segment_buffer)?
+            .buffer_of(&start_buffer, starts.len())?
+            .buffer(&index_buffer)?
+            .enqueue(&self.queue)?;
+
+        let indices = self.read_result_range(&index_buffer, 0, n, &argsort_kernel_event)?;
+
+        Ok(starts
+            .windows(2)
+            .map(|bounds| indices[bounds[0] as usize..bounds[1] as usize].to_vec())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::RankStrategy;
+    use crate::sorter::GpuSorter;
+    use crate::test_util::{assert_stable, pseudo_random, test_sorter, test_sorter_with};
+
+    #[test]
+    fn argsort_batch_is_stable() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let arrays: Vec<Vec<i32>> = [0, 1, 37, 200]
+            .iter()
+            .map(|&n| pseudo_random(n, n as u64).iter().map(|v| v % 8).collect())
+            .collect();
+        let indices = sorter.argsort_batch(&arrays).unwrap();
+        assert_eq!(indices.len(), arrays.len());
+        for (array, indices) in arrays.iter().zip(&indices) {
+            assert_stable(array, indices);
+            let sorted: Vec<i32> = indices.iter().map(|&i| array[i as usize]).collect();
+            assert!(sorted.windows(2).all(|pair| pair[0] <= pair[1]));
+        }
+    }
+
+    #[test]
+    fn pipelined_batches_match_the_serial_batch() {
+        let arrays = vec![
+            pseudo_random(500, 3),
+            Vec::new(),
+            vec![3],
+            // Duplicates, which every compare kernel must give distinct
+            // ranks.
+            vec![3, 1, 3, 2, 1],
+            vec![-1; 300],
+            pseudo_random(200, 4).iter().map(|v| v % 4).collect(),
+        ];
+        for strategy in [
+            RankStrategy::Atomic,
+            RankStrategy::Tiled,
+            RankStrategy::Loop,
+        ] {
+            let Some(sorter) = test_sorter_with(GpuSorter::builder().rank_strategy(strategy))
+            else {
+                return;
+            };
+            let serial = sorter.sort_batch(&arrays).unwrap();
+            for (sorted, array) in serial.iter().zip(&arrays) {
+                let mut expected = array.clone();
+                expected.sort_unstable();
+                assert_eq!(*sorted, expected, "{:?}", strategy);
+            }
+            assert_eq!(
+                sorter.sort_batch_pipelined(&arrays).unwrap(),
+                serial,
+                "{:?}",
+                strategy
+            );
+        }
+    }
+}
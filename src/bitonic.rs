@@ -0,0 +1,175 @@
+use crate::buffer::write_i32;
+use crate::error::Result;
+use crate::launch::SortKernel;
+use crate::profiling::{event_duration_ns, ProfilingReport};
+use crate::sorter::{check_window, GpuSorter};
+use opencl3::event::Event;
+use opencl3::memory::{Buffer, CL_MEM_READ_WRITE};
+use opencl3::types::{cl_int, cl_uint};
+use std::ops::Range;
+use std::time::SystemTime;
+
+impl GpuSorter {
+    /// Sorts `input` in place on the device with the bitonic sorting
+    /// network. The input is padded with `i32::MAX` up to the next power of
+    /// two `p`, which the network needs, and the padding is dropped from the
+    /// result. That is `log2(p) * (log2(p) + 1) / 2` launches of `p / 2`
+    /// work-items each, O(n log² n) work in all, so unlike
+    /// [`GpuSorter::sort`] it stays practical far past a few thousand
+    /// elements, and needs no scratch buffers.
+    pub fn sort_bitonic(&self, input: &[i32]) -> Result<Vec<i32>> {
+        Ok(self.sort_bitonic_profiled(input, 0..input.len(), None)?.0)
+    }
+
+    /// Like [`GpuSorter::sort_bitonic`], but returns only `window` of the
+    /// sorted result, transferring only that much from the device, along
+    /// with the summed device time of every network stage as `compare_ns`,
+    /// if the driver reports them all, and the host wall-clock time of the
+    /// whole sort, to compare against [`GpuSorter::ranks_profiled`] and the
+    /// CLI's rank timings.
+    ///
+    /// If given, `progress` is called on the host after each network stage
+    /// has been enqueued with the number of stages done and the total.
+    ///
+    /// Returns [`SortError::InvalidRange`](crate::SortError::InvalidRange)
+    /// if `window` doesn't fit in `input`.
+    pub fn sort_bitonic_profiled(
+        &self,
+        input: &[i32],
+        window: Range<usize>,
+        progress: Option<&dyn Fn(usize, usize)>,
+    ) -> Result<(Vec<i32>, ProfilingReport)> {
+        let n = input.len();
+        check_window(&window, n)?;
+        if n < 2 {
+            return Ok((input[window].to_vec(), ProfilingReport::default()));
+        }
+
+        let start = SystemTime::now();
+        let padded_len = n.next_power_of_two();
+        let mut padded = input.to_vec();
+        padded.resize(padded_len, i32::MAX);
+        let mut data = self
+            .pool
+            .take(&self.context, padded_len, CL_MEM_READ_WRITE)?;
+        // The buffer goes back to the pool whether or not the sort finished.
+        let sorted = self.bitonic_network(&mut data, &padded, window, progress);
+        self.pool.give(padded_len, CL_MEM_READ_WRITE, data);
+        let (result, events) = sorted?;
+        let end = SystemTime::now();
+
+        // The final read has waited for the last stage, and the queue is in
+        // order, so every stage's profiling information is available now.
+        let compare_ns = events
+            .iter()
+            .try_fold(0, |sum, event| Some(sum + event_duration_ns(event)?));
+        let report = ProfilingReport {
+            compare_ns,
+            assign_ns: None,
+            host_wall_ns: end.duration_since(start).unwrap_or_default().as_nanos() as u64,
+        };
+        Ok((result, report))
+    }
+
+    /// Uploads `padded` into `data`, runs every stage of the network over it
+    /// and reads back `window` of the result, which must lie within the
+    /// unpadded input. Returns it with the events of the stages, in order.
+    fn bitonic_network(
+        &self,
+        data: &mut Buffer<cl_int>,
+        padded: &[i32],
+        window: Range<usize>,
+        progress: Option<&dyn Fn(usize, usize)>,
+    ) -> Result<(Vec<i32>, Vec<Event>)> {
+        let padded_len = padded.len();
+        let log = padded_len.trailing_zeros() as usize;
+        let total = log * (log + 1) / 2;
+        let upload = write_i32(&self.queue, data, "bitonic data", padded)?;
+        let mut stages: Vec<Event> = Vec::new();
+        let mut k = 2;
+        while k <= padded_len {
+            self.check_cancelled()?;
+            let mut j = k / 2;
+            while j > 0 {
+                let stage = SortKernel::new(&self.bitonic_step_kernel, padded_len / 2)
+                    .checked(self.checked)
+                    .round_to(self.work_group_size)
+                    .buffer(&*data)?
+                    .scalar(&(j as cl_uint))
+                    .scalar(&(k as cl_uint))
+                    .wait(stages.last().unwrap_or(&upload))
+                    .enqueue(&self.queue)?;
+                stages.push(stage);
+                if let Some(progress) = progress {
+                    progress(stages.len(), total);
+                }
+                j /= 2;
+            }
+            k *= 2;
+        }
+
+        let result = self.read_result_range(
+            data,
+            window.start,
+            window.len(),
+            stages.last().unwrap_or(&upload),
+        )?;
+        Ok((result, stages))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stable::SortOrder;
+    use crate::test_util::{assert_valid_sort, pseudo_random, test_sorter};
+    use std::cell::RefCell;
+
+    #[test]
+    fn bitonic_sorts_random_input() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        // Not a power of two, so the padding is exercised too.
+        for n in [0, 1, 2, 3, 1000] {
+            let input = pseudo_random(n, n as u64);
+            assert_valid_sort(
+                &input,
+                &sorter.sort_bitonic(&input).unwrap(),
+                SortOrder::Ascending,
+            );
+        }
+    }
+
+    #[test]
+    fn bitonic_reports_every_stage() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        // 1000 pads to 1024 = 2^10, so 10 * 11 / 2 stages.
+        let input = pseudo_random(1000, 8);
+        let calls = RefCell::new(Vec::new());
+        let progress = |done, total| calls.borrow_mut().push((done, total));
+        let (output, report) = sorter
+            .sort_bitonic_profiled(&input, 0..1000, Some(&progress))
+            .unwrap();
+        assert_valid_sort(&input, &output, SortOrder::Ascending);
+        // There is no assign kernel to time.
+        assert_eq!(report.assign_ns, None);
+        let expected: Vec<(usize, usize)> = (1..=55).map(|done| (done, 55)).collect();
+        assert_eq!(calls.into_inner(), expected);
+    }
+
+    #[test]
+    fn bitonic_keeps_keys_equal_to_the_padding() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        // The padding is i32::MAX, which real keys can equal.
+        for input in [vec![i32::MAX, 1, i32::MAX], vec![2, 1], vec![i32::MAX; 5]] {
+            let (output, _) = sorter
+                .sort_bitonic_profiled(&input, 0..input.len(), None)
+                .unwrap();
+            assert_valid_sort(&input, &output, SortOrder::Ascending);
+        }
+    }
+}
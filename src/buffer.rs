@@ -0,0 +1,275 @@
+use crate::error::{Result, SortError};
+use crate::profiling::phase_span;
+use opencl3::command_queue::CommandQueue;
+use opencl3::context::Context;
+use opencl3::event::Event;
+use opencl3::memory::{Buffer, ClMem, CL_MAP_READ, CL_MEM_COPY_HOST_PTR, CL_MEM_READ_ONLY};
+use opencl3::types::{cl_int, cl_mem, cl_mem_flags, CL_BLOCKING};
+use std::ffi::c_void;
+use std::mem;
+use std::ptr;
+
+/// A device buffer that knows how many elements it holds, so writes, reads
+/// and fills cover exactly that many without the length being passed
+/// alongside it.
+pub struct SizedBuffer<T> {
+    buf: Buffer<T>,
+    len: usize,
+}
+
+impl<T> SizedBuffer<T> {
+    /// Creates an uninitialised buffer of `len` elements with `flags`.
+    /// Zero-sized buffers are invalid in OpenCL, so `len` must not be 0.
+    pub fn create(context: &Context, flags: cl_mem_flags, len: usize) -> Result<Self> {
+        if len == 0 {
+            return Err(SortError::InvalidInput(
+                "a device buffer needs at least one element".to_string(),
+            ));
+        }
+        let buf = Buffer::create(context, flags, len, ptr::null_mut())?;
+        Ok(SizedBuffer { buf, len })
+    }
+
+    /// Wraps `buf`, which holds exactly `len` elements.
+    pub(crate) fn from_buffer(buf: Buffer<T>, len: usize) -> Self {
+        SizedBuffer { buf, len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The underlying buffer, to pass as a kernel argument. Pass this rather
+    /// than the `SizedBuffer` itself, which `set_arg` would take by value.
+    pub fn buffer(&self) -> &Buffer<T> {
+        &self.buf
+    }
+
+    pub fn into_buffer(self) -> Buffer<T> {
+        self.buf
+    }
+
+    /// Writes `data` to the buffer, which `name` identifies in the error
+    /// returned when `data` does not hold exactly [`SizedBuffer::len`]
+    /// elements.
+    pub fn write(&mut self, queue: &CommandQueue, name: &'static str, data: &[T]) -> Result<Event> {
+        if data.len() != self.len {
+            return Err(SortError::WriteSizeMismatch {
+                buffer: name,
+                buffer_len: self.len,
+                host_len: data.len(),
+            });
+        }
+        phase_span!("upload", buffer = name);
+        Ok(queue.enqueue_write_buffer(&mut self.buf, CL_BLOCKING, 0, data, &[])?)
+    }
+
+    /// Reads the whole buffer once `wait` has completed.
+    pub fn read(&self, queue: &CommandQueue, wait: &Event) -> Result<Vec<T>>
+    where
+        T: Clone + Default,
+    {
+        phase_span!("readback");
+        let mut values = vec![T::default(); self.len];
+        queue.enqueue_read_buffer(&self.buf, CL_BLOCKING, 0, &mut values, &[wait.get()])?;
+        Ok(values)
+    }
+
+    /// Fills the whole buffer with `value` on the device.
+    pub fn fill(&mut self, queue: &CommandQueue, value: T) -> Result<Event> {
+        let size = self.len * mem::size_of::<T>();
+        Ok(queue.enqueue_fill_buffer(&mut self.buf, &[value], 0, size, &[])?)
+    }
+}
+
+/// Fills the first `n` elements of `buf` with `value` on the device.
+///
+/// The pattern is a single `cl_int`, so the fill size is always a whole
+/// number of elements. Returns the fill's event so kernels can wait on it.
+pub fn fill_i32(
+    queue: &CommandQueue,
+    buf: &mut Buffer<cl_int>,
+    value: i32,
+    n: usize,
+) -> Result<Event> {
+    Ok(queue.enqueue_fill_buffer(buf, &[value], 0, n * mem::size_of::<cl_int>(), &[])?)
+}
+
+/// Writes `data` to the whole of `buf`, which `name` identifies in the
+/// error returned when `data` does not hold exactly the buffer's element
+/// count.
+pub fn write_i32(
+    queue: &CommandQueue,
+    buf: &mut Buffer<cl_int>,
+    name: &'static str,
+    data: &[i32],
+) -> Result<Event> {
+    let buffer_len = buf.size()? / mem::size_of::<cl_int>();
+    if data.len() != buffer_len {
+        return Err(SortError::WriteSizeMismatch {
+            buffer: name,
+            buffer_len,
+            host_len: data.len(),
+        });
+    }
+    phase_span!("upload", buffer = name);
+    Ok(queue.enqueue_write_buffer(buf, CL_BLOCKING, 0, data, &[])?)
+}
+
+/// Reads `len` elements of `buf` starting at element `offset`, once `wait`
+/// has completed. Only that range is transferred from the device.
+pub fn read_range(
+    queue: &CommandQueue,
+    buf: &Buffer<cl_int>,
+    offset: usize,
+    len: usize,
+    wait: &Event,
+) -> Result<Vec<i32>> {
+    let mut values = vec![0; len];
+    read_into(queue, buf, offset, &mut values, wait)?;
+    Ok(values)
+}
+
+/// Like [`read_into`], but maps the range for reading, copies out of the
+/// mapped region and unmaps it, waiting for the unmap so the mapping never
+/// outlives the call, or the buffer. Returns the map's event.
+pub(crate) fn map_into<T: Copy>(
+    queue: &CommandQueue,
+    buf: &Buffer<T>,
+    offset: usize,
+    out: &mut [T],
+    wait: &Event,
+) -> Result<Option<Event>> {
+    if out.is_empty() {
+        return Ok(None);
+    }
+    phase_span!("readback");
+    let mut mapped: cl_mem = ptr::null_mut();
+    let map_event = queue.enqueue_map_buffer(
+        buf,
+        CL_BLOCKING,
+        CL_MAP_READ,
+        offset * mem::size_of::<T>(),
+        mem::size_of_val(out),
+        &mut mapped,
+        &[wait.get()],
+    )?;
+    // The blocking map has made `out.len()` elements readable at `mapped`.
+    unsafe { ptr::copy_nonoverlapping(mapped as *const T, out.as_mut_ptr(), out.len()) };
+    queue
+        .enqueue_unmap_mem_object(buf.get(), mapped, &[])?
+        .wait()?;
+    Ok(Some(map_event))
+}
+
+/// Like [`read_range`], but reads `out.len()` elements into `out`. Returns
+/// the read's event, or `None` if `out` is empty and nothing was read.
+pub(crate) fn read_into<T>(
+    queue: &CommandQueue,
+    buf: &Buffer<T>,
+    offset: usize,
+    out: &mut [T],
+    wait: &Event,
+) -> Result<Option<Event>> {
+    if out.is_empty() {
+        return Ok(None);
+    }
+    phase_span!("readback");
+    Ok(Some(queue.enqueue_read_buffer(
+        buf,
+        CL_BLOCKING,
+        offset * mem::size_of::<T>(),
+        out,
+        &[wait.get()],
+    )?))
+}
+
+/// Creates a read-only buffer initialised from `input` with
+/// `CL_MEM_COPY_HOST_PTR`, so allocation and upload are a single driver
+/// call. Use a separate `enqueue_write_buffer` for buffers that are reused.
+pub(crate) fn upload<T>(context: &Context, input: &[T]) -> Result<Buffer<T>> {
+    phase_span!("upload");
+    Ok(Buffer::create(
+        context,
+        CL_MEM_READ_ONLY | CL_MEM_COPY_HOST_PTR,
+        input.len(),
+        input.as_ptr() as *mut c_void,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_sorter;
+    use opencl3::memory::CL_MEM_READ_WRITE;
+
+    #[test]
+    fn read_range_returns_exactly_the_range() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let values: Vec<i32> = (0..10).collect();
+        let mut buffer = Buffer::<cl_int>::create(
+            sorter.context(),
+            CL_MEM_READ_WRITE,
+            values.len(),
+            ptr::null_mut(),
+        )
+        .unwrap();
+        let event = write_i32(sorter.queue(), &mut buffer, "values", &values).unwrap();
+        assert_eq!(
+            read_range(sorter.queue(), &buffer, 0, 4, &event).unwrap(),
+            [0, 1, 2, 3]
+        );
+        assert_eq!(
+            read_range(sorter.queue(), &buffer, 7, 3, &event).unwrap(),
+            [7, 8, 9]
+        );
+        assert!(read_range(sorter.queue(), &buffer, 5, 0, &event)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn writes_of_the_wrong_length_are_rejected() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let values: Vec<i32> = (0..10).collect();
+        let mut buffer = Buffer::<cl_int>::create(
+            sorter.context(),
+            CL_MEM_READ_WRITE,
+            values.len(),
+            ptr::null_mut(),
+        )
+        .unwrap();
+        for data in [&values[1..], &[0; 11][..]] {
+            match write_i32(sorter.queue(), &mut buffer, "values", data) {
+                Err(SortError::WriteSizeMismatch {
+                    buffer: "values",
+                    buffer_len: 10,
+                    host_len,
+                }) => assert_eq!(host_len, data.len()),
+                other => panic!("wrote {} elements into 10: {:?}", data.len(), other.err()),
+            }
+        }
+
+        let mut sized = SizedBuffer::create(sorter.context(), CL_MEM_READ_WRITE, 10).unwrap();
+        assert!(matches!(
+            sized.write(sorter.queue(), "sized", &values[..9]),
+            Err(SortError::WriteSizeMismatch {
+                buffer: "sized",
+                buffer_len: 10,
+                host_len: 9,
+            })
+        ));
+        let event = sized.write(sorter.queue(), "sized", &values).unwrap();
+        assert_eq!(sized.read(sorter.queue(), &event).unwrap(), values);
+        let event = sized.fill(sorter.queue(), 7).unwrap();
+        assert_eq!(sized.read(sorter.queue(), &event).unwrap(), [7; 10]);
+    }
+}
@@ -0,0 +1,491 @@
+use crate::error::{Result, SortError};
+use crate::kernels::KERNEL_DIR_VAR;
+use crate::multi::{device_capability, MultiSorter};
+use crate::pool::BufferPool;
+use crate::sorter::{devices_on, find_platforms, platform_at, GpuSorter};
+use opencl3::device::{Device, CL_DEVICE_TYPE_GPU};
+use opencl3::platform::Platform;
+use opencl3::types::cl_device_id;
+use std::cmp::Reverse;
+use std::env;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// How [`GpuSorter::sort`] computes each element's sorted position.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RankStrategy {
+    /// Count the elements that sort before each one, the smaller ones and
+    /// the equal ones at lower indices, with global atomics, then scatter
+    /// each element to its count. O(n²) work, but only two launches, so it
+    /// wins on small arrays.
+    #[default]
+    Atomic,
+    /// Like [`RankStrategy::Atomic`], but with one work-item per element
+    /// counting the elements before it in a loop and writing its rank once.
+    /// This avoids atomics entirely at the cost of a serial inner loop, which
+    /// tends to pay off for moderate sizes on devices with slow atomics.
+    Loop,
+    /// Like [`RankStrategy::Loop`], but each work-group first copies a tile
+    /// of the input into local memory and its work-items count against the
+    /// tile, cutting global memory reads by the work-group size. The tile is
+    /// as large as the work-group, which is the
+    /// [`GpuSorterBuilder::round_work_size`] if set and otherwise up to
+    /// 256 work-items.
+    Tiled,
+    /// Find positions through prefix sums with the radix sort, avoiding
+    /// global atomics. Its 32 passes cost more on small arrays, but it scales
+    /// far better.
+    Scan,
+    /// Pick [`RankStrategy::Atomic`] for small arrays and
+    /// [`RankStrategy::Scan`] otherwise.
+    Auto,
+}
+
+/// How the sorter reads sorted results back into host memory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReadStrategy {
+    /// A blocking `clEnqueueReadBuffer` straight into the destination.
+    #[default]
+    Copy,
+    /// Map the result range for reading, copy out of the mapped region and
+    /// unmap it again before returning. On devices that share memory with
+    /// the host, or whose drivers pin mapped memory, this can beat a read
+    /// for large results.
+    Map,
+}
+
+/// Configures a [`GpuSorter`] before its kernels are built.
+#[derive(Default)]
+pub struct GpuSorterBuilder {
+    rank_strategy: RankStrategy,
+    platform_vendor: Option<String>,
+    platform_index: Option<usize>,
+    device_index: usize,
+    cancel_token: Option<Arc<AtomicBool>>,
+    counting_bin_cap: Option<usize>,
+    pool_max_bytes: Option<usize>,
+    unchecked: bool,
+    skip_if_sorted: bool,
+    work_group_size: Option<usize>,
+    read_strategy: ReadStrategy,
+    kernel_dir: Option<PathBuf>,
+    max_devices: Option<usize>,
+}
+
+impl GpuSorterBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the strategy used by [`GpuSorter::sort`].
+    pub fn rank_strategy(mut self, rank_strategy: RankStrategy) -> Self {
+        self.rank_strategy = rank_strategy;
+        self
+    }
+
+    /// Only considers platforms whose vendor contains `vendor`, such as
+    /// `"NVIDIA"`, so the device is picked from one vendor's driver stack
+    /// however the ICD loader orders the platforms.
+    pub fn platform_vendor_contains(mut self, vendor: &str) -> Self {
+        self.platform_vendor = Some(vendor.to_string());
+        self
+    }
+
+    /// Only considers the platform at `index` in the ICD loader's
+    /// enumeration order, which is stable for a given installation, so a
+    /// script can target a platform without matching its vendor name.
+    /// Building returns [`SortError::PlatformIndex`], listing the platforms,
+    /// if there is no such platform. A vendor filter then applies to that
+    /// platform alone.
+    pub fn platform_index(mut self, index: usize) -> Self {
+        self.platform_index = Some(index);
+        self
+    }
+
+    /// Picks the GPU at `index` among those on the considered platforms,
+    /// rather than the first.
+    pub fn device_index(mut self, index: usize) -> Self {
+        self.device_index = index;
+        self
+    }
+
+    /// Lets multi-pass operations be cancelled from another thread by
+    /// setting `token`. It is checked before each pass is enqueued; once set,
+    /// the operation waits for the commands already enqueued and returns
+    /// [`SortError::Cancelled`]. A kernel that is already running can't be
+    /// interrupted, so cancellation takes effect at the next pass boundary.
+    /// The token isn't cleared by the sorter.
+    pub fn cancel_token(mut self, token: Arc<AtomicBool>) -> Self {
+        self.cancel_token = Some(token);
+        self
+    }
+
+    /// Caps the bins, one per value between the minimum and maximum, that
+    /// [`GpuSorter::sort_counting`] allocates, defaulting to
+    /// [`crate::DEFAULT_COUNTING_BIN_CAP`]. Wider ranges are refused, and
+    /// [`GpuSorter::sort_auto`] passes over the counting sort for them, so a
+    /// few widely spread values can't trigger a huge allocation.
+    pub fn counting_bin_cap(mut self, bins: usize) -> Self {
+        self.counting_bin_cap = Some(bins);
+        self
+    }
+
+    /// Caps the bytes of free scratch buffers the sorter's [`BufferPool`]
+    /// keeps between sorts, defaulting to [`crate::DEFAULT_POOL_MAX_BYTES`].
+    /// Returning a buffer beyond the cap releases the least recently
+    /// returned ones. A cap of 0 disables reuse.
+    pub fn pool_max_bytes(mut self, bytes: usize) -> Self {
+        self.pool_max_bytes = Some(bytes);
+        self
+    }
+
+    /// Whether kernel launches check that every buffer bound to them holds
+    /// enough elements for the work size, returning
+    /// [`SortError::BufferSizeMismatch`] instead of reading or writing out of
+    /// bounds on the device, and whether the atomic rank sort's result is
+    /// scanned for slots the assign kernel left unfilled, returning
+    /// [`SortError::IncompleteSort`] rather than corrupt output. Checking is
+    /// on by default.
+    ///
+    /// Each check is a driver query per buffer per launch, which is
+    /// measurable only for very small sorts. Turn it off only once the
+    /// inputs and any buffers passed in are known to be well-formed, as a
+    /// mismatch then goes undetected.
+    pub fn checked(mut self, checked: bool) -> Self {
+        self.unchecked = !checked;
+        self
+    }
+
+    /// Makes [`GpuSorter::sort`] first check with
+    /// [`GpuSorter::is_sorted_gpu`] whether its input is already sorted,
+    /// returning a copy straight away if so. The check is a pass over the
+    /// input, so this only pays off when sorted input is common, as in
+    /// pipelines that sort defensively. Constant input counts as sorted, so
+    /// it is skipped too. Off by default.
+    pub fn skip_if_sorted(mut self, skip: bool) -> Self {
+        self.skip_if_sorted = skip;
+        self
+    }
+
+    /// Launches every built-in kernel in work-groups of `local` work-items,
+    /// rounding its global work size up to a multiple of it; the padding
+    /// work-items return without doing anything. Some drivers run faster
+    /// with an aligned global size than with one of their own choosing. By
+    /// default the driver picks, as it always does for the kernels of a
+    /// [`CustomSorter`](crate::CustomSorter), whose dispatch sets its own
+    /// work size.
+    ///
+    /// The same `local` is used on every device, which holds it constant
+    /// when comparing hardware but may be slower than each device's own
+    /// choice. [`GpuSorterBuilder::build`] returns
+    /// [`SortError::InvalidInput`] if `local` is zero or exceeds the device's
+    /// maximum work-group size.
+    pub fn round_work_size(mut self, local: usize) -> Self {
+        self.work_group_size = Some(local);
+        self
+    }
+
+    /// Sets how every sort reads its result back, from [`GpuSorter::sort`]
+    /// and [`GpuSorter::sort_traced`] to the ranks, argsorts and batch
+    /// sorts, see [`ReadStrategy`]. Both give the same result.
+    pub fn read_strategy(mut self, read_strategy: ReadStrategy) -> Self {
+        self.read_strategy = read_strategy;
+        self
+    }
+
+    /// Limits [`GpuSorterBuilder::build_multi`] to the `n` most capable GPUs,
+    /// ranked by compute units times clock frequency, so a sort on a shared
+    /// machine doesn't take every device. All of them are used by default,
+    /// and a limit of 0 is taken as 1.
+    pub fn max_devices(mut self, n: usize) -> Self {
+        self.max_devices = Some(n);
+        self
+    }
+
+    /// Builds each kernel that has a source file in `dir`, such as
+    /// `compare.cl` for the compare kernel, from that file rather than the
+    /// embedded source. This takes precedence over the
+    /// [`KERNEL_DIR_VAR`](crate::kernels::KERNEL_DIR_VAR) environment
+    /// variable.
+    pub fn kernel_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.kernel_dir = Some(dir.into());
+        self
+    }
+
+    /// Creates the sorter on the selected GPU device.
+    pub fn build(self) -> Result<GpuSorter> {
+        let device_id = *self
+            .gpus()?
+            .get(self.device_index)
+            .ok_or(SortError::NoDevice)?;
+        self.configure(&Device::new(device_id))
+    }
+
+    /// Creates the sorter on `device`, which need not be a GPU, ignoring the
+    /// platform vendor and device index.
+    pub fn build_for(self, device: &Device) -> Result<GpuSorter> {
+        self.configure(device)
+    }
+
+    /// Creates a sorter on each GPU of the considered platforms, or on the
+    /// [`GpuSorterBuilder::max_devices`] most capable of them, for
+    /// [`MultiSorter`]. The device index is ignored.
+    pub fn build_multi(self) -> Result<MultiSorter> {
+        let mut ranked = self
+            .gpus()?
+            .into_iter()
+            .map(|id| {
+                let device = Device::new(id);
+                Ok((device_capability(&device)?, device))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if ranked.is_empty() {
+            return Err(SortError::NoDevice);
+        }
+        // Most capable first; the sort is stable, so equals keep the driver's
+        // order.
+        ranked.sort_by_key(|&(capability, _)| Reverse(capability));
+        ranked.truncate(self.max_devices.unwrap_or(usize::MAX).max(1));
+
+        let sorters = ranked
+            .iter()
+            .map(|(_, device)| self.configure(device))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(MultiSorter::new(sorters))
+    }
+
+    /// The GPUs on the considered platforms whose vendor matches, in driver
+    /// order.
+    fn gpus(&self) -> Result<Vec<cl_device_id>> {
+        let mut platforms = match self.platform_index {
+            Some(index) => vec![platform_at(index)?],
+            None => find_platforms()?,
+        };
+        if let Some(vendor) = &self.platform_vendor {
+            let vendors = platforms
+                .iter()
+                .map(Platform::vendor)
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            platforms = platforms
+                .into_iter()
+                .zip(&vendors)
+                .filter(|(_, name)| name.contains(vendor.as_str()))
+                .map(|(platform, _)| platform)
+                .collect();
+            if platforms.is_empty() {
+                return Err(SortError::NoPlatform {
+                    vendor: vendor.clone(),
+                    available: vendors,
+                });
+            }
+        }
+        devices_on(&platforms, CL_DEVICE_TYPE_GPU)
+    }
+
+    /// Builds a sorter on `device` with this builder's settings.
+    fn configure(&self, device: &Device) -> Result<GpuSorter> {
+        if let Some(local) = self.work_group_size {
+            let max = device.max_work_group_size()?;
+            if local == 0 || local > max {
+                return Err(SortError::InvalidInput(format!(
+                    "a work-group size of {} is outside 1..={}, the range {} supports",
+                    local,
+                    max,
+                    device.name()?
+                )));
+            }
+        }
+        let kernel_dir = self
+            .kernel_dir
+            .clone()
+            .or_else(|| env::var_os(KERNEL_DIR_VAR).map(PathBuf::from));
+        let mut sorter = GpuSorter::with_kernel_dir(device, kernel_dir)?;
+        sorter.rank_strategy = self.rank_strategy;
+        sorter.cancel_token = self.cancel_token.clone();
+        sorter.checked = !self.unchecked;
+        sorter.skip_if_sorted = self.skip_if_sorted;
+        sorter.work_group_size = self.work_group_size;
+        sorter.read_strategy = self.read_strategy;
+        if let Some(bins) = self.counting_bin_cap {
+            sorter.counting_bin_cap = bins;
+        }
+        if let Some(bytes) = self.pool_max_bytes {
+            sorter.pool = BufferPool::with_max_bytes(bytes);
+        }
+        Ok(sorter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernels::COMPARE_KERNEL;
+    use crate::stable::SortOrder;
+    use crate::test_util::{
+        assert_valid_sort, pseudo_random, temp_dir, test_sorter, test_sorter_with,
+    };
+    use std::fs;
+
+    #[test]
+    fn round_work_size_rejects_sizes_the_device_cannot_launch() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let device = Device::new(sorter.context().default_device());
+        let max = device.max_work_group_size().unwrap();
+        for local in [0, max + 1] {
+            assert!(matches!(
+                GpuSorter::builder()
+                    .round_work_size(local)
+                    .build_for(&device),
+                Err(SortError::InvalidInput(_))
+            ));
+        }
+        let sorter = GpuSorter::builder()
+            .round_work_size(max)
+            .build_for(&device)
+            .unwrap();
+        assert_eq!(sorter.work_group_size(), Some(max));
+    }
+
+    #[test]
+    fn kernel_dir_sources_replace_the_embedded_kernels() {
+        // The compare kernel with its comparison flipped ranks larger values
+        // first, so the sort comes out descending.
+        let dir = temp_dir("kernel-dir");
+        let descending = COMPARE_KERNEL.replace("source[j] < source[i]", "source[j] > source[i]");
+        assert_ne!(descending, COMPARE_KERNEL);
+        fs::write(dir.join("compare.cl"), descending).unwrap();
+        let builder = GpuSorter::builder()
+            .rank_strategy(RankStrategy::Atomic)
+            .kernel_dir(&dir);
+        if let Some(sorter) = test_sorter_with(builder) {
+            let input = pseudo_random(300, 97);
+            let output = sorter.sort(&input).unwrap();
+            assert_valid_sort(&input, &output, SortOrder::Descending);
+
+            // A file that doesn't define the kernel it is named after fails
+            // the build.
+            fs::write(
+                dir.join("compare.cl"),
+                COMPARE_KERNEL.replace("compare_kernel", "renamed_kernel"),
+            )
+            .unwrap();
+            let device = Device::new(sorter.context().default_device());
+            assert!(matches!(
+                GpuSorter::builder().kernel_dir(&dir).build_for(&device),
+                Err(SortError::Build(_))
+            ));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn round_work_size_pads_every_sort() {
+        // 7 divides none of the lengths, so every launch is padded.
+        let Some(sorter) = test_sorter_with(GpuSorter::builder().round_work_size(7)) else {
+            return;
+        };
+        assert_eq!(sorter.work_group_size(), Some(7));
+        for n in [2, 3, 100, 1000] {
+            let input = pseudo_random(n, n as u64);
+            for output in [
+                sorter.sort(&input).unwrap(),
+                sorter.sort_radix(&input, None).unwrap(),
+                sorter.sort_bitonic(&input).unwrap(),
+                sorter.sort_odd_even(&input, None).unwrap(),
+            ] {
+                assert_valid_sort(&input, &output, SortOrder::Ascending);
+            }
+            let small: Vec<i32> = input.iter().map(|x| x & 0xff).collect();
+            let counted = sorter.sort_counting(&small).unwrap();
+            assert_valid_sort(&small, &counted, SortOrder::Ascending);
+
+            assert_eq!(
+                sorter.reduce_min(&input).unwrap(),
+                input.iter().copied().min()
+            );
+            assert_eq!(
+                sorter.reduce_max(&input).unwrap(),
+                input.iter().copied().max()
+            );
+            assert_eq!(
+                sorter.reduce_sum(&input).unwrap(),
+                input.iter().fold(0i32, |sum, &x| sum.wrapping_add(x))
+            );
+            assert!(!sorter.is_sorted_gpu(&[2, 1]).unwrap());
+            assert!(sorter.is_sorted_gpu(&counted).unwrap());
+
+            let (partitioned, split) = sorter.partition(&input, 0).unwrap();
+            assert_eq!(split, input.iter().filter(|&&x| x < 0).count());
+            assert!(partitioned[..split].iter().all(|&x| x < 0));
+            assert!(partitioned[split..].iter().all(|&x| x >= 0));
+        }
+    }
+
+    #[test]
+    fn round_work_size_pads_every_other_kernel() {
+        let Some(sorter) = test_sorter_with(GpuSorter::builder().round_work_size(7)) else {
+            return;
+        };
+        for n in [2, 3, 100] {
+            let input = pseudo_random(n, n as u64);
+            let mut sorted = input.clone();
+            sorted.sort_unstable();
+            let mut unique = sorted.clone();
+            unique.dedup();
+
+            let wide: Vec<u64> = input.iter().map(|&x| x as u32 as u64).collect();
+            let mut expected = wide.clone();
+            expected.sort_unstable();
+            assert_eq!(sorter.sort_u64(&wide).unwrap(), expected);
+            let bytes: Vec<u8> = input.iter().map(|&x| x as u8).collect();
+            let mut expected = bytes.clone();
+            expected.sort_unstable();
+            assert_eq!(sorter.sort_u8(&bytes).unwrap(), expected);
+            let shorts: Vec<i16> = input.iter().map(|&x| x as i16).collect();
+            let mut expected = shorts.clone();
+            expected.sort_unstable();
+            assert_eq!(sorter.sort_i16(&shorts).unwrap(), expected);
+
+            let (keys, _) = sorter.sort_stable(&input, &input).unwrap();
+            assert_eq!(keys, sorted);
+            let indices = sorter.argsort_i32(&input).unwrap();
+            let gathered: Vec<i32> = indices.iter().map(|&i| input[i as usize]).collect();
+            assert_eq!(gathered, sorted);
+            assert_eq!(
+                sorter.argsort_batch(std::slice::from_ref(&input)).unwrap(),
+                [indices]
+            );
+
+            assert_eq!(sorter.sort_unique(&input).unwrap(), unique);
+            let rle = sorter.sort_rle(&input).unwrap();
+            assert_eq!(
+                rle.iter().map(|&(_, count)| count as usize).sum::<usize>(),
+                n
+            );
+            let (split_sorted, split) = sorter.sort_and_split(&input, 0).unwrap();
+            assert_eq!(split_sorted, sorted);
+            assert_eq!(split, sorted.iter().filter(|&&x| x < 0).count());
+            let keep: Vec<u8> = (0..n).map(|i| (i % 2) as u8).collect();
+            let kept: Vec<i32> = input.iter().skip(1).step_by(2).copied().collect();
+            assert_eq!(sorter.compact(&input, &keep).unwrap(), kept);
+
+            let (gap_sorted, gaps) = sorter.sort_and_gaps(&input).unwrap();
+            assert_eq!(gap_sorted, sorted);
+            assert_eq!(gaps.len(), n - 1);
+            assert_eq!(sorter.sort_deltas(&sorted).unwrap().len(), n - 1);
+            assert_eq!(sorter.sort_deltas_wide(&sorted).unwrap().len(), n - 1);
+
+            let runs = [
+                sorter.to_device(&sorted).unwrap(),
+                sorter.to_device(&sorted[..1]).unwrap(),
+            ];
+            let merged = sorter.to_host(&sorter.kway_merge(&runs).unwrap()).unwrap();
+            let mut expected = [&sorted[..], &sorted[..1]].concat();
+            expected.sort_unstable();
+            assert_eq!(merged, expected);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::buffer::{fill_i32, upload};
+use crate::error::{Result, SortError};
+use crate::launch::SortKernel;
+use crate::sorter::GpuSorter;
+use opencl3::memory::CL_MEM_READ_WRITE;
+use std::ops::Range;
+
+/// Default of [`GpuSorterBuilder::counting_bin_cap`], 2^24 bins (64 MiB of
+/// counts).
+///
+/// [`GpuSorterBuilder::counting_bin_cap`]: crate::GpuSorterBuilder::counting_bin_cap
+pub const DEFAULT_COUNTING_BIN_CAP: usize = 1 << 24;
+
+impl GpuSorter {
+    /// Sorts `input` with a counting sort over the range of its values: a
+    /// histogram with one bin per value between the minimum and maximum, a
+    /// scan of the bins, and one work-item per bin writing its run.
+    ///
+    /// Memory and time grow with the range rather than just the length, so
+    /// this suits inputs whose range is no larger than their length. Returns
+    /// [`SortError::InvalidInput`] if the range doesn't fit in an `i32` or
+    /// needs more bins than the sorter's counting bin cap.
+    pub fn sort_counting(&self, input: &[i32]) -> Result<Vec<i32>> {
+        self.sort_counting_window(input, 0..input.len())
+    }
+
+    /// Like [`GpuSorter::sort_counting`], but only reads `window` of the
+    /// sorted result back from the device. `window` must fit in `input`.
+    pub(crate) fn sort_counting_window(
+        &self,
+        input: &[i32],
+        window: Range<usize>,
+    ) -> Result<Vec<i32>> {
+        let n = input.len();
+        let (Some(min), Some(max)) = (self.reduce_min(input)?, self.reduce_max(input)?) else {
+            return Ok(Vec::new());
+        };
+        let bins = (max as i64 - min as i64 + 1)
+            .try_into()
+            .ok()
+            .filter(|&bins: &usize| bins <= i32::MAX as usize)
+            .ok_or_else(|| {
+                SortError::InvalidInput(format!(
+                    "value range {}..={} is too wide for a counting sort",
+                    min, max
+                ))
+            })?;
+        if bins > self.counting_bin_cap {
+            return Err(SortError::InvalidInput(format!(
+                "value range {}..={} needs {} bins, more than the cap of {}",
+                min, max, bins, self.counting_bin_cap
+            )));
+        }
+
+        let source = upload(&self.context, input)?;
+        let mut bin_buffer = self.pool.take(&self.context, bins, CL_MEM_READ_WRITE)?;
+        let scratch = [
+            self.pool.take(&self.context, bins, CL_MEM_READ_WRITE)?,
+            self.pool.take(&self.context, bins, CL_MEM_READ_WRITE)?,
+        ];
+        let result_buffer = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+
+        let bin_fill_event = fill_i32(&self.queue, &mut bin_buffer, 0, bins)?;
+
+        let histogram_kernel_event = SortKernel::new(&self.histogram_i32_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&source)?
+            .buffer_of(&bin_buffer, bins)?
+            .scalar(&min)
+            .wait(&bin_fill_event)
+            .enqueue(&self.queue)?;
+
+        let (scan, scan_event) =
+            self.inclusive_scan(&bin_buffer, &scratch, bins, &histogram_kernel_event)?;
+
+        let expand_kernel_event = SortKernel::new(&self.expand_i32_kernel, bins)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&bin_buffer)?
+            .buffer(scan)?
+            .buffer_of(&result_buffer, n)?
+            .scalar(&min)
+            .wait(&scan_event)
+            .enqueue(&self.queue)?;
+
+        let mut result = vec![0; window.len()];
+        self.read_result(
+            &result_buffer,
+            window.start,
+            &mut result,
+            &expand_kernel_event,
+        )?;
+
+        for buffer in scratch.into_iter().chain([bin_buffer]) {
+            self.pool.give(bins, CL_MEM_READ_WRITE, buffer);
+        }
+        self.pool.give(n, CL_MEM_READ_WRITE, result_buffer);
+        Ok(result)
+    }
+
+    /// Returns the `k` most frequent values of `input` with their counts,
+    /// most frequent first and ties broken by the smaller value. All distinct
+    /// values are returned if there are fewer than `k`.
+    ///
+    /// The counts come from the counting sort's histogram kernel when the
+    /// range fits within [`GpuSorter::counting_bin_cap`], and from
+    /// [`GpuSorter::sort_rle`] otherwise; the top `k` bins are then selected
+    /// on the host.
+    pub fn top_k_frequent(&self, input: &[i32], k: usize) -> Result<Vec<(i32, u32)>> {
+        let (Some(min), Some(max)) = (self.reduce_min(input)?, self.reduce_max(input)?) else {
+            return Ok(Vec::new());
+        };
+        let range = (max as i64 - min as i64 + 1) as u64;
+        let mut counts = if range <= self.counting_bin_cap.min(i32::MAX as usize) as u64 {
+            let bins = range as usize;
+            let source = upload(&self.context, input)?;
+            let mut bin_buffer = self.pool.take(&self.context, bins, CL_MEM_READ_WRITE)?;
+            let bin_fill_event = fill_i32(&self.queue, &mut bin_buffer, 0, bins)?;
+
+            let histogram_kernel_event = SortKernel::new(&self.histogram_i32_kernel, input.len())
+                .checked(self.checked)
+                .round_to(self.work_group_size)
+                .buffer(&source)?
+                .buffer_of(&bin_buffer, bins)?
+                .scalar(&min)
+                .wait(&bin_fill_event)
+                .enqueue(&self.queue)?;
+
+            let histogram =
+                self.read_result_range(&bin_buffer, 0, bins, &histogram_kernel_event)?;
+            self.pool.give(bins, CL_MEM_READ_WRITE, bin_buffer);
+            histogram
+                .into_iter()
+                .enumerate()
+                .filter(|&(_, count)| count > 0)
+                .map(|(offset, count)| ((min as i64 + offset as i64) as i32, count as u32))
+                .collect()
+        } else {
+            self.sort_rle(input)?
+        };
+
+        counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts.truncate(k);
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::SortError;
+    use crate::sorter::GpuSorter;
+    use crate::stable::SortOrder;
+    use crate::test_util::{assert_valid_sort, pseudo_random, test_sorter, test_sorter_with};
+
+    #[test]
+    fn counting_sorts_random_input() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        for n in [0, 1, 2, 1000] {
+            // Negative values too, within a range the bins can cover.
+            let input: Vec<i32> = pseudo_random(n, n as u64).iter().map(|v| v % 500).collect();
+            let output = sorter.sort_counting(&input).unwrap();
+            assert_valid_sort(&input, &output, SortOrder::Ascending);
+        }
+    }
+
+    #[test]
+    fn top_k_frequent_ranks_a_known_distribution() {
+        // Each v in 1..=10 occurs v times, and -3 as often as 10, so it
+        // comes first of the two.
+        let mut input: Vec<i32> = (1..=10).flat_map(|v| vec![v; v as usize]).collect();
+        input.extend([-3; 10]);
+        let order = pseudo_random(input.len(), 89);
+        let mut shuffled: Vec<(i32, i32)> = order.into_iter().zip(input).collect();
+        shuffled.sort_unstable();
+        let input: Vec<i32> = shuffled.into_iter().map(|(_, v)| v).collect();
+
+        // With a bin cap of 1 the counts come from the run-length encoding
+        // instead of the histogram.
+        for builder in [
+            GpuSorter::builder(),
+            GpuSorter::builder().counting_bin_cap(1),
+        ] {
+            let Some(sorter) = test_sorter_with(builder) else {
+                return;
+            };
+            assert_eq!(
+                sorter.top_k_frequent(&input, 4).unwrap(),
+                [(-3, 10), (10, 10), (9, 9), (8, 8)]
+            );
+            assert_eq!(sorter.top_k_frequent(&input, 20).unwrap().len(), 11);
+            assert!(sorter.top_k_frequent(&input, 0).unwrap().is_empty());
+            assert!(sorter.top_k_frequent(&[], 3).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn a_bin_cap_of_one_only_counts_constant_input() {
+        let Some(sorter) = test_sorter_with(GpuSorter::builder().counting_bin_cap(1)) else {
+            return;
+        };
+        assert_eq!(sorter.counting_bin_cap(), 1);
+        assert_eq!(sorter.sort_counting(&[7; 50]).unwrap(), [7; 50]);
+        assert!(sorter.sort_counting(&[]).unwrap().is_empty());
+        assert!(matches!(
+            sorter.sort_counting(&[3, 4, 3]),
+            Err(SortError::InvalidInput(_))
+        ));
+    }
+}
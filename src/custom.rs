@@ -0,0 +1,166 @@
+use crate::buffer::upload;
+use crate::error::{Result, SortError};
+use crate::kernels::BUILD_OPTIONS;
+use crate::profiling::{event_duration_ns, ProfilingReport};
+use crate::sorter::GpuSorter;
+use opencl3::error_codes::CL_INVALID_KERNEL_NAME;
+use opencl3::kernel::{ExecuteKernel, Kernel};
+use opencl3::memory::{Buffer, CL_MEM_READ_WRITE};
+use opencl3::program::Program;
+use opencl3::types::cl_int;
+use std::time::SystemTime;
+
+/// Prepares one launch of a custom kernel over `n` elements: binds its
+/// arguments and sets its work sizes. It is given the read-only input buffer
+/// and the data buffer, which holds a copy of the input when the kernel runs
+/// and is read back as the result, so both in-place and out-of-place kernels
+/// fit.
+pub type DispatchFn = Box<dyn Fn(&mut ExecuteKernel, &Buffer<cl_int>, &Buffer<cl_int>, usize)>;
+
+/// A [`GpuSorter`] that sorts with a user-supplied kernel, see
+/// [`GpuSorter::with_custom_kernel`].
+pub struct CustomSorter {
+    sorter: GpuSorter,
+    kernel: Kernel,
+    dispatch: DispatchFn,
+}
+
+impl GpuSorter {
+    /// Builds `source` on this sorter's device and returns a sorter that runs
+    /// its kernel `kernel_name` in place of the crate's own, with `dispatch`
+    /// setting up each launch. The crate still owns the context, queue,
+    /// buffers and timing, so a custom kernel can be benchmarked the same way
+    /// as the built-in ones.
+    ///
+    /// Returns [`SortError::Build`] with the build log if `source` doesn't
+    /// compile, or if it doesn't define `kernel_name`.
+    pub fn with_custom_kernel(
+        self,
+        source: &str,
+        kernel_name: &str,
+        dispatch: DispatchFn,
+    ) -> Result<CustomSorter> {
+        let program = Program::create_and_build_from_source(&self.context, source, BUILD_OPTIONS)
+            .map_err(SortError::Build)?;
+        let kernel = Kernel::create(&program, kernel_name).map_err(|e| match e.0 {
+            CL_INVALID_KERNEL_NAME => SortError::Build(format!(
+                "the custom source does not define a kernel named {}",
+                kernel_name
+            )),
+            _ => SortError::Cl(e),
+        })?;
+        Ok(CustomSorter {
+            sorter: self,
+            kernel,
+            dispatch,
+        })
+    }
+}
+
+impl CustomSorter {
+    /// The sorter whose device, context and queue the custom kernel uses.
+    pub fn sorter(&self) -> &GpuSorter {
+        &self.sorter
+    }
+
+    /// Runs the custom kernel on `input` and returns the data buffer's
+    /// contents afterwards.
+    pub fn sort(&self, input: &[i32]) -> Result<Vec<i32>> {
+        Ok(self.sort_profiled(input)?.0)
+    }
+
+    /// Like [`CustomSorter::sort`], also returning the kernel's device time,
+    /// as `compare_ns` if the driver reports it, and the host wall-clock
+    /// time of the whole sort.
+    pub fn sort_profiled(&self, input: &[i32]) -> Result<(Vec<i32>, ProfilingReport)> {
+        let n = input.len();
+        if n < 2 {
+            return Ok((input.to_vec(), ProfilingReport::default()));
+        }
+
+        let sorter = &self.sorter;
+        let start = SystemTime::now();
+        let input_buffer = upload(&sorter.context, input)?;
+        let mut data_buffer = sorter
+            .pool
+            .take_sized(&sorter.context, n, CL_MEM_READ_WRITE)?;
+        let data_write_event = data_buffer.write(&sorter.queue, "data", input)?;
+
+        let mut launch = ExecuteKernel::new(&self.kernel);
+        (self.dispatch)(&mut launch, &input_buffer, data_buffer.buffer(), n);
+        let kernel_event = launch
+            .set_wait_event(&data_write_event)
+            .enqueue_nd_range(&sorter.queue)?;
+
+        let result =
+            sorter.read_result_range(data_buffer.buffer(), 0, data_buffer.len(), &kernel_event)?;
+        let end = SystemTime::now();
+        sorter.pool.give_sized(CL_MEM_READ_WRITE, data_buffer);
+
+        let report = ProfilingReport {
+            compare_ns: event_duration_ns(&kernel_event),
+            assign_ns: None,
+            host_wall_ns: end.duration_since(start).unwrap_or_default().as_nanos() as u64,
+        };
+        Ok((result, report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{pseudo_random, test_sorter};
+
+    /// Writes its input reversed, which checks the plumbing rather than
+    /// sorting.
+    const REVERSE_COPY_KERNEL: &str = r#"
+kernel void reverse_copy_kernel(global const int* source, global int* data, int n)
+{
+    int i = get_global_id(0);
+    data[i] = source[n - 1 - i];
+}
+"#;
+
+    fn reverse_dispatch() -> DispatchFn {
+        Box::new(|launch, input, data, n| {
+            launch
+                .set_arg(input)
+                .set_arg(data)
+                .set_arg(&(n as cl_int))
+                .set_global_work_size(n);
+        })
+    }
+
+    #[test]
+    fn custom_kernels_run_in_place_of_the_sort() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let custom = sorter
+            .with_custom_kernel(
+                REVERSE_COPY_KERNEL,
+                "reverse_copy_kernel",
+                reverse_dispatch(),
+            )
+            .unwrap();
+        let input = pseudo_random(257, 15);
+        let output = custom.sort(&input).unwrap();
+        assert!(output.iter().eq(input.iter().rev()));
+        assert_eq!(custom.sort(&[6]).unwrap(), [6]);
+    }
+
+    #[test]
+    fn custom_kernels_must_build_and_be_defined() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let missing = sorter.clone_for_thread().unwrap().with_custom_kernel(
+            REVERSE_COPY_KERNEL,
+            "sort_kernel",
+            reverse_dispatch(),
+        );
+        assert!(matches!(missing, Err(SortError::Build(_))));
+        let broken = sorter.with_custom_kernel("kernel void broken(", "broken", reverse_dispatch());
+        assert!(matches!(broken, Err(SortError::Build(_))));
+    }
+}
@@ -0,0 +1,257 @@
+use crate::buffer::{upload, SizedBuffer};
+use crate::error::Result;
+use crate::kernels::*;
+use crate::launch::SortKernel;
+use crate::reduce::ReduceOp;
+use crate::sorter::{build_kernel, GpuSorter};
+use opencl3::event::Event;
+use opencl3::kernel::Kernel;
+use opencl3::memory::{Buffer, CL_MEM_READ_WRITE, CL_MEM_WRITE_ONLY};
+use opencl3::types::{cl_int, cl_ulong};
+
+/// The sign bit of a 64-bit key, flipped so unsigned order matches signed
+/// order.
+const SIGN_BIT_64: u64 = 1 << 63;
+
+/// The first differences of `input`, `input[i + 1] - input[i]`, computed on
+/// the host in 64 bits.
+fn host_deltas(input: &[i32]) -> impl Iterator<Item = i64> + '_ {
+    input.windows(2).map(|pair| pair[1] as i64 - pair[0] as i64)
+}
+
+impl GpuSorter {
+    /// Sorts the first differences of `input`, `input[i + 1] - input[i]`,
+    /// giving `input.len() - 1` values, or none for fewer than two elements.
+    ///
+    /// The differences are taken in a kernel straight into the radix sort's
+    /// key buffer, so they never go through the host. A difference can need
+    /// 33 bits, so any outside the `i32` range saturate to `i32::MIN` or
+    /// `i32::MAX`; [`GpuSorter::sort_deltas_wide`] keeps them exact.
+    pub fn sort_deltas(&self, input: &[i32]) -> Result<Vec<i32>> {
+        let n = input.len().saturating_sub(1);
+        if n < 2 {
+            return Ok(host_deltas(input)
+                .map(|delta| delta.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+                .collect());
+        }
+
+        let (delta_kernel, _) = self.delta_kernels()?;
+        let source = upload(&self.context, input)?;
+        let keys = [
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+        ];
+        let delta_event = SortKernel::new(delta_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&source)?
+            .buffer(&keys[0])?
+            .enqueue(&self.queue)?;
+
+        let (sorted, event) = self.radix_sort_keys(keys, n, delta_event, None, None)?;
+        let mut result = vec![0; n];
+        self.read_result(&sorted, 0, &mut result, &event)?;
+        self.pool.give(n, CL_MEM_READ_WRITE, sorted);
+        Ok(result)
+    }
+
+    /// Like [`GpuSorter::sort_deltas`], but takes the differences in 64 bits
+    /// so none saturate, and sorts them with the `u64` rank sort of
+    /// [`GpuSorter::sort_u64`] on the device.
+    pub fn sort_deltas_wide(&self, input: &[i32]) -> Result<Vec<i64>> {
+        let n = input.len().saturating_sub(1);
+        if n < 2 {
+            return Ok(host_deltas(input).collect());
+        }
+
+        let (_, delta_wide_kernel) = self.delta_kernels()?;
+        let source = upload(&self.context, input)?;
+        let deltas = SizedBuffer::<cl_ulong>::create(&self.context, CL_MEM_READ_WRITE, n)?;
+        let mut rank_buffer = self.pool.take_sized(&self.context, n, CL_MEM_READ_WRITE)?;
+        let result_buffer = SizedBuffer::<cl_ulong>::create(&self.context, CL_MEM_WRITE_ONLY, n)?;
+
+        let delta_event = SortKernel::new(delta_wide_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&source)?
+            .buffer(deltas.buffer())?
+            .enqueue(&self.queue)?;
+        let rank_fill_event = rank_buffer.fill(&self.queue, 0)?;
+
+        let rank_kernel_event = SortKernel::pairs(&self.rank_u64_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(deltas.buffer())?
+            .buffer(rank_buffer.buffer())?
+            .wait(&delta_event)
+            .wait(&rank_fill_event)
+            .enqueue(&self.queue)?;
+
+        let scatter_kernel_event = SortKernel::new(&self.scatter_u64_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(deltas.buffer())?
+            .buffer(rank_buffer.buffer())?
+            .buffer(result_buffer.buffer())?
+            .wait(&rank_kernel_event)
+            .enqueue(&self.queue)?;
+
+        let result = self.read_result_range(
+            result_buffer.buffer(),
+            0,
+            result_buffer.len(),
+            &scatter_kernel_event,
+        )?;
+        self.pool.give_sized(CL_MEM_READ_WRITE, rank_buffer);
+        Ok(result
+            .into_iter()
+            .map(|key| (key ^ SIGN_BIT_64) as i64)
+            .collect())
+    }
+
+    /// Sorts `input` and also returns the `input.len() - 1` gaps between
+    /// adjacent sorted values, `sorted[i + 1] - sorted[i]`, for spacing and
+    /// clustering analysis. The gaps are taken on the device from the radix
+    /// sort's result buffer, so the sorted values go through the host once,
+    /// to be returned. A gap is never negative, but can be up to
+    /// `u32::MAX`, so any beyond `i32::MAX` saturate to it, as in
+    /// [`GpuSorter::sort_deltas`].
+    pub fn sort_and_gaps(&self, input: &[i32]) -> Result<(Vec<i32>, Vec<i32>)> {
+        let n = input.len();
+        if n < 2 {
+            return Ok((input.to_vec(), Vec::new()));
+        }
+
+        let (sorted, gaps, gap_event) = self.sorted_gaps(input)?;
+        let mut result = vec![0; n];
+        self.read_result(&sorted, 0, &mut result, &gap_event)?;
+        let gap_values = self.read_result_range(&gaps, 0, n - 1, &gap_event)?;
+        self.pool.give(n, CL_MEM_READ_WRITE, sorted);
+        self.pool.give(n - 1, CL_MEM_READ_WRITE, gaps);
+        Ok((result, gap_values))
+    }
+
+    /// The largest gap [`GpuSorter::sort_and_gaps`] would return, or `None`
+    /// for fewer than two elements. The gaps are reduced on the device with
+    /// the [`GpuSorter::reduce_max`] kernel, so only the one value is read
+    /// back.
+    pub fn max_gap(&self, input: &[i32]) -> Result<Option<i32>> {
+        let n = input.len();
+        if n < 2 {
+            return Ok(None);
+        }
+
+        let (sorted, gaps, gap_event) = self.sorted_gaps(input)?;
+        let max = if n == 2 {
+            self.read_result_range(&gaps, 0, 1, &gap_event)?[0]
+        } else {
+            self.reduce_buffer(&gaps, n - 1, ReduceOp::Max, Some(&gap_event))?
+        };
+        self.pool.give(n, CL_MEM_READ_WRITE, sorted);
+        self.pool.give(n - 1, CL_MEM_READ_WRITE, gaps);
+        Ok(Some(max))
+    }
+
+    /// Radix sorts at least two elements of `input` and enqueues the
+    /// saturating difference kernel over the result. Returns the pooled
+    /// sorted and gap buffers, `n` and `n - 1` elements long, and the event
+    /// after which both are complete.
+    fn sorted_gaps(&self, input: &[i32]) -> Result<(Buffer<cl_int>, Buffer<cl_int>, Event)> {
+        let n = input.len();
+        let (delta_kernel, _) = self.delta_kernels()?;
+        let (sorted, sort_event) = self.radix_sort_buffer(input, None)?;
+        let gaps = self.pool.take(&self.context, n - 1, CL_MEM_READ_WRITE)?;
+        let gap_event = SortKernel::new(delta_kernel, n - 1)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&sorted)?
+            .buffer(&gaps)?
+            .wait(&sort_event)
+            .enqueue(&self.queue)?;
+        Ok((sorted, gaps, gap_event))
+    }
+
+    /// The saturating and 64-bit difference kernels, built the first time
+    /// either is needed.
+    fn delta_kernels(&self) -> Result<&(Kernel, Kernel)> {
+        if let Some(kernels) = self.delta_kernels.get() {
+            return Ok(kernels);
+        }
+        let kernels = (
+            build_kernel(
+                &self.context,
+                self.kernel_dir.as_deref(),
+                DELTA_KERNEL,
+                DELTA_KERNEL_NAME,
+            )?,
+            build_kernel(
+                &self.context,
+                self.kernel_dir.as_deref(),
+                DELTA_WIDE_KERNEL,
+                DELTA_WIDE_KERNEL_NAME,
+            )?,
+        );
+        Ok(self.delta_kernels.get_or_init(|| kernels))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stable::SortOrder;
+    use crate::test_util::{assert_valid_sort, pseudo_random, test_sorter};
+
+    #[test]
+    fn gaps_are_non_negative_and_sum_to_the_range() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        // Shifted down so no gap saturates.
+        let values: Vec<i32> = pseudo_random(1000, 71).iter().map(|v| v >> 8).collect();
+        let (sorted, gaps) = sorter.sort_and_gaps(&values).unwrap();
+        assert_valid_sort(&values, &sorted, SortOrder::Ascending);
+        assert_eq!(gaps.len(), values.len() - 1);
+        assert!(gaps.iter().all(|&gap| gap >= 0));
+        let range = i64::from(sorted[sorted.len() - 1]) - i64::from(sorted[0]);
+        assert_eq!(gaps.iter().map(|&gap| i64::from(gap)).sum::<i64>(), range);
+        assert_eq!(sorter.max_gap(&values).unwrap(), gaps.iter().copied().max());
+
+        assert_eq!(sorter.sort_and_gaps(&[4]).unwrap(), (vec![4], vec![]));
+        assert_eq!(sorter.max_gap(&[4]).unwrap(), None);
+        assert_eq!(sorter.max_gap(&[9, 4]).unwrap(), Some(5));
+        // A gap past i32::MAX saturates.
+        assert_eq!(
+            sorter.sort_and_gaps(&[i32::MAX, i32::MIN]).unwrap().1,
+            [i32::MAX]
+        );
+    }
+
+    #[test]
+    fn deltas_saturate_or_widen_past_the_i32_range() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let monotonic: Vec<i32> = pseudo_random(500, 72)
+            .iter()
+            .scan(0, |sum, &value| {
+                *sum += (value & 0xff) + 1;
+                Some(*sum)
+            })
+            .collect();
+        let extremes = [0, i32::MAX, i32::MIN, i32::MAX, -1, 5];
+        for values in [&monotonic[..], &pseudo_random(500, 73), &extremes] {
+            let mut wide: Vec<i64> = values
+                .windows(2)
+                .map(|pair| i64::from(pair[1]) - i64::from(pair[0]))
+                .collect();
+            wide.sort_unstable();
+            let saturated: Vec<i32> = wide
+                .iter()
+                .map(|&delta| delta.clamp(i32::MIN.into(), i32::MAX.into()) as i32)
+                .collect();
+            assert_eq!(sorter.sort_deltas(values).unwrap(), saturated);
+            assert_eq!(sorter.sort_deltas_wide(values).unwrap(), wide);
+        }
+        assert!(sorter.sort_deltas(&[7]).unwrap().is_empty());
+        assert_eq!(sorter.sort_deltas_wide(&[7, 2]).unwrap(), [-5]);
+    }
+}
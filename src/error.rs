@@ -0,0 +1,200 @@
+use crate::probe::OpenClVersion;
+use opencl3::error_codes::ClError;
+use std::fmt;
+use std::io;
+
+/// Errors returned by the sorting library.
+#[derive(Debug)]
+pub enum SortError {
+    /// An OpenCL API call failed.
+    Cl(ClError),
+    /// Reading input or writing output failed.
+    Io(io::Error),
+    /// Input data could not be interpreted.
+    InvalidInput(String),
+    /// A token in text input is not an integer in base `radix`.
+    Parse {
+        offset: usize,
+        token: String,
+        radix: u32,
+    },
+    /// A kernel failed to build, with the build log.
+    Build(String),
+    /// No OpenCL platform is available, usually because no ICD loader or
+    /// driver is installed.
+    NoOpenCl,
+    /// No OpenCL device of the requested type was found.
+    NoDevice,
+    /// No platform's vendor contains `vendor`; `available` lists the vendors
+    /// of the platforms that were found.
+    NoPlatform {
+        vendor: String,
+        available: Vec<String>,
+    },
+    /// There is no platform at `index` in enumeration order; `available`
+    /// lists the names of the platforms that were found, in that order.
+    PlatformIndex {
+        index: usize,
+        available: Vec<String>,
+    },
+    /// A string key is longer than the packing width in strict mode.
+    KeyTooLong {
+        index: usize,
+        len: usize,
+        width: usize,
+    },
+    /// A range does not lie within a slice of length `len`.
+    InvalidRange {
+        start: usize,
+        end: usize,
+        len: usize,
+    },
+    /// A string key has a non-ASCII byte at byte `offset`, which strict
+    /// case-insensitive packing rejects.
+    NonAsciiKey { index: usize, offset: usize },
+    /// An element count is zero, negative or more than the `max` a device
+    /// buffer can hold.
+    InvalidSize { size: i64, max: usize },
+    /// A string packing width outside of `1..=8` was requested.
+    InvalidWidth(usize),
+    /// The sorter's cancellation token was set during the operation.
+    Cancelled,
+    /// A buffer bound to kernel argument `arg` holds fewer elements than the
+    /// launch's work size.
+    BufferSizeMismatch {
+        arg: usize,
+        expected: usize,
+        actual: usize,
+    },
+    /// The device lacks an OpenCL extension the operation needs.
+    MissingExtension(&'static str),
+    /// The operation needs a newer OpenCL C version than the device offers.
+    UnsupportedOpenClVersion {
+        required: OpenClVersion,
+        found: OpenClVersion,
+    },
+    /// A host slice written to the buffer named `buffer` does not hold the
+    /// buffer's element count.
+    WriteSizeMismatch {
+        buffer: &'static str,
+        buffer_len: usize,
+        host_len: usize,
+    },
+    /// The rank sort left the result slot at `index` unfilled, so its output
+    /// would have been corrupt.
+    IncompleteSort { index: usize },
+}
+
+impl fmt::Display for SortError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SortError::Cl(e) => write!(f, "OpenCL error: {}", e),
+            SortError::Io(e) => write!(f, "I/O error: {}", e),
+            SortError::InvalidInput(message) => write!(f, "invalid input: {}", message),
+            SortError::Parse {
+                offset,
+                token,
+                radix: 10,
+            } => write!(f, "invalid integer {:?} at byte offset {}", token, offset),
+            SortError::Parse {
+                offset,
+                token,
+                radix,
+            } => write!(
+                f,
+                "invalid base-{} integer {:?} at byte offset {}",
+                radix, token, offset
+            ),
+            SortError::Build(log) => write!(f, "kernel build failed: {}", log),
+            SortError::NoOpenCl => write!(
+                f,
+                "no OpenCL platform found; install an OpenCL driver (ICD) for your device"
+            ),
+            SortError::NoDevice => write!(f, "no device found in platform"),
+            SortError::NoPlatform { vendor, available } => write!(
+                f,
+                "no platform vendor contains {:?}; found {}",
+                vendor,
+                available.join(", ")
+            ),
+            SortError::PlatformIndex { index, available } => {
+                write!(f, "there is no platform {}; the platforms are:", index)?;
+                for (index, name) in available.iter().enumerate() {
+                    write!(f, "\n  {}: {}", index, name)?;
+                }
+                Ok(())
+            }
+            SortError::KeyTooLong { index, len, width } => write!(
+                f,
+                "key {} is {} bytes long, which exceeds the width of {}",
+                index, len, width
+            ),
+            SortError::InvalidRange { start, end, len } => write!(
+                f,
+                "range {}..{} is out of bounds for a slice of length {}",
+                start, end, len
+            ),
+            SortError::NonAsciiKey { index, offset } => write!(
+                f,
+                "key {} has a non-ASCII byte at offset {}, which case folding doesn't cover",
+                index, offset
+            ),
+            SortError::InvalidSize { size, max } => write!(
+                f,
+                "array size {} is out of range; it must be between 1 and {}",
+                size, max
+            ),
+            SortError::InvalidWidth(width) => {
+                write!(f, "key width must be between 1 and 8, got {}", width)
+            }
+            SortError::Cancelled => write!(f, "operation cancelled"),
+            SortError::BufferSizeMismatch {
+                arg,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "kernel argument {} is a buffer of {} elements, but the launch needs {}",
+                arg, actual, expected
+            ),
+            SortError::MissingExtension(extension) => {
+                write!(f, "the device does not support {}", extension)
+            }
+            SortError::UnsupportedOpenClVersion { required, found } => write!(
+                f,
+                "this needs OpenCL C {}, but the device supports {}",
+                required, found
+            ),
+            SortError::WriteSizeMismatch {
+                buffer,
+                buffer_len,
+                host_len,
+            } => write!(
+                f,
+                "cannot write {} elements to the {} buffer of {} elements",
+                host_len, buffer, buffer_len
+            ),
+            SortError::IncompleteSort { index } => write!(
+                f,
+                "the sort left result slot {} unfilled; the compare kernel gave two elements one rank",
+                index
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SortError {}
+
+impl From<ClError> for SortError {
+    fn from(e: ClError) -> Self {
+        SortError::Cl(e)
+    }
+}
+
+impl From<io::Error> for SortError {
+    fn from(e: io::Error) -> Self {
+        SortError::Io(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, SortError>;
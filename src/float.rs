@@ -0,0 +1,133 @@
+use crate::buffer::{upload, SizedBuffer};
+use crate::error::{Result, SortError};
+use crate::kernels::*;
+use crate::launch::SortKernel;
+use crate::probe::ElementType;
+use crate::sorter::{build_kernel, GpuSorter};
+use opencl3::device::Device;
+use opencl3::kernel::Kernel;
+use opencl3::memory::{CL_MEM_READ_WRITE, CL_MEM_WRITE_ONLY};
+use opencl3::types::{cl_double, cl_int};
+
+/// Where the float sorts put NaNs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NanPolicy {
+    /// After every number, in their original order.
+    #[default]
+    Last,
+    /// Before every number, in their original order.
+    First,
+    /// Return [`SortError::InvalidInput`] if the input holds a NaN.
+    Error,
+}
+
+impl GpuSorter {
+    /// Sorts `f64`s with the rank sort, transferring them as `double`s.
+    /// Numbers are ordered by value with `-0.0` before `0.0`, as by
+    /// `f64::total_cmp`, and NaNs are placed according to `nan`.
+    ///
+    /// The kernels are built on first use, so a device without
+    /// `cl_khr_fp64` only fails here, with
+    /// [`SortError::MissingExtension`].
+    pub fn sort_f64(&self, input: &[f64], nan: NanPolicy) -> Result<Vec<f64>> {
+        if nan == NanPolicy::Error {
+            if let Some(index) = input.iter().position(|x| x.is_nan()) {
+                return Err(SortError::InvalidInput(format!("element {} is NaN", index)));
+            }
+        }
+        let n = input.len();
+        if n < 2 {
+            return Ok(input.to_vec());
+        }
+
+        let (rank_kernel, scatter_kernel) = self.f64_kernels()?;
+        let input_buffer = upload(&self.context, input)?;
+        let mut rank_buffer = self.pool.take_sized(&self.context, n, CL_MEM_READ_WRITE)?;
+        let result_buffer = SizedBuffer::<cl_double>::create(&self.context, CL_MEM_WRITE_ONLY, n)?;
+
+        let rank_fill_event = rank_buffer.fill(&self.queue, 0)?;
+
+        let rank_kernel_event = SortKernel::pairs(rank_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&input_buffer)?
+            .buffer(rank_buffer.buffer())?
+            .scalar(&((nan == NanPolicy::First) as cl_int))
+            .wait(&rank_fill_event)
+            .enqueue(&self.queue)?;
+
+        let scatter_kernel_event = SortKernel::new(scatter_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&input_buffer)?
+            .buffer(rank_buffer.buffer())?
+            .buffer(result_buffer.buffer())?
+            .wait(&rank_kernel_event)
+            .enqueue(&self.queue)?;
+
+        let result = self.read_result_range(
+            result_buffer.buffer(),
+            0,
+            result_buffer.len(),
+            &scatter_kernel_event,
+        )?;
+
+        self.pool.give_sized(CL_MEM_READ_WRITE, rank_buffer);
+        Ok(result)
+    }
+
+    /// The `double` rank and scatter kernels, built the first time they are
+    /// needed once the device is known to support `cl_khr_fp64`.
+    fn f64_kernels(&self) -> Result<&(Kernel, Kernel)> {
+        if let Some(kernels) = self.f64_kernels.get() {
+            return Ok(kernels);
+        }
+        let device = Device::new(self.context.default_device());
+        ElementType::F64.check_extensions(&device.extensions()?)?;
+        let kernels = (
+            build_kernel(
+                &self.context,
+                self.kernel_dir.as_deref(),
+                RANK_F64_KERNEL,
+                RANK_F64_KERNEL_NAME,
+            )?,
+            build_kernel(
+                &self.context,
+                self.kernel_dir.as_deref(),
+                SCATTER_F64_KERNEL,
+                SCATTER_F64_KERNEL_NAME,
+            )?,
+        );
+        Ok(self.f64_kernels.get_or_init(|| kernels))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_sorter;
+
+    #[test]
+    fn sort_f64_needs_cl_khr_fp64() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let device = Device::new(sorter.context().default_device());
+        let fp64 = ElementType::F64
+            .check_extensions(&device.extensions().unwrap())
+            .is_ok();
+        match sorter.sort_f64(&[2.0, -0.0, f64::NAN, 0.0, 1.0], NanPolicy::Last) {
+            Ok(sorted) if fp64 => {
+                // Compared by bits, so that -0.0 must come before 0.0.
+                let bits: Vec<u64> = sorted[..4].iter().map(|x| x.to_bits()).collect();
+                let expected: Vec<u64> = [-0.0f64, 0.0, 1.0, 2.0].map(f64::to_bits).to_vec();
+                assert_eq!(bits, expected);
+                assert!(sorted[4].is_nan());
+            }
+            Err(SortError::MissingExtension("cl_khr_fp64")) if !fp64 => {}
+            other => panic!("fp64 is {}, but sort_f64 gave {:?}", fp64, other),
+        }
+        // Short inputs need no kernel, so sort without the extension.
+        assert_eq!(sorter.sort_f64(&[1.5], NanPolicy::Last).unwrap(), [1.5]);
+    }
+}
@@ -0,0 +1,65 @@
+use crate::error::Result;
+use crate::sorter::GpuSorter;
+use opencl3::memory::{Buffer, CL_MEM_COPY_HOST_PTR, CL_MEM_READ_WRITE};
+use opencl3::types::CL_BLOCKING;
+use std::ffi::c_void;
+use std::ptr;
+
+/// An array resident on the device, owning its buffer, so results can be
+/// passed between device operations without a round trip through the host.
+pub struct GpuVec<T> {
+    buffer: Buffer<T>,
+    len: usize,
+}
+
+impl<T> GpuVec<T> {
+    /// Wraps `buffer`, of which the first `len` elements are valid.
+    pub(crate) fn from_buffer(buffer: Buffer<T>, len: usize) -> Self {
+        GpuVec { buffer, len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The underlying buffer, for use with the sorter's context and queue.
+    /// It may be longer than [`GpuVec::len`].
+    pub fn buffer(&self) -> &Buffer<T> {
+        &self.buffer
+    }
+}
+
+impl GpuSorter {
+    /// Copies `data` into a new read-write device buffer.
+    pub fn to_device<T>(&self, data: &[T]) -> Result<GpuVec<T>> {
+        // Zero-sized buffers are invalid, so an empty vector gets one spare
+        // element.
+        let buffer = if data.is_empty() {
+            Buffer::create(&self.context, CL_MEM_READ_WRITE, 1, ptr::null_mut())?
+        } else {
+            Buffer::create(
+                &self.context,
+                CL_MEM_READ_WRITE | CL_MEM_COPY_HOST_PTR,
+                data.len(),
+                data.as_ptr() as *mut c_void,
+            )?
+        };
+        Ok(GpuVec::from_buffer(buffer, data.len()))
+    }
+
+    /// Reads `vec` back to the host, once all previously enqueued work has
+    /// completed.
+    pub fn to_host<T: Clone + Default>(&self, vec: &GpuVec<T>) -> Result<Vec<T>> {
+        let mut data = vec![T::default(); vec.len];
+        if !data.is_empty() {
+            self.queue
+                .enqueue_read_buffer(&vec.buffer, CL_BLOCKING, 0, &mut data, &[])?
+                .wait()?;
+        }
+        Ok(data)
+    }
+}
@@ -0,0 +1,694 @@
+use crate::error::{Result, SortError};
+use std::fs::File;
+use std::io::{BufRead, Read, Write};
+use std::mem;
+use std::path::Path;
+
+/// Integers read from a binary input file, either mapped straight from the
+/// file or copied into an owned buffer.
+pub enum BinaryInput {
+    #[cfg(feature = "memmap2")]
+    Mapped(memmap2::Mmap),
+    Owned(Vec<i32>),
+}
+
+impl BinaryInput {
+    /// The file contents as native-endian `i32`s.
+    pub fn as_slice(&self) -> &[i32] {
+        match self {
+            #[cfg(feature = "memmap2")]
+            BinaryInput::Mapped(map) => {
+                // The length and alignment were checked when the file was mapped.
+                let (_, values, _) = unsafe { map.align_to::<i32>() };
+                values
+            }
+            BinaryInput::Owned(values) => values,
+        }
+    }
+}
+
+fn check_binary_len(len: usize) -> Result<()> {
+    if !len.is_multiple_of(mem::size_of::<i32>()) {
+        return Err(SortError::InvalidInput(format!(
+            "binary input is {} bytes long, which is not a whole number of 4-byte integers",
+            len
+        )));
+    }
+    Ok(())
+}
+
+/// Opens a file of native-endian `i32`s. With the `memmap2` feature the file
+/// is memory-mapped so it can be uploaded without an intermediate copy,
+/// falling back to a buffered read if mapping fails.
+///
+/// The mapping assumes the file isn't modified while it is in use.
+pub fn open_binary(path: &Path) -> Result<BinaryInput> {
+    let mut file = File::open(path)?;
+
+    #[cfg(feature = "memmap2")]
+    if let Ok(map) = unsafe { memmap2::Mmap::map(&file) } {
+        check_binary_len(map.len())?;
+        let (prefix, _, _) = unsafe { map.align_to::<i32>() };
+        if prefix.is_empty() {
+            return Ok(BinaryInput::Mapped(map));
+        }
+    }
+
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    check_binary_len(bytes.len())?;
+    Ok(BinaryInput::Owned(
+        bytes
+            .chunks_exact(mem::size_of::<i32>())
+            .map(|chunk| i32::from_ne_bytes(chunk.try_into().unwrap()))
+            .collect(),
+    ))
+}
+
+/// Magic number at the start of a framed binary file.
+pub const FRAMED_MAGIC: [u8; 4] = *b"OCLS";
+
+/// Length of the framed header: the magic number and a `u32` count.
+const FRAMED_HEADER_LEN: usize = 8;
+
+/// Decodes framed binary data: [`FRAMED_MAGIC`], a native-endian `u32`
+/// element count, then that many native-endian `i32`s. Unlike plain binary
+/// input, truncated or padded data is detected because the payload must
+/// match the count.
+pub fn parse_framed(bytes: &[u8]) -> Result<Vec<i32>> {
+    if bytes.len() < FRAMED_HEADER_LEN || bytes[..4] != FRAMED_MAGIC {
+        return Err(SortError::InvalidInput(
+            "framed input does not start with the OCLS header".to_string(),
+        ));
+    }
+    let count = u32::from_ne_bytes(bytes[4..8].try_into().unwrap()) as usize;
+    let payload = &bytes[FRAMED_HEADER_LEN..];
+    if payload.len() != count * mem::size_of::<i32>() {
+        return Err(SortError::InvalidInput(format!(
+            "framed header declares {} integers, but the payload is {} bytes",
+            count,
+            payload.len()
+        )));
+    }
+    Ok(payload
+        .chunks_exact(mem::size_of::<i32>())
+        .map(|chunk| i32::from_ne_bytes(chunk.try_into().unwrap()))
+        .collect())
+}
+
+/// Reads a framed binary file, see [`parse_framed`].
+pub fn read_framed(path: &Path) -> Result<Vec<i32>> {
+    parse_framed(&std::fs::read(path)?)
+}
+
+/// Writes `values` in the format read by [`parse_framed`].
+pub fn write_framed(writer: &mut impl Write, values: &[i32]) -> Result<()> {
+    let count = u32::try_from(values.len()).map_err(|_| {
+        SortError::InvalidInput(format!(
+            "{} integers do not fit in a framed header",
+            values.len()
+        ))
+    })?;
+    writer.write_all(&FRAMED_MAGIC)?;
+    writer.write_all(&count.to_ne_bytes())?;
+    for value in values {
+        writer.write_all(&value.to_ne_bytes())?;
+    }
+    Ok(())
+}
+
+/// Most bytes a 32-bit LEB128 varint takes: 7 bits per byte.
+const MAX_VARINT_LEN: usize = 5;
+
+/// Decodes a stream of zig-zag LEB128 varints, as written by
+/// [`write_varint`]. Each value's bits are stored low group first, 7 to a
+/// byte, with the high bit set on every byte but the last; zig-zag encoding
+/// first maps 0, -1, 1, -2, ... to 0, 1, 2, 3, ..., so small negative values
+/// stay short too. A varint cut off by the end of the stream, or one too long
+/// for 32 bits, is an error giving its byte offset.
+pub fn parse_varint(bytes: &[u8]) -> Result<Vec<i32>> {
+    let mut values = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let mut encoded: u32 = 0;
+        let mut len = 0;
+        loop {
+            let Some(&byte) = bytes.get(offset + len) else {
+                return Err(SortError::InvalidInput(format!(
+                    "varint at byte offset {} is truncated",
+                    offset
+                )));
+            };
+            if len == MAX_VARINT_LEN - 1 && byte & 0xF0 != 0 {
+                return Err(SortError::InvalidInput(format!(
+                    "varint at byte offset {} does not fit in 32 bits",
+                    offset
+                )));
+            }
+            encoded |= u32::from(byte & 0x7F) << (7 * len);
+            len += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        values.push((encoded >> 1) as i32 ^ -((encoded & 1) as i32));
+        offset += len;
+    }
+    Ok(values)
+}
+
+/// Reads a file of zig-zag LEB128 varints, see [`parse_varint`].
+pub fn read_varint(path: &Path) -> Result<Vec<i32>> {
+    parse_varint(&std::fs::read(path)?)
+}
+
+/// Writes `values` in the format read by [`parse_varint`].
+pub fn write_varint(writer: &mut impl Write, values: &[i32]) -> Result<()> {
+    for &value in values {
+        let mut encoded = ((value << 1) ^ (value >> 31)) as u32;
+        while encoded >= 0x80 {
+            writer.write_all(&[encoded as u8 | 0x80])?;
+            encoded >>= 7;
+        }
+        writer.write_all(&[encoded as u8])?;
+    }
+    Ok(())
+}
+
+/// Splits `text` into tokens separated by any run of whitespace or commas,
+/// yielding each token with its byte offset.
+fn tokens(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|token| !token.is_empty())
+        .map(move |token| (token.as_ptr() as usize - text.as_ptr() as usize, token))
+}
+
+/// Parses `token` as an integer in base `radix`, with an optional sign. In
+/// bases 2, 8 and 16 the digits may be preceded by a `0b`, `0o` or `0x`
+/// prefix respectively, in either case.
+fn parse_integer(token: &str, radix: u32) -> Option<i32> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token.strip_prefix('+').unwrap_or(token)),
+    };
+    let prefix = match radix {
+        2 => "0b",
+        8 => "0o",
+        16 => "0x",
+        _ => "",
+    };
+    let digits = match digits.get(..prefix.len()) {
+        Some(start) if !prefix.is_empty() && start.eq_ignore_ascii_case(prefix) => {
+            &digits[prefix.len()..]
+        }
+        _ => digits,
+    };
+    if digits.starts_with(['+', '-']) {
+        return None;
+    }
+    let magnitude = i64::from_str_radix(digits, radix).ok()?;
+    i32::try_from(if negative { -magnitude } else { magnitude }).ok()
+}
+
+/// What the text readers do with a token that isn't an integer in range,
+/// such as `NaN` or a value that overflows `i32`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParseErrorPolicy {
+    /// Drop the token.
+    Skip,
+    /// Read the token as 0.
+    Zero,
+    /// Return [`SortError::Parse`] with the token's byte offset.
+    #[default]
+    Fail,
+}
+
+/// Parses `token`, found at byte `offset`, in base `radix`. An invalid token
+/// is handled as `policy` says and, unless that fails the read, counted in
+/// `invalid`; `None` means it was skipped.
+fn parse_token(
+    offset: usize,
+    token: &str,
+    radix: u32,
+    policy: ParseErrorPolicy,
+    invalid: &mut usize,
+) -> Result<Option<i32>> {
+    if let Some(value) = parse_integer(token, radix) {
+        return Ok(Some(value));
+    }
+    match policy {
+        ParseErrorPolicy::Fail => Err(SortError::Parse {
+            offset,
+            token: token.to_string(),
+            radix,
+        }),
+        ParseErrorPolicy::Skip => {
+            *invalid += 1;
+            Ok(None)
+        }
+        ParseErrorPolicy::Zero => {
+            *invalid += 1;
+            Ok(Some(0))
+        }
+    }
+}
+
+/// Parses every integer in `text`, whatever the layout: any mix of
+/// whitespace and commas separates values, so CSV, one value per line and
+/// several values per line all work.
+pub fn parse_text(text: &str) -> Result<Vec<i32>> {
+    parse_text_radix(text, 10)
+}
+
+/// Like [`parse_text`], but reads the integers in base `radix`, see
+/// [`i32::from_str_radix`]. Panics if `radix` is outside `2..=36`.
+pub fn parse_text_radix(text: &str, radix: u32) -> Result<Vec<i32>> {
+    Ok(parse_text_lenient(text, radix, ParseErrorPolicy::Fail)?.0)
+}
+
+/// Like [`parse_text_radix`], handling invalid tokens as `policy` says.
+/// Returns the values and how many tokens were skipped or read as 0.
+pub fn parse_text_lenient(
+    text: &str,
+    radix: u32,
+    policy: ParseErrorPolicy,
+) -> Result<(Vec<i32>, usize)> {
+    let mut values = Vec::new();
+    let mut invalid = 0;
+    for (offset, token) in tokens(text) {
+        values.extend(parse_token(offset, token, radix, policy, &mut invalid)?);
+    }
+    Ok((values, invalid))
+}
+
+/// Parses every number in `text`, laid out as for [`parse_text`], as an
+/// `f64`. `nan`, `inf` and `-inf` are accepted in any case, as everything
+/// `f64::from_str` reads is.
+pub fn parse_floats(text: &str) -> Result<Vec<f64>> {
+    tokens(text)
+        .map(|(offset, token)| {
+            token.parse().map_err(|_| {
+                SortError::InvalidInput(format!(
+                    "invalid number {:?} at byte offset {}",
+                    token, offset
+                ))
+            })
+        })
+        .collect()
+}
+
+/// What [`read_text_capped`] does when the input holds more values than
+/// the cap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CapPolicy {
+    /// Return [`SortError::InvalidInput`].
+    Error,
+    /// Keep the first `cap` values and stop reading.
+    Truncate,
+}
+
+/// Reads integers laid out as for [`parse_text`] from `reader` a line at a
+/// time, so a slow producer's values are parsed as they arrive rather than
+/// after the whole input has been buffered as text. Reading stops at EOF, or
+/// once `cap` values have been read if there is a cap. Returns the values
+/// and whether the input was truncated at the cap, and how many invalid
+/// tokens were skipped or read as 0 under `on_error`. The integers are read
+/// in base `radix`, as by [`parse_text_radix`].
+pub fn read_text_capped(
+    mut reader: impl BufRead,
+    cap: Option<usize>,
+    policy: CapPolicy,
+    radix: u32,
+    on_error: ParseErrorPolicy,
+) -> Result<(Vec<i32>, bool, usize)> {
+    let mut values = Vec::new();
+    let mut invalid = 0;
+    let mut line = String::new();
+    let mut line_offset = 0;
+    loop {
+        line.clear();
+        let len = reader.read_line(&mut line)?;
+        if len == 0 {
+            return Ok((values, false, invalid));
+        }
+        for (offset, token) in tokens(&line) {
+            if cap == Some(values.len()) {
+                return match policy {
+                    CapPolicy::Error => Err(SortError::InvalidInput(format!(
+                        "input holds more than the maximum of {} values",
+                        values.len()
+                    ))),
+                    CapPolicy::Truncate => Ok((values, true, invalid)),
+                };
+            }
+            let offset = line_offset + offset;
+            values.extend(parse_token(offset, token, radix, on_error, &mut invalid)?);
+        }
+        line_offset += len;
+    }
+}
+
+/// Parses field `field`, counting from 0, of every line of `text` split on
+/// `delimiter` as an integer in base `radix`, giving each line's sort key.
+/// Whitespace around the field is ignored. A line too short to have the
+/// field is [`SortError::InvalidInput`]. Panics if `radix` is outside
+/// `2..=36`.
+pub fn field_keys(text: &str, field: usize, delimiter: char, radix: u32) -> Result<Vec<i32>> {
+    text.lines()
+        .enumerate()
+        .map(|(line_number, line)| {
+            let token = line.split(delimiter).nth(field).ok_or_else(|| {
+                SortError::InvalidInput(format!(
+                    "line {} has no field {}: {:?}",
+                    line_number + 1,
+                    field,
+                    line
+                ))
+            })?;
+            let token = token.trim();
+            parse_integer(token, radix).ok_or_else(|| SortError::Parse {
+                offset: token.as_ptr() as usize - text.as_ptr() as usize,
+                token: token.to_string(),
+                radix,
+            })
+        })
+        .collect()
+}
+
+/// Reads the integers in a text file, see [`parse_text`].
+pub fn read_text(path: &Path) -> Result<Vec<i32>> {
+    read_text_radix(path, 10)
+}
+
+/// Reads the integers in a text file in base `radix`, see
+/// [`parse_text_radix`].
+pub fn read_text_radix(path: &Path, radix: u32) -> Result<Vec<i32>> {
+    parse_text_radix(&std::fs::read_to_string(path)?, radix)
+}
+
+/// Reads the integers in a text file, see [`parse_text_lenient`].
+pub fn read_text_lenient(
+    path: &Path,
+    radix: u32,
+    policy: ParseErrorPolicy,
+) -> Result<(Vec<i32>, usize)> {
+    parse_text_lenient(&std::fs::read_to_string(path)?, radix, policy)
+}
+
+/// Generates `n` integers drawn uniformly from `0..max` with `rng`, so a
+/// seeded generator gives the same input every time. A `max` of 0 gives `n`
+/// zeros, without drawing from `rng`.
+///
+/// Panics if `max` is negative.
+#[cfg(feature = "rand")]
+pub fn random_input(n: usize, max: i32, rng: &mut impl rand::Rng) -> Vec<i32> {
+    if max == 0 {
+        return vec![0; n];
+    }
+    (0..n).map(|_| rng.gen_range(0..max)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_text_reads_csv() {
+        assert_eq!(parse_text("1,-2,3\n4,5,6\n").unwrap(), [1, -2, 3, 4, 5, 6]);
+        assert_eq!(parse_text("7, 8 ,9").unwrap(), [7, 8, 9]);
+    }
+
+    #[test]
+    fn parse_text_reads_space_separated_values() {
+        assert_eq!(parse_text("10 20\t30   40").unwrap(), [10, 20, 30, 40]);
+        assert_eq!(parse_text("1\n2\n3").unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn parse_text_reads_mixed_layouts() {
+        let text = "\n 1, 2\t3\r\n\n,,4 ,\t5\n\n  6  \n";
+        assert_eq!(parse_text(text).unwrap(), [1, 2, 3, 4, 5, 6]);
+        assert!(parse_text(" \n\t,\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_text_reports_the_offset_of_the_first_bad_token() {
+        match parse_text("1, 2\n3 x4 y\n") {
+            Err(SortError::Parse { offset, token, .. }) => {
+                assert_eq!(offset, 7);
+                assert_eq!(token, "x4");
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_integer_reads_hex() {
+        assert_eq!(parse_integer("ff", 16), Some(255));
+        assert_eq!(parse_integer("FF", 16), Some(255));
+        assert_eq!(parse_integer("-7fffffff", 16), Some(-i32::MAX));
+        assert_eq!(parse_integer("-80000000", 16), Some(i32::MIN));
+        assert_eq!(parse_integer("80000000", 16), None);
+        assert_eq!(parse_integer("ff", 10), None);
+    }
+
+    #[test]
+    fn parse_integer_strips_the_prefix_of_its_base() {
+        assert_eq!(parse_integer("0xff", 16), Some(255));
+        assert_eq!(parse_integer("0XfF", 16), Some(255));
+        assert_eq!(parse_integer("-0x10", 16), Some(-16));
+        assert_eq!(parse_integer("+0x10", 16), Some(16));
+        assert_eq!(parse_integer("0b101", 2), Some(5));
+        assert_eq!(parse_integer("0o17", 8), Some(15));
+        // A bare prefix has no digits, and a prefix is only stripped in its
+        // own base.
+        assert_eq!(parse_integer("0x", 16), None);
+        assert_eq!(parse_integer("0x10", 10), None);
+        assert_eq!(parse_integer("0x10", 8), None);
+        assert_eq!(parse_integer("0b1", 16), Some(0xb1));
+        // Only one sign, before the prefix.
+        assert_eq!(parse_integer("0x-1", 16), None);
+        assert_eq!(parse_integer("--1", 10), None);
+    }
+
+    #[test]
+    fn parse_integer_rejects_digits_outside_the_base() {
+        assert_eq!(parse_integer("102", 2), None);
+        assert_eq!(parse_integer("8", 8), None);
+        assert_eq!(parse_integer("1a", 10), None);
+        assert_eq!(parse_integer("g", 16), None);
+        assert_eq!(parse_integer("", 10), None);
+        assert_eq!(parse_integer("-", 10), None);
+    }
+
+    #[test]
+    fn parse_text_radix_reports_the_token_and_base() {
+        assert_eq!(parse_text_radix("ff 0x10,-1", 16).unwrap(), [255, 16, -1]);
+        match parse_text_radix("1 12", 2) {
+            Err(error @ SortError::Parse { .. }) => assert_eq!(
+                error.to_string(),
+                "invalid base-2 integer \"12\" at byte offset 2"
+            ),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    /// A `NaN` and an overflowing token among valid integers.
+    const BAD_TOKENS: &str = "3 NaN -1\n99999999999, 2\n";
+
+    #[test]
+    fn skip_policy_drops_bad_tokens() {
+        let (values, invalid) = parse_text_lenient(BAD_TOKENS, 10, ParseErrorPolicy::Skip).unwrap();
+        assert_eq!(values, [3, -1, 2]);
+        assert_eq!(invalid, 2);
+    }
+
+    #[test]
+    fn zero_policy_reads_bad_tokens_as_zero() {
+        let (values, invalid) = parse_text_lenient(BAD_TOKENS, 10, ParseErrorPolicy::Zero).unwrap();
+        assert_eq!(values, [3, 0, -1, 0, 2]);
+        assert_eq!(invalid, 2);
+    }
+
+    #[test]
+    fn fail_policy_reports_the_first_bad_token() {
+        match parse_text_lenient(BAD_TOKENS, 10, ParseErrorPolicy::Fail) {
+            Err(SortError::Parse { offset, token, .. }) => {
+                assert_eq!(offset, 2);
+                assert_eq!(token, "NaN");
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+        assert_eq!(ParseErrorPolicy::default(), ParseErrorPolicy::Fail);
+    }
+
+    #[test]
+    fn streamed_text_follows_the_policy() {
+        let read =
+            |policy| read_text_capped(BAD_TOKENS.as_bytes(), None, CapPolicy::Error, 10, policy);
+        assert_eq!(
+            read(ParseErrorPolicy::Skip).unwrap(),
+            (vec![3, -1, 2], false, 2)
+        );
+        assert_eq!(
+            read(ParseErrorPolicy::Zero).unwrap(),
+            (vec![3, 0, -1, 0, 2], false, 2)
+        );
+        // The offset counts from the start of the stream, not the line.
+        assert!(matches!(
+            read_text_capped(
+                "1\n2 x".as_bytes(),
+                None,
+                CapPolicy::Error,
+                10,
+                ParseErrorPolicy::Fail
+            ),
+            Err(SortError::Parse { offset: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn framed_round_trip() {
+        let values = [0, -1, i32::MIN, i32::MAX, 42];
+        let mut bytes = Vec::new();
+        write_framed(&mut bytes, &values).unwrap();
+        assert_eq!(bytes.len(), 8 + 4 * values.len());
+        assert_eq!(bytes[..4], FRAMED_MAGIC);
+        assert_eq!(parse_framed(&bytes).unwrap(), values);
+
+        let mut empty = Vec::new();
+        write_framed(&mut empty, &[]).unwrap();
+        assert!(parse_framed(&empty).unwrap().is_empty());
+    }
+
+    #[test]
+    fn framed_rejects_truncated_and_padded_payloads() {
+        let mut bytes = Vec::new();
+        write_framed(&mut bytes, &[1, 2, 3]).unwrap();
+        for len in [bytes.len() - 1, bytes.len() - 4, 8] {
+            assert!(matches!(
+                parse_framed(&bytes[..len]),
+                Err(SortError::InvalidInput(_))
+            ));
+        }
+        bytes.extend_from_slice(&4i32.to_ne_bytes());
+        assert!(matches!(
+            parse_framed(&bytes),
+            Err(SortError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn framed_rejects_a_bad_header() {
+        assert!(matches!(
+            parse_framed(b"OCL"),
+            Err(SortError::InvalidInput(_))
+        ));
+        let mut bytes = Vec::new();
+        write_framed(&mut bytes, &[1]).unwrap();
+        bytes[0] = b'X';
+        assert!(matches!(
+            parse_framed(&bytes),
+            Err(SortError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        let values = [0, -1, 1, -64, 63, 64, -65, 1 << 20, i32::MIN, i32::MAX];
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, &values).unwrap();
+        assert_eq!(parse_varint(&bytes).unwrap(), values);
+        assert!(parse_varint(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn varint_uses_zig_zag_encoding() {
+        let encode = |value| {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, &[value]).unwrap();
+            bytes
+        };
+        assert_eq!(encode(0), [0x00]);
+        assert_eq!(encode(-1), [0x01]);
+        assert_eq!(encode(1), [0x02]);
+        assert_eq!(encode(-64), [0x7F]);
+        assert_eq!(encode(64), [0x80, 0x01]);
+        assert_eq!(encode(i32::MIN), [0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+    }
+
+    #[test]
+    fn varint_reports_the_offset_of_a_truncated_value() {
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, &[5, 300]).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        match parse_varint(&bytes) {
+            Err(SortError::InvalidInput(message)) => {
+                assert_eq!(message, "varint at byte offset 1 is truncated")
+            }
+            other => panic!("expected a truncation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn varint_rejects_values_over_32_bits() {
+        match parse_varint(&[0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x1F]) {
+            Err(SortError::InvalidInput(message)) => {
+                assert_eq!(message, "varint at byte offset 1 does not fit in 32 bits")
+            }
+            other => panic!("expected an overflow error, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn random_input_with_max_zero_is_all_zeros() {
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        assert_eq!(random_input(10, 0, &mut rng), vec![0; 10]);
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn random_input_is_the_same_for_the_same_seed() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+        let first = random_input(1000, 500, &mut StdRng::seed_from_u64(7));
+        let second = random_input(1000, 500, &mut StdRng::seed_from_u64(7));
+        assert_eq!(first, second);
+        assert!(first.iter().all(|&x| (0..500).contains(&x)));
+        assert_ne!(
+            first,
+            random_input(1000, 500, &mut StdRng::seed_from_u64(8))
+        );
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn random_input_continues_from_the_rng_state() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut halves = random_input(50, 100, &mut rng);
+        halves.extend(random_input(50, 100, &mut rng));
+        assert_eq!(
+            halves,
+            random_input(100, 100, &mut StdRng::seed_from_u64(3))
+        );
+    }
+
+    #[test]
+    fn field_keys_read_the_chosen_column() {
+        let csv = "kiwi,10\npear,2\nplum, 10\nfig,-4\nlime,10\n";
+        assert_eq!(field_keys(csv, 1, ',', 10).unwrap(), [10, 2, 10, -4, 10]);
+        assert_eq!(field_keys("a;ff\nb; 7\n", 1, ';', 16).unwrap(), [255, 7]);
+        assert!(matches!(
+            field_keys(csv, 2, ',', 10),
+            Err(SortError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            field_keys(csv, 0, ',', 10),
+            Err(SortError::Parse { offset: 0, .. })
+        ));
+    }
+}
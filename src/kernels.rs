@@ -0,0 +1,604 @@
+use crate::error::Result;
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+
+/// Environment variable naming a directory of kernel sources to use in place
+/// of the embedded ones, for trying out kernel changes without rebuilding.
+pub const KERNEL_DIR_VAR: &str = "OCL_SORT_KERNEL_DIR";
+
+/// Compiler options every kernel is built with.
+pub const BUILD_OPTIONS: &str = "-cl-std=CL1.2";
+
+/// The source of the kernel called `name`: the file in `dir` named after it
+/// without the `_kernel` suffix, such as `compare.cl` for `compare_kernel`,
+/// if `dir` is given and the file exists, and `embedded` otherwise.
+pub fn kernel_source<'a>(
+    dir: Option<&Path>,
+    name: &str,
+    embedded: &'a str,
+) -> Result<Cow<'a, str>> {
+    let file_name = format!("{}.cl", name.strip_suffix("_kernel").unwrap_or(name));
+    match dir.map(|dir| dir.join(file_name)) {
+        Some(path) if path.exists() => Ok(Cow::Owned(fs::read_to_string(path)?)),
+        _ => Ok(Cow::Borrowed(embedded)),
+    }
+}
+
+/// Counts into `counts[i]` the elements that sort before `source[i]`: the
+/// smaller ones, and the equal ones at lower indices, so that every element
+/// gets a distinct rank and equal elements keep their order.
+pub const COMPARE_KERNEL: &str = r#"
+kernel void compare_kernel(global const int* source, global int* counts, int n)
+{
+    int i = get_global_id(0);
+    int j = get_global_id(1);
+    if(i >= n || j >= n) return;
+    if(source[j] < source[i] || (source[j] == source[i] && j < i))
+    {
+        atomic_inc(&counts[i]);
+    }
+}
+"#;
+
+pub const COMPARE_KERNEL_NAME: &str = "compare_kernel";
+
+/// [`COMPARE_KERNEL`] that also counts how many of its `atomic_inc`s fire,
+/// for measuring contention: in `totals[0]` those for a smaller value and in
+/// `totals[1]` those for an equal value at a lower index.
+pub const COMPARE_COUNTED_KERNEL: &str = r#"
+kernel void compare_counted_kernel(global const int* source, global int* counts, global uint* totals, int n)
+{
+    int i = get_global_id(0);
+    int j = get_global_id(1);
+    if(i >= n || j >= n) return;
+    if(source[j] < source[i])
+    {
+        atomic_inc(&counts[i]);
+        atomic_inc(&totals[0]);
+    }
+    else if(source[j] == source[i] && j < i)
+    {
+        atomic_inc(&counts[i]);
+        atomic_inc(&totals[1]);
+    }
+}
+"#;
+
+pub const COMPARE_COUNTED_KERNEL_NAME: &str = "compare_counted_kernel";
+
+/// Scatters each element to its rank from [`COMPARE_KERNEL`]. The ranks are
+/// distinct, so every slot of `results` is written exactly once.
+pub const ASSIGN_KERNEL: &str = r#"
+kernel void assign_kernel(global const int* source, global const int* counts, global int* results, int n)
+{
+    int my_id = get_global_id(0);
+    if(my_id >= n) return;
+    results[counts[my_id]] = source[my_id];
+}
+"#;
+
+pub const ASSIGN_KERNEL_NAME: &str = "assign_kernel";
+
+pub const RANK_U64_KERNEL: &str = r#"
+kernel void rank_u64_kernel(global const ulong* source, global int* ranks, int n)
+{
+    int i = get_global_id(0);
+    int j = get_global_id(1);
+    if(i >= n || j >= n) return;
+    if(source[j] < source[i] || (source[j] == source[i] && j < i))
+    {
+        atomic_inc(&ranks[i]);
+    }
+}
+"#;
+
+pub const RANK_U64_KERNEL_NAME: &str = "rank_u64_kernel";
+
+pub const SCATTER_INDEX_KERNEL: &str = r#"
+kernel void scatter_index_kernel(global const int* ranks, global uint* indices, int n)
+{
+    int i = get_global_id(0);
+    if(i >= n) return;
+    indices[ranks[i]] = i;
+}
+"#;
+
+pub const SCATTER_INDEX_KERNEL_NAME: &str = "scatter_index_kernel";
+
+pub const RANK_I16_KERNEL: &str = r#"
+kernel void rank_i16_kernel(global const short* source, global int* ranks, int n)
+{
+    int i = get_global_id(0);
+    int j = get_global_id(1);
+    if(i >= n || j >= n) return;
+    if(source[j] < source[i] || (source[j] == source[i] && j < i))
+    {
+        atomic_inc(&ranks[i]);
+    }
+}
+"#;
+
+pub const RANK_I16_KERNEL_NAME: &str = "rank_i16_kernel";
+
+pub const SCATTER_I16_KERNEL: &str = r#"
+kernel void scatter_i16_kernel(global const short* source, global const int* ranks, global short* results, int n)
+{
+    int i = get_global_id(0);
+    if(i >= n) return;
+    results[ranks[i]] = source[i];
+}
+"#;
+
+pub const SCATTER_I16_KERNEL_NAME: &str = "scatter_i16_kernel";
+
+pub const HISTOGRAM_U8_KERNEL: &str = r#"
+kernel void histogram_u8_kernel(global const uchar* source, global int* bins, int n)
+{
+    int i = get_global_id(0);
+    if(i >= n) return;
+    atomic_inc(&bins[source[i]]);
+}
+"#;
+
+pub const HISTOGRAM_U8_KERNEL_NAME: &str = "histogram_u8_kernel";
+
+pub const EXPAND_U8_KERNEL: &str = r#"
+kernel void expand_u8_kernel(global const int* bins, global uchar* results, int n)
+{
+    int value = get_global_id(0);
+    if(value >= n) return;
+    int start = 0;
+    for(int v = 0; v < value; v++)
+    {
+        start += bins[v];
+    }
+    for(int k = 0; k < bins[value]; k++)
+    {
+        results[start + k] = value;
+    }
+}
+"#;
+
+pub const EXPAND_U8_KERNEL_NAME: &str = "expand_u8_kernel";
+
+pub const RADIX_FLAGS_KERNEL: &str = r#"
+kernel void radix_flags_kernel(global const int* keys, global int* flags, uint bit, int n)
+{
+    int i = get_global_id(0);
+    if(i >= n) return;
+    flags[i] = (((uint)keys[i] >> bit) & 1u) == 0u ? 1 : 0;
+}
+"#;
+
+pub const RADIX_FLAGS_KERNEL_NAME: &str = "radix_flags_kernel";
+
+pub const SCAN_STEP_KERNEL: &str = r#"
+kernel void scan_step_kernel(global const int* source, global int* results, uint offset, int n)
+{
+    uint i = get_global_id(0);
+    if(i >= n) return;
+    results[i] = i >= offset ? source[i] + source[i - offset] : source[i];
+}
+"#;
+
+pub const SCAN_STEP_KERNEL_NAME: &str = "scan_step_kernel";
+
+/// Moves each key to its place in a stable split on `flags`, XORing it with
+/// `flip` on the way, which the radix sort uses to bias and unbias the sign
+/// bit.
+pub const RADIX_SCATTER_KERNEL: &str = r#"
+kernel void radix_scatter_kernel(global const int* keys, global const int* flags, global const int* scan, global int* results, uint flip, int n)
+{
+    int i = get_global_id(0);
+    if(i >= n) return;
+    int zeros = scan[n - 1];
+    int before = scan[i] - flags[i];
+    int index = flags[i] ? before : zeros + (i - before);
+    results[index] = keys[i] ^ flip;
+}
+"#;
+
+pub const RADIX_SCATTER_KERNEL_NAME: &str = "radix_scatter_kernel";
+
+pub const REVERSE_KERNEL: &str = r#"
+kernel void reverse_kernel(global int* data, uint len, int n)
+{
+    uint i = get_global_id(0);
+    if(i >= n) return;
+    int tmp = data[i];
+    data[i] = data[len - 1 - i];
+    data[len - 1 - i] = tmp;
+}
+"#;
+
+pub const REVERSE_KERNEL_NAME: &str = "reverse_kernel";
+
+pub const UNIQUE_FLAGS_KERNEL: &str = r#"
+kernel void unique_flags_kernel(global const int* sorted, global int* flags, int n)
+{
+    int i = get_global_id(0);
+    if(i >= n) return;
+    flags[i] = i == 0 || sorted[i] != sorted[i - 1];
+}
+"#;
+
+pub const UNIQUE_FLAGS_KERNEL_NAME: &str = "unique_flags_kernel";
+
+pub const COMPACT_KERNEL: &str = r#"
+kernel void compact_kernel(global const int* source, global const int* flags, global const int* scan, global int* results, int n)
+{
+    int i = get_global_id(0);
+    if(i >= n) return;
+    if(flags[i])
+    {
+        results[scan[i] - 1] = source[i];
+    }
+}
+"#;
+
+pub const COMPACT_KERNEL_NAME: &str = "compact_kernel";
+
+pub const RANK_EQUAL_KERNEL: &str = r#"
+kernel void rank_equal_kernel(global const int* source, global int* smaller, global int* equal, int descending, int last_wins, int n)
+{
+    int i = get_global_id(0);
+    int j = get_global_id(1);
+    if(i >= n || j >= n) return;
+    if(descending ? source[j] > source[i] : source[j] < source[i])
+    {
+        atomic_inc(&smaller[i]);
+    }
+    else if(source[j] == source[i] && (last_wins ? j > i : j < i))
+    {
+        atomic_inc(&equal[i]);
+    }
+}
+"#;
+
+pub const RANK_EQUAL_KERNEL_NAME: &str = "rank_equal_kernel";
+
+pub const SCATTER_PAIRS_KERNEL: &str = r#"
+kernel void scatter_pairs_kernel(global const int* keys, global const int* values, global const int* smaller, global const int* equal, global int* key_results, global int* value_results, int n)
+{
+    int i = get_global_id(0);
+    if(i >= n) return;
+    int position = smaller[i] + equal[i];
+    key_results[position] = keys[i];
+    value_results[position] = values[i];
+}
+"#;
+
+pub const SCATTER_PAIRS_KERNEL_NAME: &str = "scatter_pairs_kernel";
+
+pub const SEGMENTED_ARGSORT_KERNEL: &str = r#"
+kernel void segmented_argsort_kernel(global const int* source, global const uint* segments, global const uint* starts, global uint* indices, int n)
+{
+    uint i = get_global_id(0);
+    if(i >= n) return;
+    uint segment = segments[i];
+    uint start = starts[segment];
+    uint end = starts[segment + 1];
+    uint rank = 0;
+    for(uint j = start; j < end; j++)
+    {
+        if(source[j] < source[i] || (source[j] == source[i] && j < i))
+        {
+            rank++;
+        }
+    }
+    indices[start + rank] = i - start;
+}
+"#;
+
+pub const SEGMENTED_ARGSORT_KERNEL_NAME: &str = "segmented_argsort_kernel";
+
+pub const REDUCE_KERNEL: &str = r#"
+kernel void reduce_kernel(global const int* source, global int* results, uint len, int op, int n)
+{
+    uint i = get_global_id(0);
+    if(i >= n) return;
+    int a = source[2 * i];
+    if(2 * i + 1 < len)
+    {
+        int b = source[2 * i + 1];
+        a = op == 0 ? min(a, b) : op == 1 ? max(a, b) : (int)((uint)a + (uint)b);
+    }
+    results[i] = a;
+}
+"#;
+
+pub const REDUCE_KERNEL_NAME: &str = "reduce_kernel";
+
+pub const KWAY_MERGE_KERNEL: &str = r#"
+kernel void kway_merge_kernel(global const int* source, global const uint* starts, uint runs, global int* results, int n)
+{
+    uint i = get_global_id(0);
+    if(i >= n) return;
+    uint run = 0;
+    while(starts[run + 1] <= i)
+    {
+        run++;
+    }
+
+    int x = source[i];
+    uint position = i - starts[run];
+    for(uint s = 0; s < runs; s++)
+    {
+        if(s == run)
+        {
+            continue;
+        }
+        uint lo = starts[s];
+        uint hi = starts[s + 1];
+        while(lo < hi)
+        {
+            uint mid = lo + (hi - lo) / 2;
+            if(s < run ? source[mid] <= x : source[mid] < x)
+            {
+                lo = mid + 1;
+            }
+            else
+            {
+                hi = mid;
+            }
+        }
+        position += lo - starts[s];
+    }
+    results[position] = x;
+}
+"#;
+
+pub const KWAY_MERGE_KERNEL_NAME: &str = "kway_merge_kernel";
+
+pub const COMPARE_1D_KERNEL: &str = r#"
+kernel void compare_1d_kernel(global const int* source, global int* counts, int n)
+{
+    int i = get_global_id(0);
+    if(i >= n) return;
+    int x = source[i];
+    int count = 0;
+    for(int j = 0; j < n; j++)
+    {
+        count += source[j] < x || (source[j] == x && j < i);
+    }
+    counts[i] = count;
+}
+"#;
+
+pub const COMPARE_1D_KERNEL_NAME: &str = "compare_1d_kernel";
+
+/// [`COMPARE_1D_KERNEL`] with the source staged through local memory one
+/// work-group-sized tile at a time, so each element is read from global
+/// memory once per work-group rather than once per work-item. The padded
+/// work-items of the last work-group still load tiles and reach every
+/// barrier; they only skip the final write.
+pub const COMPARE_TILED_KERNEL: &str = r#"
+kernel void compare_tiled_kernel(global const int* source, global int* counts, local int* tile, int n)
+{
+    int i = get_global_id(0);
+    int lid = get_local_id(0);
+    int size = get_local_size(0);
+    int x = i < n ? source[i] : 0;
+    int count = 0;
+    for(int base = 0; base < n; base += size)
+    {
+        if(base + lid < n) tile[lid] = source[base + lid];
+        barrier(CLK_LOCAL_MEM_FENCE);
+        int len = min(size, n - base);
+        for(int j = 0; j < len; j++)
+        {
+            count += tile[j] < x || (tile[j] == x && base + j < i);
+        }
+        barrier(CLK_LOCAL_MEM_FENCE);
+    }
+    if(i < n) counts[i] = count;
+}
+"#;
+
+pub const COMPARE_TILED_KERNEL_NAME: &str = "compare_tiled_kernel";
+
+pub const HISTOGRAM_I32_KERNEL: &str = r#"
+kernel void histogram_i32_kernel(global const int* source, global int* bins, int min, int n)
+{
+    int i = get_global_id(0);
+    if(i >= n) return;
+    atomic_inc(&bins[source[i] - min]);
+}
+"#;
+
+pub const HISTOGRAM_I32_KERNEL_NAME: &str = "histogram_i32_kernel";
+
+pub const EXPAND_I32_KERNEL: &str = r#"
+kernel void expand_i32_kernel(global const int* bins, global const int* scan, global int* results, int min, int n)
+{
+    int value = get_global_id(0);
+    if(value >= n) return;
+    int start = scan[value] - bins[value];
+    for(int k = 0; k < bins[value]; k++)
+    {
+        results[start + k] = value + min;
+    }
+}
+"#;
+
+pub const EXPAND_I32_KERNEL_NAME: &str = "expand_i32_kernel";
+
+pub const SCATTER_U64_KERNEL: &str = r#"
+kernel void scatter_u64_kernel(global const ulong* source, global const int* ranks, global ulong* results, int n)
+{
+    int i = get_global_id(0);
+    if(i >= n) return;
+    results[ranks[i]] = source[i];
+}
+"#;
+
+pub const SCATTER_U64_KERNEL_NAME: &str = "scatter_u64_kernel";
+
+pub const ODD_EVEN_KERNEL: &str = r#"
+kernel void odd_even_kernel(global int* data, uint len, uint phase, global int* swapped, int n)
+{
+    uint i = 2 * get_global_id(0) + phase;
+    if(i + 1 < len && data[i] > data[i + 1])
+    {
+        int x = data[i];
+        data[i] = data[i + 1];
+        data[i + 1] = x;
+        atomic_or(swapped, 1);
+    }
+}
+"#;
+
+pub const ODD_EVEN_KERNEL_NAME: &str = "odd_even_kernel";
+
+/// One stage of the bitonic sorting network on a power-of-two `data`, with
+/// one work-item per comparator: element `i` is compare-exchanged with
+/// `i + j`, ascending where bit `k` of `i` is clear and descending where it
+/// is set, as in [`bitonic_network`](crate::network::bitonic_network).
+pub const BITONIC_STEP_KERNEL: &str = r#"
+kernel void bitonic_step_kernel(global int* data, uint j, uint k, int n)
+{
+    uint t = get_global_id(0);
+    if(t >= n) return;
+    uint i = 2 * j * (t / j) + t % j;
+    int low = data[i];
+    int high = data[i + j];
+    if((low > high) == ((i & k) == 0))
+    {
+        data[i] = high;
+        data[i + j] = low;
+    }
+}
+"#;
+
+pub const BITONIC_STEP_KERNEL_NAME: &str = "bitonic_step_kernel";
+
+pub const UNSORTED_KERNEL: &str = r#"
+kernel void unsorted_kernel(global const int* data, global int* unsorted, int descending, int n)
+{
+    if(get_global_id(0) >= n) return;
+    uint i = get_global_id(0) + 1;
+    if(descending ? data[i - 1] < data[i] : data[i - 1] > data[i])
+    {
+        atomic_or(unsorted, 1);
+    }
+}
+"#;
+
+pub const UNSORTED_KERNEL_NAME: &str = "unsorted_kernel";
+
+pub const PIVOT_FLAGS_KERNEL: &str = r#"
+kernel void pivot_flags_kernel(global const int* keys, global int* flags, int pivot, int n)
+{
+    int i = get_global_id(0);
+    if(i >= n) return;
+    flags[i] = keys[i] < pivot ? 1 : 0;
+}
+"#;
+
+pub const PIVOT_FLAGS_KERNEL_NAME: &str = "pivot_flags_kernel";
+
+/// The first differences of `data`, computed in 64 bits and saturated to the
+/// `int` range.
+pub const DELTA_KERNEL: &str = r#"
+kernel void delta_kernel(global const int* data, global int* deltas, int n)
+{
+    int i = get_global_id(0);
+    if(i >= n) return;
+    long delta = (long)data[i + 1] - (long)data[i];
+    deltas[i] = (int)clamp(delta, (long)INT_MIN, (long)INT_MAX);
+}
+"#;
+
+pub const DELTA_KERNEL_NAME: &str = "delta_kernel";
+
+/// The first differences of `data` in 64 bits, with the sign bit flipped so
+/// unsigned order matches signed order.
+pub const DELTA_WIDE_KERNEL: &str = r#"
+kernel void delta_wide_kernel(global const int* data, global ulong* deltas, int n)
+{
+    int i = get_global_id(0);
+    if(i >= n) return;
+    long delta = (long)data[i + 1] - (long)data[i];
+    deltas[i] = as_ulong(delta) ^ 0x8000000000000000UL;
+}
+"#;
+
+pub const DELTA_WIDE_KERNEL_NAME: &str = "delta_wide_kernel";
+
+pub const RANK_F64_KERNEL: &str = r#"
+#pragma OPENCL EXTENSION cl_khr_fp64 : enable
+
+ulong f64_key(double x, int nan_first)
+{
+    if(isnan(x))
+    {
+        return nan_first ? 0 : ULONG_MAX;
+    }
+    ulong bits = as_ulong(x);
+    return bits >> 63 ? ~bits : bits | 0x8000000000000000UL;
+}
+
+kernel void rank_f64_kernel(global const double* source, global int* ranks, int nan_first, int n)
+{
+    int i = get_global_id(0);
+    int j = get_global_id(1);
+    if(i >= n || j >= n) return;
+    ulong x = f64_key(source[i], nan_first);
+    ulong y = f64_key(source[j], nan_first);
+    if(y < x || (y == x && j < i))
+    {
+        atomic_inc(&ranks[i]);
+    }
+}
+"#;
+
+pub const RANK_F64_KERNEL_NAME: &str = "rank_f64_kernel";
+
+pub const SCATTER_F64_KERNEL: &str = r#"
+#pragma OPENCL EXTENSION cl_khr_fp64 : enable
+
+kernel void scatter_f64_kernel(global const double* source, global const int* ranks, global double* results, int n)
+{
+    int i = get_global_id(0);
+    if(i >= n) return;
+    results[ranks[i]] = source[i];
+}
+"#;
+
+pub const SCATTER_F64_KERNEL_NAME: &str = "scatter_f64_kernel";
+
+pub const RUN_ENDS_KERNEL: &str = r#"
+kernel void run_ends_kernel(global const int* sorted, global const int* scan, global int* ends, int n)
+{
+    int i = get_global_id(0);
+    if(i >= n) return;
+    if(i == n - 1 || sorted[i] != sorted[i + 1])
+    {
+        ends[scan[i] - 1] = i + 1;
+    }
+}
+"#;
+
+pub const RUN_ENDS_KERNEL_NAME: &str = "run_ends_kernel";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn kernel_source_prefers_the_file_in_the_kernel_dir() {
+        let dir = temp_dir("kernel-source");
+        fs::write(dir.join("compare.cl"), "// edited").unwrap();
+        let source = kernel_source(Some(&dir), COMPARE_KERNEL_NAME, COMPARE_KERNEL).unwrap();
+        assert!(matches!(source, Cow::Owned(ref edited) if edited == "// edited"));
+        // Kernels without a file, and sorters without a directory, keep the
+        // embedded source.
+        let source = kernel_source(Some(&dir), ASSIGN_KERNEL_NAME, ASSIGN_KERNEL).unwrap();
+        assert!(matches!(source, Cow::Borrowed(ASSIGN_KERNEL)));
+        let source = kernel_source(None, COMPARE_KERNEL_NAME, COMPARE_KERNEL).unwrap();
+        assert!(matches!(source, Cow::Borrowed(COMPARE_KERNEL)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
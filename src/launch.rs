@@ -0,0 +1,223 @@
+use crate::error::{Result, SortError};
+use crate::profiling::phase_span;
+use opencl3::command_queue::CommandQueue;
+use opencl3::event::Event;
+use opencl3::kernel::{ExecuteKernel, Kernel};
+use opencl3::memory::{Buffer, ClMem};
+use opencl3::types::cl_int;
+use std::mem;
+
+/// A kernel launch over `n` work-items per dimension that checks every
+/// buffer bound to it holds at least `n` elements, or the length given to
+/// [`SortKernel::buffer_of`], so a mismatched buffer is an error rather than
+/// an out-of-bounds access on the device.
+///
+/// Each check queries the buffer's size from the driver; see
+/// [`SortKernel::checked`] to skip them.
+///
+/// `n` is passed to the kernel as its last argument, after those bound
+/// here, so that the padded work-items of a [`SortKernel::round_to`] launch
+/// can return early.
+pub(crate) struct SortKernel<'a> {
+    execute: ExecuteKernel<'a>,
+    n: usize,
+    pairs: bool,
+    local: Option<usize>,
+    arg: usize,
+    checked: bool,
+}
+
+impl<'a> SortKernel<'a> {
+    /// A launch of `n` work-items.
+    pub(crate) fn new(kernel: &'a Kernel, n: usize) -> Self {
+        Self {
+            execute: ExecuteKernel::new(kernel),
+            n,
+            pairs: false,
+            local: None,
+            arg: 0,
+            checked: true,
+        }
+    }
+
+    /// A launch of `n` × `n` work-items, one per pair of elements.
+    pub(crate) fn pairs(kernel: &'a Kernel, n: usize) -> Self {
+        Self {
+            pairs: true,
+            ..Self::new(kernel, n)
+        }
+    }
+
+    /// Launches in work-groups of `local` work-items along the first
+    /// dimension, padding that dimension of the global size up to a multiple
+    /// of `local`. A pair launch's work-groups are `local` × 1, so its second
+    /// dimension needs no padding. With `None` the driver picks the
+    /// work-group size and there is no padding.
+    pub(crate) fn round_to(mut self, local: Option<usize>) -> Self {
+        self.local = local;
+        self
+    }
+
+    /// Whether [`SortKernel::buffer`] checks buffer sizes, which it does by
+    /// default.
+    pub(crate) fn checked(mut self, checked: bool) -> Self {
+        self.checked = checked;
+        self
+    }
+
+    /// Binds `buffer` as the next argument, returning
+    /// [`SortError::BufferSizeMismatch`] if it is shorter than `n` and the
+    /// launch is checked.
+    pub(crate) fn buffer<T>(self, buffer: &Buffer<T>) -> Result<Self> {
+        let n = self.n;
+        self.buffer_of(buffer, n)
+    }
+
+    /// Like [`SortKernel::buffer`], but checks that `buffer` holds at least
+    /// `len` elements, for buffers that the work-items don't index one each,
+    /// such as a histogram's bins.
+    pub(crate) fn buffer_of<T>(mut self, buffer: &Buffer<T>, len: usize) -> Result<Self> {
+        if self.checked {
+            let actual = buffer.size()? / mem::size_of::<T>();
+            if actual < len {
+                return Err(SortError::BufferSizeMismatch {
+                    arg: self.arg,
+                    expected: len,
+                    actual,
+                });
+            }
+        }
+        self.execute.set_arg(buffer);
+        self.arg += 1;
+        Ok(self)
+    }
+
+    /// Binds `buffer` as the next argument without a size check, for
+    /// buffers that aren't indexed by work-item, such as a single counter.
+    pub(crate) fn arg<T>(mut self, buffer: &Buffer<T>) -> Self {
+        self.execute.set_arg(buffer);
+        self.arg += 1;
+        self
+    }
+
+    /// Binds `value` as the next argument, such as a pass's bit or offset.
+    pub(crate) fn scalar<T>(mut self, value: &T) -> Self {
+        self.execute.set_arg(value);
+        self.arg += 1;
+        self
+    }
+
+    /// Allocates local memory for `len` ints as the next argument, such as a
+    /// tile shared by a work-group.
+    pub(crate) fn local_ints(mut self, len: usize) -> Self {
+        self.execute
+            .set_arg_local_buffer(len * mem::size_of::<cl_int>());
+        self.arg += 1;
+        self
+    }
+
+    /// Makes the launch wait for `event`.
+    pub(crate) fn wait(mut self, event: &Event) -> Self {
+        self.execute.set_wait_event(event);
+        self
+    }
+
+    pub(crate) fn enqueue(mut self, queue: &CommandQueue) -> Result<Event> {
+        phase_span!(
+            "kernel",
+            kernel = %self.execute.kernel.function_name().unwrap_or_default()
+        );
+        self.execute.set_arg(&(self.n as cl_int));
+        let global = self
+            .local
+            .map_or(self.n, |local| self.n.next_multiple_of(local));
+        match (self.pairs, self.local) {
+            (false, None) => self.execute.set_global_work_size(global),
+            (false, Some(local)) => self
+                .execute
+                .set_global_work_size(global)
+                .set_local_work_size(local),
+            (true, None) => self.execute.set_global_work_sizes(&[global, self.n]),
+            (true, Some(local)) => self
+                .execute
+                .set_global_work_sizes(&[global, self.n])
+                .set_local_work_sizes(&[local, 1]),
+        };
+        Ok(self.execute.enqueue_nd_range(queue)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_sorter;
+    use opencl3::memory::CL_MEM_READ_WRITE;
+    use std::ptr;
+
+    fn buffer(context: &opencl3::context::Context, len: usize) -> Buffer<cl_int> {
+        Buffer::create(context, CL_MEM_READ_WRITE, len, ptr::null_mut()).unwrap()
+    }
+
+    #[test]
+    fn undersized_buffers_are_rejected() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let (full, short) = (buffer(&sorter.context, 4), buffer(&sorter.context, 3));
+        let result = SortKernel::new(&sorter.assign_kernel, 4)
+            .buffer(&full)
+            .and_then(|launch| launch.buffer(&short));
+        assert!(matches!(
+            result,
+            Err(SortError::BufferSizeMismatch {
+                arg: 1,
+                expected: 4,
+                actual: 3
+            })
+        ));
+
+        let result = SortKernel::pairs(&sorter.rank_u64_kernel, 4).buffer(&short);
+        assert!(matches!(
+            result,
+            Err(SortError::BufferSizeMismatch { arg: 0, .. })
+        ));
+
+        let result = SortKernel::new(&sorter.reverse_kernel, 2).buffer_of(&short, 4);
+        assert!(matches!(
+            result,
+            Err(SortError::BufferSizeMismatch {
+                arg: 0,
+                expected: 4,
+                actual: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn unchecked_launches_skip_the_size_check() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let short = buffer(&sorter.context, 3);
+        assert!(SortKernel::new(&sorter.assign_kernel, 4)
+            .checked(false)
+            .buffer(&short)
+            .is_ok());
+    }
+
+    #[test]
+    fn reverse_rejects_a_buffer_shorter_than_its_length() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let short = buffer(&sorter.context, 3);
+        assert!(matches!(
+            sorter.reverse_gpu(&short, 6),
+            Err(SortError::BufferSizeMismatch {
+                expected: 6,
+                actual: 3,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,77 @@
+//! Sorting of integer arrays on OpenCL devices.
+//!
+//! [`gpu_sort`] sorts a slice in one call, reusing a sorter on each thread.
+//! A [`GpuSorter`] holds the context, queue and compiled kernels so that
+//! each [`GpuSorter::sort`] only pays for its own transfers and launches;
+//! build one with [`GpuSorter::new`] or configure it with
+//! [`GpuSorter::builder`].
+//!
+//! With the `tracing` feature, host-side work is wrapped in info-level
+//! [`tracing`](https://docs.rs/tracing) spans so that profilers can tell
+//! the phases apart: `build` around each kernel build, `upload` around
+//! every buffer upload, `kernel` around the enqueue of every built-in
+//! kernel, and `readback` around every read of a result. A launch's span
+//! covers its enqueue only; the device time is in its event.
+
+mod auto;
+mod batch;
+mod bitonic;
+mod buffer;
+mod builder;
+mod counting;
+mod custom;
+mod delta;
+mod error;
+mod float;
+mod gpu_vec;
+pub mod input;
+pub mod kernels;
+mod launch;
+mod merge;
+mod multi;
+mod narrow;
+pub mod network;
+mod occupancy;
+mod odd_even;
+pub mod output;
+mod pool;
+mod primitives;
+mod probe;
+mod profiling;
+mod radix;
+mod reduce;
+mod scan;
+mod sorter;
+mod stable;
+mod stats;
+mod stream;
+mod strings;
+mod subset;
+#[cfg(test)]
+mod test_util;
+mod wide;
+
+pub use auto::{max_chunk_elements, SortAlgorithm};
+pub use buffer::{fill_i32, read_range, write_i32, SizedBuffer};
+pub use builder::{GpuSorterBuilder, RankStrategy, ReadStrategy};
+pub use counting::DEFAULT_COUNTING_BIN_CAP;
+pub use custom::{CustomSorter, DispatchFn};
+pub use error::{Result, SortError};
+pub use float::NanPolicy;
+pub use gpu_vec::GpuVec;
+pub use multi::MultiSorter;
+pub use occupancy::KernelOccupancy;
+pub use pool::{BufferPool, DEFAULT_POOL_MAX_BYTES};
+pub use probe::{
+    builtin_sort_kernels, device_candidates, probe_backend, supported_types, BackendKind,
+    DeviceCandidate, DeviceInfo, ElementType, OpenClVersion,
+};
+pub use profiling::{event_duration_ns, ProfilingReport};
+pub use radix::RADIX_PASSES;
+pub use sorter::{
+    find_devices, find_devices_on, gpu_sort, gpu_sort_bitonic, CompareAtomics, GpuSorter, RankTrace,
+};
+pub use stable::{SortOrder, TieBreak};
+pub use stats::OrderStats;
+pub use stream::{SortStream, DEFAULT_MAX_INFLIGHT};
+pub use strings::StringKeyOptions;
@@ -1,258 +1,1855 @@
-use opencl3::command_queue::{CommandQueue, CL_QUEUE_PROFILING_ENABLE};
-use opencl3::context::Context;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use ocl_sort::input::{
+    field_keys, open_binary, parse_floats, random_input, read_framed, read_text_capped,
+    read_text_lenient, read_varint, write_framed, write_varint, BinaryInput, CapPolicy,
+    ParseErrorPolicy,
+};
+use ocl_sort::kernels::BUILD_OPTIONS;
+use ocl_sort::network::{bitonic_network, network_dot};
+use ocl_sort::output::{format_duration, format_float};
+use ocl_sort::{
+    builtin_sort_kernels, device_candidates, find_devices, find_devices_on, max_chunk_elements,
+    supported_types, write_i32, DeviceCandidate, DeviceInfo, ElementType, GpuSorter, MultiSorter,
+    NanPolicy, ProfilingReport, RankStrategy, ReadStrategy, Result, SortAlgorithm, SortError,
+    SortOrder,
+};
 use opencl3::device::{
-    device_type_text, get_all_devices, vendor_id_text, Device, CL_DEVICE_TYPE_GPU,
+    device_type_text, vendor_id_text, Device, CL_DEVICE_TYPE_ALL, CL_DEVICE_TYPE_GPU,
 };
-use opencl3::kernel::{ExecuteKernel, Kernel};
-use opencl3::memory::{Buffer, CL_MEM_READ_ONLY, CL_MEM_WRITE_ONLY};
-use opencl3::program::Program;
-use opencl3::types::{cl_event, cl_int, CL_BLOCKING};
-use opencl3::Result;
-use rand::Rng;
-use std::io::{self};
+use opencl3::event::Event;
+use opencl3::memory::{Buffer, CL_MEM_READ_WRITE};
+use opencl3::types::{cl_int, CL_BLOCKING};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::ptr;
 use std::time::SystemTime;
 
-const COMPARE_KERNEL: &str = r#"
-#pragma OPENCL EXTENSION cl_khr_int64_base_atomics : enable
-kernel void compare_kernel(global const int* source, global int* counts)
-{
-    int i = get_global_id(0);
-    int j = get_global_id(1);
-    if(source[i]>source[j])
-    {
-        atomic_inc(&counts[i]);
+#[derive(Clone, Copy, ValueEnum)]
+enum InputFormat {
+    /// Integers separated by any mix of whitespace and commas
+    Text,
+    /// Native-endian 4-byte integers
+    Binary,
+    /// An OCLS magic number and u32 count, then native-endian 4-byte integers
+    BinaryFramed,
+    /// Zig-zag LEB128 varints, one after another
+    Varint,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    /// An OCLS magic number and u32 count, then native-endian 4-byte integers
+    BinaryFramed,
+    /// Zig-zag LEB128 varints, one after another
+    Varint,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Algorithm {
+    /// Rank each element by counting smaller ones, then scatter
+    Rank,
+    /// LSD radix sort, one bit per pass
+    Radix,
+    /// Pick counting, rank or radix sort from the input's length and range
+    Auto,
+    /// Radix sort a part of the input on each GPU, then merge on the host
+    MultiDevice,
+    /// Bitonic sorting network over the input padded to a power of two
+    Bitonic,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ReadBack {
+    /// Read the result into host memory with a blocking read
+    Copy,
+    /// Map the result buffer, copy out of it and unmap it
+    Map,
+}
+
+impl From<ReadBack> for ReadStrategy {
+    fn from(read_back: ReadBack) -> Self {
+        match read_back {
+            ReadBack::Copy => ReadStrategy::Copy,
+            ReadBack::Map => ReadStrategy::Map,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OnParseError {
+    /// Drop the token
+    Skip,
+    /// Read the token as 0
+    Zero,
+    /// Stop with an error giving the token's byte offset
+    Fail,
+}
+
+impl From<OnParseError> for ParseErrorPolicy {
+    fn from(on_parse_error: OnParseError) -> Self {
+        match on_parse_error {
+            OnParseError::Skip => ParseErrorPolicy::Skip,
+            OnParseError::Zero => ParseErrorPolicy::Zero,
+            OnParseError::Fail => ParseErrorPolicy::Fail,
+        }
     }
 }
-"#;
 
-const COMPARE_KERNEL_NAME: &str = "compare_kernel";
+#[derive(Clone, Copy, ValueEnum)]
+enum NetworkFormat {
+    /// A Graphviz DOT graph
+    Dot,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check that every OpenCL device builds the kernels and sorts correctly
+    Selftest,
+    /// Time host-to-device writes and device-to-host reads of growing buffers
+    /// without running a kernel, printing CSV
+    BenchTransfer {
+        /// Largest buffer to time, in 4-byte elements; sizes grow by 4x from
+        /// 1024
+        #[arg(long, default_value_t = 1 << 24)]
+        max_elements: usize,
+    },
+    /// Time sorts of random input of growing sizes, printing CSV. With
+    /// --baseline, exits non-zero if any size got slower than the baseline
+    #[command(args_conflicts_with_subcommands = true)]
+    Bench {
+        #[command(subcommand)]
+        command: Option<BenchCommand>,
+        /// Parallel sorting algorithm to time
+        #[arg(long, value_enum, default_value_t = Algorithm::Rank)]
+        algorithm: Algorithm,
+        /// Largest input to time; sizes grow by 4x from 1024
+        #[arg(long, default_value_t = 1 << 16)]
+        max_elements: usize,
+        /// Sorts timed per size after a warm-up sort; the fastest is kept
+        #[arg(long, default_value_t = 5, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        runs: usize,
+        /// Compare against the timings in this CSV file, as printed by an
+        /// earlier bench, or write them to it if it doesn't exist
+        #[arg(long)]
+        baseline: Option<PathBuf>,
+        /// How many percent slower than the baseline a size may get before
+        /// it counts as a regression
+        #[arg(long, default_value_t = 15.0)]
+        threshold: f64,
+        /// Write each size's generated input to this directory, as
+        /// <elements>.bin in the framed binary format
+        #[arg(long, conflicts_with = "load_corpus")]
+        save_corpus: Option<PathBuf>,
+        /// How the rank and radix sorts read their result back, to compare
+        /// the two on large results
+        #[arg(long, value_enum, default_value_t = ReadBack::Copy)]
+        read_strategy: ReadBack,
+        /// Time the inputs saved in this directory by --save-corpus instead
+        /// of generating them, so runs on any machine sort identical data
+        #[arg(long)]
+        load_corpus: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum BenchCommand {
+    /// Time the atomic, tiled and loop compare kernels against each other on
+    /// random input of growing sizes and duplicate densities, printing CSV
+    /// with the fastest of each row
+    CompareKernels {
+        /// Largest input to time; sizes grow by 4x from 1024. The kernels do
+        /// O(n²) comparisons, so this stays small
+        #[arg(long, default_value_t = 1 << 14)]
+        max_elements: usize,
+        /// Launches timed per size and kernel after a warm-up launch; the
+        /// fastest is kept
+        #[arg(long, default_value_t = 5, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        runs: usize,
+    },
+}
 
-const ASSIGN_KERNEL: &str = r#"
-#pragma OPENCL EXTENSION cl_khr_int64_base_atomics : enable
-kernel void assign_kernel(global const int* source, global const int* counts, global int* results)
-{
-    int my_id = get_global_id(0);
-    int i = 0;
-    int expected;
-    do
-    {
-        expected = -1;
-        expected = atomic_cmpxchg(&results[counts[my_id]+i],expected,source[my_id]);
-        i++;
-    }while(expected != -1 && (counts[my_id]+i < get_global_size(0)));
+/// Where [`bench`] keeps its inputs, if anywhere.
+enum Corpus {
+    Save(PathBuf),
+    Load(PathBuf),
 }
-"#;
 
-const ASSIGN_KERNEL_NAME: &str = "assign_kernel";
+#[derive(Parser)]
+#[command(
+    about = "Sorts random integers on an OpenCL device and with a CPU merge sort",
+    args_override_self = true
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Read default options from this TOML file of `key = value` lines, keyed
+    /// by long flag name (`size = 1024`, `algorithm = "radix"`,
+    /// `verbose = true`). Flags given on the command line take precedence
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Sort the integers in this file instead of generating random input, or
+    /// read them as text from stdin until EOF if the path is `-`
+    #[arg(long)]
+    input: Option<PathBuf>,
+
+    /// Sort the lines of the --input text file, rather than its integers,
+    /// by the integer in this field of each line, counting from 0. Lines
+    /// with equal keys keep their order, and the reordered lines are printed
+    #[arg(long, requires = "input")]
+    sort_by_field: Option<usize>,
+
+    /// Sort the numbers in the --input text file as floats, rather than
+    /// integers, with the f64 sort, and print them one per line. NaNs go
+    /// last and print as `nan`, infinities as `inf` and `-inf`
+    #[arg(long, requires = "input", conflicts_with = "sort_by_field")]
+    floats: bool,
+
+    /// Print the sorted --floats with this many decimal digits, rather than
+    /// the shortest that parses back to the same value
+    #[arg(long, requires = "floats")]
+    float_precision: Option<usize>,
+
+    /// Separator between the fields of a line for --sort-by-field
+    #[arg(long, default_value_t = ',')]
+    field_delimiter: char,
+
+    /// Maximum number of values to read from stdin with --input -
+    #[arg(long)]
+    max_input: Option<usize>,
+
+    /// With --max-input, warn and sort the values read so far when the cap is
+    /// hit, rather than failing
+    #[arg(long)]
+    truncate_input: bool,
+
+    /// Format of the --input file
+    #[arg(long, value_enum, default_value_t = InputFormat::Text)]
+    input_format: InputFormat,
+
+    /// Base of the integers in text input: 2, 8, 10 or 16. In bases 2, 8 and
+    /// 16 a `0b`, `0o` or `0x` prefix is accepted
+    #[arg(long, default_value_t = 10, value_parser = parse_radix)]
+    input_radix: u32,
+
+    /// What to do with a token in text input that isn't an integer in range,
+    /// such as `NaN` or an overflowing value
+    #[arg(long, value_enum, default_value_t = OnParseError::Fail)]
+    on_parse_error: OnParseError,
+
+    /// Parallel sorting algorithm to compare against merge sort
+    #[arg(long, value_enum, default_value_t = Algorithm::Rank)]
+    algorithm: Algorithm,
+
+    /// Sort the input in pieces of this many elements, one after another on
+    /// the device with --algorithm, then merge the pieces on the host,
+    /// rather than in one piece. --verbose prints the largest piece that
+    /// fits on the device. Not for --algorithm multi-device, which already
+    /// splits its input
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    chunk_size: Option<usize>,
+
+    /// Check on the device whether the input is already sorted first, and if
+    /// so skip the parallel sort. The check costs a pass over the input
+    #[arg(long)]
+    skip_if_sorted: bool,
+
+    /// Check on the device whether every element of the input is equal
+    /// first, and if so skip the parallel sort. The check costs a min and a
+    /// max reduction, cheaper than --skip-if-sorted's pass
+    #[arg(long)]
+    skip_if_constant: bool,
+
+    /// Launch the rank algorithm's kernels in work-groups of this many
+    /// work-items, padding the global work size up to a multiple of it, to
+    /// compare against the driver's own choice
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    work_group_size: Option<usize>,
+
+    /// Launch the kernels of every --algorithm, and of the sortedness and
+    /// constant checks, on each device in work-groups of exactly this many
+    /// work-items, failing if a device's maximum is smaller. This holds the
+    /// size constant when comparing devices, though it may be slower than
+    /// each device's own choice
+    #[arg(
+        long,
+        conflicts_with = "work_group_size",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    fixed_local_size: Option<usize>,
+
+    /// Use the GPUs of the OpenCL platform at this index, counting from 0 in
+    /// the order the ICD loader lists them, instead of the first GPU found
+    /// on any. An index out of range prints the platforms with their indices
+    #[arg(long, value_name = "INDEX")]
+    platform: Option<usize>,
+
+    /// With --algorithm multi-device, use only this many GPUs, the most
+    /// capable by compute units times clock frequency. All are used by
+    /// default
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    max_devices: Option<usize>,
+
+    /// Show the progress of the radix passes or bitonic stages on stderr
+    #[arg(long)]
+    progress: bool,
+
+    /// Exclusive upper bound of the generated values; prompted for if omitted.
+    /// With 0 every value is 0 and the parallel sort is skipped. Must not be
+    /// negative
+    #[arg(long, value_parser = clap::value_parser!(i32).range(0..))]
+    max_value: Option<i32>,
+
+    /// Number of elements to sort, at least 1; prompted for if omitted. With
+    /// --input it is taken from the file, and must match the file if given
+    #[arg(long)]
+    size: Option<i32>,
+
+    /// Seed for the input generator, for reproducible inputs
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Print only `size max_value compare_ns assign_ns cpu_ns`, tab-separated.
+    /// For radix and auto, compare_ns is the whole sort's wall-clock time
+    #[arg(long)]
+    timing_only: bool,
+
+    /// Print the count buffer holding each element's rank (rank algorithm only)
+    #[arg(long)]
+    dump_ranks: bool,
+
+    /// Write to this file a CSV timeline of every device command the run
+    /// enqueued, one row per command: its phase, then its queued, submit,
+    /// start and end times in nanoseconds since the first was queued (rank
+    /// algorithm only)
+    #[arg(long, value_name = "FILE")]
+    trace: Option<PathBuf>,
+
+    /// Write the parallel sorted array to this file, in --output-format
+    #[arg(long)]
+    output: Option<PathBuf>,
+
+    /// Format of the --output file
+    #[arg(long, value_enum, default_value_t = OutputFormat::BinaryFramed)]
+    output_format: OutputFormat,
+
+    /// Read back, print and write with --output only the first this many
+    /// sorted values
+    #[arg(long, conflicts_with_all = ["tail", "verify_permutation"])]
+    head: Option<usize>,
+
+    /// Read back, print and write with --output only the last this many
+    /// sorted values
+    #[arg(long, conflicts_with = "verify_permutation")]
+    tail: Option<usize>,
+
+    /// Also print each sorted value with its original index, as
+    /// `value,original_index` lines (to stderr with --timing-only)
+    #[arg(long)]
+    with_indices: bool,
+
+    /// Print the compare and assign kernels' work-group limits, the
+    /// algorithm --algorithm auto picks, the device kernel time
+    /// against host wall-clock time, the largest input the algorithm could
+    /// sort in one piece on the device and, for the rank algorithm, how many
+    /// compare atomics fired; with --timing-only print these and the device
+    /// info to stderr
+    #[arg(long)]
+    verbose: bool,
+
+    /// List every candidate GPU with its selection score (compute units x
+    /// clock), whether it is discrete or integrated and its memory, marking
+    /// the one the sort runs on, or with --algorithm multi-device each one
+    /// it uses (to stderr with --timing-only)
+    #[arg(long)]
+    explain_device: bool,
+
+    /// Also time `slice::sort_unstable` on the same input. With --timing-only
+    /// its duration is appended as a sixth column, std_ns
+    #[arg(long)]
+    compare_std: bool,
+
+    /// Also time this many more runs of the parallel sort on the same sorter,
+    /// reporting the first sort, which includes one-time kernel setup, apart
+    /// from the warmed runs' mean and minimum (to stderr with --timing-only)
+    #[arg(long)]
+    repeat: Option<usize>,
+
+    /// Check that the parallel sort's output holds exactly the input's values,
+    /// exiting with an error if any were lost, duplicated or replaced
+    #[arg(long)]
+    verify_permutation: bool,
+
+    /// Write the compiled compare and assign programs to this directory, one
+    /// `<kernel>.<device>.bin` file per device. The format is vendor-specific:
+    /// PTX on NVIDIA, device ISA or an intermediate form elsewhere
+    #[arg(long)]
+    dump_binary: Option<PathBuf>,
+
+    /// Write the settings the run's result and timing depend on to this file,
+    /// or to stdout (stderr with --timing-only) if it is `-` or omitted. The
+    /// manifest is in the --config format, so passing it back with --config
+    /// repeats the run
+    #[arg(long, num_args = 0..=1, default_missing_value = "-")]
+    manifest: Option<PathBuf>,
+
+    /// Print the bitonic sorting network for --size, which must be a power of
+    /// two, in this format and exit without sorting
+    #[arg(long, value_enum)]
+    emit_network: Option<NetworkFormat>,
+}
 
 fn main() -> Result<()> {
+    let mut args = Args::parse();
+    if let Some(path) = &args.config {
+        let argv = with_config(env::args_os().collect(), config_args(path)?);
+        args = Args::parse_from(argv);
+    }
+
+    match args.command {
+        Some(Command::Selftest) => {
+            if !selftest()? {
+                process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::BenchTransfer { max_elements }) => return bench_transfer(max_elements),
+        Some(Command::Bench {
+            command: Some(BenchCommand::CompareKernels { max_elements, runs }),
+            ..
+        }) => return bench_compare_kernels(max_elements, runs),
+        Some(Command::Bench {
+            command: None,
+            algorithm,
+            max_elements,
+            runs,
+            baseline,
+            threshold,
+            save_corpus,
+            load_corpus,
+            read_strategy,
+        }) => {
+            let corpus = save_corpus
+                .map(Corpus::Save)
+                .or(load_corpus.map(Corpus::Load));
+            if !bench(
+                algorithm,
+                max_elements,
+                runs,
+                baseline.as_deref(),
+                threshold,
+                corpus.as_ref(),
+                read_strategy.into(),
+            )? {
+                process::exit(1);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    if let Some(NetworkFormat::Dot) = args.emit_network {
+        let size = args
+            .size
+            .ok_or_else(|| SortError::InvalidInput("--emit-network needs --size".to_string()))?;
+        let size = usize::try_from(size).map_err(|_| {
+            SortError::InvalidInput(format!("size must not be negative, got {}", size))
+        })?;
+        print!("{}", network_dot(size, &bitonic_network(size)?));
+        return Ok(());
+    }
+
+    if args.explain_device {
+        let explanation = explain_device(args.algorithm, args.max_devices)?;
+        if args.timing_only {
+            eprintln!("{}", explanation);
+        } else {
+            println!("{}", explanation);
+        }
+    }
+
+    if let (Some(field), Some(path)) = (args.sort_by_field, &args.input) {
+        return sort_lines(path, field, args.field_delimiter, args.input_radix);
+    }
+
+    if let (true, Some(path)) = (args.floats, &args.input) {
+        return sort_floats(path, args.float_precision);
+    }
+
     // Find a usable device for this application
-    let device_id = *get_all_devices(CL_DEVICE_TYPE_GPU)?
-        .first()
-        .expect("no device found in platform");
+    let device_ids = match args.platform {
+        Some(index) => find_devices_on(index, CL_DEVICE_TYPE_GPU)?,
+        None => find_devices(CL_DEVICE_TYPE_GPU)?,
+    };
+    let device_id = *device_ids.first().ok_or(SortError::NoDevice)?;
     let device = Device::new(device_id);
+    let local_size = args.fixed_local_size.or(args.work_group_size);
+    if let Some(local) = args.fixed_local_size {
+        let max = device.max_work_group_size()?;
+        if local > max {
+            return Err(SortError::InvalidInput(format!(
+                "--fixed-local-size {} exceeds the device's maximum work-group size of {}",
+                local, max
+            )));
+        }
+    }
 
-    //print device info
-    println!("\tCL_DEVICE_VENDOR: {}", device.vendor()?);
-    let vendor_id = device.vendor_id()?;
-    println!(
-        "\tCL_DEVICE_VENDOR_ID: {:X}, {}",
-        vendor_id,
-        vendor_id_text(vendor_id)
-    );
-    println!("\tCL_DEVICE_NAME: {}", device.name()?);
-    println!("\tCL_DEVICE_VERSION: {}", device.version()?);
-    let device_type = device.dev_type()?;
-    println!(
-        "\tCL_DEVICE_TYPE: {:X}, {}",
-        device_type,
-        device_type_text(device_type)
-    );
-    println!("\tCL_DEVICE_PROFILE: {}", device.profile()?);
-    println!("\tCL_DEVICE_EXTENSIONS: {}", device.extensions()?);
-    println!(
-        "\tCL_DEVICE_OPENCL_C_VERSION: {:?}",
-        device.opencl_c_version()?
-    );
-    println!(
-        "\tCL_DEVICE_BUILT_IN_KERNELS: {}",
-        device.built_in_kernels()?
-    );
-    println!(
-        "\tCL_DEVICE_SVM_CAPABILITIES: {:X}",
-        device.svm_mem_capability()
-    );
-    println!();
+    if !args.timing_only {
+        println!("{}", device_info(&device)?);
+    } else if args.verbose {
+        eprintln!("{}", device_info(&device)?);
+    }
 
-    // Create a Context on an OpenCL device
-    let context = Context::from_device(&device).expect("Context::from_device failed");
-
-    // Create a command_queue on the Context's device
-    let queue = CommandQueue::create(
-        &context,
-        context.default_device(),
-        CL_QUEUE_PROFILING_ENABLE,
-    )
-    .expect("CommandQueue::create failed");
-
-    //Build Kernels
-    let compare_program =
-        Program::create_and_build_from_source(&context, COMPARE_KERNEL, "-cl-std=CL1.2")
-            .expect("Compare Kernel Failed to Build");
-    let compare_kernel = Kernel::create(&compare_program, COMPARE_KERNEL_NAME)
-        .expect("Compare Kernel Create Failed");
-
-    let assign_program =
-        Program::create_and_build_from_source(&context, ASSIGN_KERNEL, "-cl-std=CL1.2")
-            .expect("Assign Kernel Failed to Build");
-    let assign_kernel =
-        Kernel::create(&assign_program, ASSIGN_KERNEL_NAME).expect("Assign Kernel Create Failed");
-
-    println!("Choose an max value:");
-    let mut max_sort_value = String::new();
-    io::stdin()
-        .read_line(&mut max_sort_value)
-        .expect("Failed to read line");
-    let max_sort_value: i32 = match max_sort_value.trim().parse() {
-        Ok(num) => num,
-        Err(_) => 0,
+    // One sorter serves every algorithm. --work-group-size only applies to
+    // the rank sort, so it gets a sorter of its own when that is given.
+    let sorter = run_sorter(&device, args.fixed_local_size)?;
+    let work_group_sorter;
+    let rank_sorter = match args.work_group_size {
+        Some(_) => {
+            work_group_sorter = run_sorter(&device, local_size)?;
+            &work_group_sorter
+        }
+        None => &sorter,
     };
 
-    println!("Choose a array size:");
-    let mut max_array_size = String::new();
-    io::stdin()
-        .read_line(&mut max_array_size)
-        .expect("Failed to read line");
-    let max_array_size: i32 = match max_array_size.trim().parse() {
-        Ok(num) => num,
-        Err(_) => 0,
-    };
+    if args.verbose {
+        let (compare, assign) = rank_sorter.rank_occupancy()?;
+        let occupancy = format!("Compare kernel: {}\nAssign kernel: {}\n", compare, assign);
+        if args.timing_only {
+            eprintln!("{}", occupancy);
+        } else {
+            println!("{}", occupancy);
+        }
+    }
 
-    let max_array_size = max_array_size.try_into().unwrap();
+    if let Some(dir) = &args.dump_binary {
+        fs::create_dir_all(dir)?;
+        for (name, binaries) in rank_sorter.rank_binaries()? {
+            for (index, binary) in binaries.iter().enumerate() {
+                fs::write(dir.join(format!("{}.{}.bin", name, index)), binary)?;
+            }
+        }
+    }
 
     //Input Data
-    let mut input = vec![0; max_array_size];
-    for i in 0..max_array_size {
-        input[i] = rand::thread_rng().gen_range(0..max_sort_value);
-    }
-
-    let mut input_buffer =
-        Buffer::<cl_int>::create(&context, CL_MEM_READ_ONLY, max_array_size, ptr::null_mut())
-            .expect("Failed to make Input Buffer");
-    let mut count_buffer = Buffer::<cl_int>::create(&context, 0, max_array_size, ptr::null_mut())
-        .expect("Failed to make Count Buffer");
-    let mut result_buffer =
-        Buffer::<cl_int>::create(&context, CL_MEM_WRITE_ONLY, max_array_size, ptr::null_mut())
-            .expect("Failed to make Result Buffer");
-
-    println!("Line: {}", line!());
-
-    let input_write_event = queue
-        .enqueue_write_buffer(&mut input_buffer, CL_BLOCKING, 0, &input, &[])
-        .expect("Failed to queue Input Buffer");
-    let count_write_event = queue
-        .enqueue_write_buffer(
-            &mut count_buffer,
-            CL_BLOCKING,
-            0,
-            &vec![0; max_array_size],
-            &[],
-        )
-        .expect("Failed to queue Count Buffer");
-    let result_write_event = queue
-        .enqueue_write_buffer(
-            &mut result_buffer,
-            CL_BLOCKING,
-            0,
-            &vec![-1; max_array_size],
-            &[],
-        )
-        .expect("Failed to queue Write Buffer");
+    // Drawn here rather than seeding from entropy so the manifest can record it.
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let binary_input: BinaryInput;
+    let owned_input: Vec<i32>;
+    let (input, max_sort_value): (&[i32], i32) = match &args.input {
+        Some(path) => {
+            let input: &[i32] = match args.input_format {
+                _ if path.as_os_str() == "-" => {
+                    let policy = if args.truncate_input {
+                        CapPolicy::Truncate
+                    } else {
+                        CapPolicy::Error
+                    };
+                    let (values, truncated, invalid) = read_text_capped(
+                        io::stdin().lock(),
+                        args.max_input,
+                        policy,
+                        args.input_radix,
+                        args.on_parse_error.into(),
+                    )?;
+                    if truncated {
+                        eprintln!(
+                            "Warning: stopped reading stdin after {} values",
+                            values.len()
+                        );
+                    }
+                    warn_invalid_tokens(invalid, args.on_parse_error);
+                    owned_input = values;
+                    &owned_input
+                }
+                InputFormat::Text => {
+                    let (values, invalid) =
+                        read_text_lenient(path, args.input_radix, args.on_parse_error.into())?;
+                    warn_invalid_tokens(invalid, args.on_parse_error);
+                    owned_input = values;
+                    &owned_input
+                }
+                InputFormat::Binary => {
+                    binary_input = open_binary(path)?;
+                    binary_input.as_slice()
+                }
+                InputFormat::BinaryFramed => {
+                    owned_input = read_framed(path)?;
+                    &owned_input
+                }
+                InputFormat::Varint => {
+                    owned_input = read_varint(path)?;
+                    &owned_input
+                }
+            };
+            if let Some(size) = args.size {
+                if usize::try_from(size).ok() != Some(input.len()) {
+                    return Err(SortError::InvalidInput(format!(
+                        "--size {} does not match the {} values in {}",
+                        size,
+                        input.len(),
+                        path.display()
+                    )));
+                }
+            }
+            let max_sort_value = input.iter().max().map_or(0, |max| max.saturating_add(1));
+            (input, max_sort_value)
+        }
+        None => {
+            let max_sort_value = match args.max_value {
+                Some(max_value) => max_value,
+                None => prompt_value(
+                    "Choose an max value:",
+                    args.timing_only,
+                    &mut io::stdin().lock(),
+                )?,
+            };
+            let max_array_size = match args.size {
+                Some(size) => size,
+                None => prompt_value(
+                    "Choose a array size:",
+                    args.timing_only,
+                    &mut io::stdin().lock(),
+                )?,
+            };
+            let max_array_size = checked_size(max_array_size, &device)?;
+
+            let mut rng = StdRng::seed_from_u64(seed);
+            owned_input = random_input(max_array_size, max_sort_value, &mut rng);
+            (&owned_input, max_sort_value)
+        }
+    };
+    let max_array_size = input.len();
+    let window = output_window(args.head, args.tail, max_array_size);
+
+    let already_sorted = args.skip_if_sorted && sorter.is_sorted_gpu(input)?;
+    if already_sorted && args.verbose {
+        if args.timing_only {
+            eprintln!("Input is already sorted, skipped the parallel sort");
+        } else {
+            println!("Input is already sorted, skipped the parallel sort");
+        }
+    }
 
-    let compare_kernel_event = ExecuteKernel::new(&compare_kernel)
-        .set_arg(&input_buffer)
-        .set_arg(&count_buffer)
-        .set_global_work_sizes(&[max_array_size, max_array_size])
-        .set_wait_event(&input_write_event)
-        .set_wait_event(&count_write_event)
-        .enqueue_nd_range(&queue)?;
+    let constant = !already_sorted && args.skip_if_constant && sorter.is_constant_gpu(input)?;
+    if constant && args.verbose {
+        if args.timing_only {
+            eprintln!("Input is constant, skipped the parallel sort");
+        } else {
+            println!("Input is constant, skipped the parallel sort");
+        }
+    }
 
-    let mut events: Vec<cl_event> = Vec::default();
-    events.push(compare_kernel_event.get());
+    // A generated input with a max value of 0 is all zeros, so already
+    // sorted too.
+    let skipped = already_sorted || constant || (args.input.is_none() && max_sort_value == 0);
+    let mut chosen = None;
+    let mut multi = None;
+    let (result, report) = match args.algorithm {
+        _ if skipped => (input[window.clone()].to_vec(), ProfilingReport::default()),
+        Algorithm::MultiDevice if args.chunk_size.is_some() => {
+            return Err(SortError::InvalidInput(
+                "--chunk-size does not apply to --algorithm multi-device".to_string(),
+            ));
+        }
+        _ if args.chunk_size.is_some() => {
+            let chunk_len = args.chunk_size.unwrap_or(max_array_size);
+            let sorter = match args.algorithm {
+                Algorithm::Rank => rank_sorter,
+                _ => &sorter,
+            };
+            let start = SystemTime::now();
+            let algorithm = match args.algorithm {
+                Algorithm::Rank => SortAlgorithm::Rank,
+                Algorithm::Bitonic => SortAlgorithm::Bitonic,
+                Algorithm::Auto => {
+                    let algorithm =
+                        sorter.choose_algorithm(&input[..chunk_len.min(max_array_size)])?;
+                    chosen = Some(algorithm);
+                    algorithm
+                }
+                Algorithm::Radix | Algorithm::MultiDevice => SortAlgorithm::Radix,
+            };
+            let result = sorter.sort_chunked_window(input, algorithm, chunk_len, window.clone())?;
+            let end = SystemTime::now();
+            let elapsed = end.duration_since(start).unwrap_or_default().as_nanos() as u64;
+            let report = ProfilingReport {
+                compare_ns: Some(elapsed),
+                assign_ns: None,
+                host_wall_ns: elapsed,
+            };
+            (result, report)
+        }
+        Algorithm::Rank => {
+            let trace = rank_sorter.sort_traced(input, window.clone(), args.dump_ranks)?;
+            if let Some(ranks) = &trace.ranks {
+                let ranks: Vec<String> = ranks.iter().map(i32::to_string).collect();
+                if args.timing_only {
+                    eprintln!("Ranks\n{}\n", ranks.join(" "));
+                } else {
+                    println!("Ranks\n{}\n", ranks.join(" "));
+                }
+            }
+            if let Some(path) = &args.trace {
+                let phases: Vec<(&str, &Event)> = trace
+                    .events
+                    .iter()
+                    .map(|(phase, event)| (*phase, event))
+                    .collect();
+                fs::write(path, trace_csv(&phases)?)?;
+            }
+            (trace.result, trace.report)
+        }
+        Algorithm::Radix => {
+            let report = |pass, total| eprint!("\rRadix pass {}/{}", pass, total);
+            let progress: Option<&dyn Fn(usize, usize)> =
+                if args.progress { Some(&report) } else { None };
 
-    let assign_kernel_event = ExecuteKernel::new(&assign_kernel)
-        .set_arg(&input_buffer)
-        .set_arg(&count_buffer)
-        .set_arg(&result_buffer)
-        .set_global_work_size(max_array_size)
-        .set_wait_event(&result_write_event)
-        .set_wait_event(&compare_kernel_event)
-        .enqueue_nd_range(&queue)?;
+            let start = SystemTime::now();
+            let result = sorter.sort_radix_window(input, window.clone(), progress)?;
+            let end = SystemTime::now();
+            if args.progress {
+                eprintln!();
+            }
+            let elapsed = end.duration_since(start).unwrap_or_default().as_nanos() as u64;
+            let report = ProfilingReport {
+                compare_ns: Some(elapsed),
+                assign_ns: None,
+                host_wall_ns: elapsed,
+            };
+            (result, report)
+        }
+        Algorithm::Bitonic => {
+            let report = |stage, total| eprint!("\rBitonic stage {}/{}", stage, total);
+            let progress: Option<&dyn Fn(usize, usize)> =
+                if args.progress { Some(&report) } else { None };
 
-    events.push(assign_kernel_event.get());
+            let sorted = sorter.sort_bitonic_profiled(input, window.clone(), progress)?;
+            if args.progress {
+                eprintln!();
+            }
+            sorted
+        }
+        Algorithm::MultiDevice => {
+            let sorter = multi.insert(multi_sorter(
+                args.platform,
+                args.max_devices,
+                args.fixed_local_size,
+            )?);
+            let start = SystemTime::now();
+            let result = sorter.sort_window(input, window.clone())?;
+            let end = SystemTime::now();
+            if args.verbose {
+                let devices = format!("Sorted across {} devices", sorter.sorters().len());
+                if args.timing_only {
+                    eprintln!("{}", devices);
+                } else {
+                    println!("{}", devices);
+                }
+            }
+            let elapsed = end.duration_since(start).unwrap_or_default().as_nanos() as u64;
+            let report = ProfilingReport {
+                compare_ns: Some(elapsed),
+                assign_ns: None,
+                host_wall_ns: elapsed,
+            };
+            (result, report)
+        }
+        Algorithm::Auto => {
+            let start = SystemTime::now();
+            let (result, algorithm) = sorter.sort_auto_window(input, window.clone())?;
+            let end = SystemTime::now();
+            chosen = Some(algorithm);
+            if algorithm == SortAlgorithm::Host {
+                eprintln!(
+                    "Warning: {} elements don't fit in device memory, sorting on the host",
+                    input.len()
+                );
+            }
+            if args.verbose {
+                let range = match (input.iter().min(), input.iter().max()) {
+                    (Some(&min), Some(&max)) => (max as i64 - min as i64) as u64 + 1,
+                    _ => 0,
+                };
+                let note = if algorithm == SortAlgorithm::Insertion {
+                    " (input detected as nearly sorted)".to_string()
+                } else if algorithm == SortAlgorithm::Reverse {
+                    " (input detected as reverse sorted)".to_string()
+                } else if range > sorter.counting_bin_cap() as u64 {
+                    format!(
+                        " (counting sort skipped: the range of {} values exceeds the cap of {} bins)",
+                        range,
+                        sorter.counting_bin_cap()
+                    )
+                } else {
+                    String::new()
+                };
+                if args.timing_only {
+                    eprintln!("Algorithm: {}{}", algorithm, note);
+                } else {
+                    println!("Algorithm: {}{}", algorithm, note);
+                }
+            }
+            let elapsed = end.duration_since(start).unwrap_or_default().as_nanos() as u64;
+            let report = ProfilingReport {
+                compare_ns: Some(elapsed),
+                assign_ns: None,
+                host_wall_ns: elapsed,
+            };
+            (result, report)
+        }
+    };
 
-    let mut result = vec![0; max_array_size];
-    let read_event =
-        queue.enqueue_read_buffer(&result_buffer, CL_BLOCKING, 0, &mut result, &events)?;
+    if report.is_partial() {
+        eprintln!("Warning: the driver did not report kernel profiling times");
+    }
 
-    read_event.wait()?;
+    if args.verbose {
+        let algorithm = match args.algorithm {
+            Algorithm::Rank => SortAlgorithm::Rank,
+            Algorithm::Radix | Algorithm::MultiDevice => SortAlgorithm::Radix,
+            Algorithm::Auto => chosen.unwrap_or(SortAlgorithm::Host),
+            Algorithm::Bitonic => SortAlgorithm::Bitonic,
+        };
+        let timings = format!(
+            "Device kernel time: {}\nHost wall-clock time: {}\nOverhead: {}\n\
+             Largest {} sort chunk (elements): {}\n",
+            duration_or_unknown(report.device_ns()),
+            format_duration(report.host_wall_ns),
+            duration_or_unknown(report.overhead_ns()),
+            algorithm,
+            max_chunk_elements(&device, algorithm)?
+        );
+        if args.timing_only {
+            eprintln!("{}", timings);
+        } else {
+            println!("{}", timings);
+        }
+    }
 
-    println!("Array to Sort");
-    for i in 0..max_array_size {
-        print!("{} ", input[i]);
+    if args.verbose && matches!(args.algorithm, Algorithm::Rank) {
+        let n = input.len() as u64;
+        let fired = rank_sorter.compare_atomic_count(input)?;
+        let atomics = format!(
+            "Compare atomics fired: {} for smaller values and {} breaking ties, \
+             one for each of the {} pairs",
+            fired.smaller,
+            fired.ties,
+            n * n.saturating_sub(1) / 2
+        );
+        if args.timing_only {
+            eprintln!("{}", atomics);
+        } else {
+            println!("{}", atomics);
+        }
     }
-    print!("\n\n");
-    println!("Parallel Sorted Array");
-    for i in 0..max_array_size {
-        print!("{} ", result[i]);
+
+    if let Some(path) = &args.manifest {
+        let manifest = manifest(
+            &args,
+            &DeviceInfo::of_device(&device)?,
+            seed,
+            max_array_size,
+            max_sort_value,
+            chosen,
+        );
+        if path.as_os_str() != "-" {
+            fs::write(path, manifest)?;
+        } else if args.timing_only {
+            eprintln!("{}", manifest);
+        } else {
+            println!("{}", manifest);
+        }
     }
-    print!("\n");
 
-    let compare_start_time = compare_kernel_event.profiling_command_start()?;
-    let compare_end_time = compare_kernel_event.profiling_command_end()?;
-    let assign_start_time = compare_kernel_event.profiling_command_start()?;
-    let assign_end_time = compare_kernel_event.profiling_command_end()?;
-    let duration = (compare_end_time - compare_start_time) + (assign_end_time - assign_start_time);
-    println!("Parallel execution duration (ns): {}\n", duration);
+    if args.verify_permutation {
+        if !is_permutation(&result, input) {
+            eprintln!("Error: the parallel sort's output is not a permutation of the input");
+            process::exit(1);
+        }
+        if !args.timing_only {
+            println!("Permutation check passed\n");
+        }
+    }
 
-    let mut merge_sort_input = vec![0; 0];
-    for i in 0..max_array_size {
-        merge_sort_input.push(input[i]);
+    if let Some(path) = &args.output {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match args.output_format {
+            OutputFormat::BinaryFramed => write_framed(&mut writer, &result)?,
+            OutputFormat::Varint => write_varint(&mut writer, &result)?,
+        }
+        writer.flush()?;
     }
+
+    if args.with_indices {
+        let (sorted, indices) = sorter.sort_with_indices(input)?;
+        let lines: Vec<String> = sorted
+            .iter()
+            .zip(&indices)
+            .map(|(value, index)| format!("{},{}", value, index))
+            .collect();
+        if args.timing_only {
+            eprintln!("value,original_index\n{}\n", lines.join("\n"));
+        } else {
+            println!("value,original_index\n{}\n", lines.join("\n"));
+        }
+    }
+
     let start = SystemTime::now();
-    let merge_sort_result = merge_sort(&merge_sort_input);
+    let merge_sort_result = merge_sort(input);
     let end = SystemTime::now();
-    let elapsed = end.duration_since(start);
+    let elapsed = end.duration_since(start).unwrap_or_default().as_nanos();
+
+    let std_elapsed = args.compare_std.then(|| {
+        let mut std_sort_result = input.to_vec();
+        let start = SystemTime::now();
+        std_sort_result.sort_unstable();
+        let end = SystemTime::now();
+        end.duration_since(start).unwrap_or_default().as_nanos()
+    });
+
+    let repeat_summary = match args.repeat {
+        Some(runs) if runs > 0 => {
+            // The repeats run on the sorter the sort above used, with its
+            // settings, so that sort is the first run, which paid for
+            // finalizing the kernels. If it was skipped, the first repeat is.
+            if multi.is_none() && matches!(args.algorithm, Algorithm::MultiDevice) {
+                multi = Some(multi_sorter(
+                    args.platform,
+                    args.max_devices,
+                    args.fixed_local_size,
+                )?);
+            }
+            let repeat_sorter = match args.algorithm {
+                Algorithm::Rank => rank_sorter,
+                _ => &sorter,
+            };
+            let mut timings = Vec::with_capacity(runs + 1);
+            if !skipped {
+                timings.push(report.host_wall_ns);
+            }
+            timings.extend(time_runs(
+                args.algorithm,
+                repeat_sorter,
+                multi.as_ref(),
+                input,
+                runs,
+            )?);
+            Some(describe_runs(&timings))
+        }
+        _ => None,
+    };
+
+    if args.timing_only {
+        if let Some(summary) = &repeat_summary {
+            eprintln!("{}", summary);
+        }
+        print!(
+            "{}\t{}\t{}\t{}\t{}",
+            max_array_size,
+            max_sort_value,
+            or_unknown(report.compare_ns),
+            or_unknown(report.assign_ns),
+            elapsed
+        );
+        match std_elapsed {
+            Some(std_elapsed) => println!("\t{}", std_elapsed),
+            None => println!(),
+        }
+        return Ok(());
+    }
+
+    println!("Array to Sort");
+    for value in input {
+        print!("{} ", value);
+    }
+    print!("\n\n");
+    println!("Parallel Sorted Array");
+    for value in &result {
+        print!("{} ", value);
+    }
+    println!();
+    println!(
+        "Parallel execution duration: {}\n",
+        duration_or_unknown(report.device_ns())
+    );
 
     println!("MergeSort Sorted Array");
-    for i in 0..max_array_size {
-        print!("{} ", merge_sort_result[i]);
+    for value in &merge_sort_result[window] {
+        print!("{} ", value);
     }
-    print!("\n");
+    println!();
     println!(
-        "MergeSort execution duration (ns): {}\n",
-        elapsed.unwrap_or_default().as_nanos()
+        "MergeSort execution duration: {}\n",
+        format_duration(elapsed as u64)
     );
 
+    if let Some(std_elapsed) = std_elapsed {
+        println!(
+            "sort_unstable execution duration: {}\n",
+            format_duration(std_elapsed as u64)
+        );
+    }
+
+    if let Some(summary) = &repeat_summary {
+        println!("{}", summary);
+    }
+
+    let builtin_sorts = builtin_sort_kernels(&device)?;
+    if builtin_sorts.is_empty() {
+        println!("No built-in sort kernel advertised by the device, skipped");
+    } else {
+        println!(
+            "Built-in sort kernels advertised but not run, as their arguments are vendor-defined: {}",
+            builtin_sorts.join(", ")
+        );
+    }
+
     //Everything is fine
     Ok(())
 }
 
-fn merge_sort(vec: &Vec<i32>) -> Vec<i32> {
+/// Inserts the flags read from a config file into `argv` after the program
+/// name. Later occurrences of a flag override earlier ones, so the file's
+/// options go before the command line's and the command line wins.
+fn with_config(mut argv: Vec<OsString>, config: Vec<String>) -> Vec<OsString> {
+    let cli_args = argv.split_off(argv.len().min(1));
+    argv.extend(config.into_iter().map(OsString::from));
+    argv.extend(cli_args);
+    argv
+}
+
+/// Reads the config file at `path` into command-line flags, see
+/// [`parse_config`].
+fn config_args(path: &Path) -> Result<Vec<String>> {
+    parse_config(&fs::read_to_string(path)?, path)
+}
+
+/// Turns the `key = value` lines of a flat TOML config file, read from
+/// `path`, into command-line flags. Values may be integers, strings or
+/// booleans; `true` enables a flag and `false` leaves it out. Keys that
+/// aren't long flags are warned about and skipped, and `-` and `_` are
+/// interchangeable in them.
+fn parse_config(text: &str, path: &Path) -> Result<Vec<String>> {
+    let command = Args::command();
+    let invalid = |number: usize, message: &str| {
+        SortError::InvalidInput(format!("{}:{}: {}", path.display(), number + 1, message))
+    };
+
+    let mut args = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(invalid(number, "tables are not supported"));
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| invalid(number, "expected `key = value`"))?;
+        let key = key.trim().replace('_', "-");
+        let value = value.trim();
+        let (value, quoted) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let (value, rest) = quoted
+                    .split_once('"')
+                    .ok_or_else(|| invalid(number, "unterminated string"))?;
+                let rest = rest.trim();
+                if !rest.is_empty() && !rest.starts_with('#') {
+                    return Err(invalid(number, "unexpected text after the string"));
+                }
+                (value.to_string(), true)
+            }
+            None => {
+                let value = value.split_once('#').map_or(value, |(value, _)| value);
+                (value.trim().replace('_', ""), false)
+            }
+        };
+
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key.as_str()));
+        let Some(arg) = arg.filter(|_| key != "config") else {
+            eprintln!(
+                "Warning: ignoring unknown key {:?} in {}",
+                key,
+                path.display()
+            );
+            continue;
+        };
+        match value.as_str() {
+            "true" if !quoted && !arg.get_action().takes_values() => {
+                args.push(format!("--{}", key))
+            }
+            "false" if !quoted && !arg.get_action().takes_values() => {}
+            _ => args.push(format!("--{}={}", key, value)),
+        }
+    }
+    Ok(args)
+}
+
+/// The manifest of a run in the `--config` format: the flags that reproduce
+/// its input and algorithm, followed by the other settings that affect the
+/// result or timing as comments. `chosen` is what `--algorithm auto` picked.
+fn manifest(
+    args: &Args,
+    device: &DeviceInfo,
+    seed: u64,
+    size: usize,
+    max_value: i32,
+    chosen: Option<SortAlgorithm>,
+) -> String {
+    let mut lines = vec![format!("algorithm = {:?}", value_name(args.algorithm))];
+    match &args.input {
+        Some(path) => {
+            lines.push(format!("input = {:?}", path.display().to_string()));
+            lines.push(format!(
+                "input-format = {:?}",
+                value_name(args.input_format)
+            ));
+            lines.push(format!("input-radix = {}", args.input_radix));
+            lines.push(format!(
+                "on-parse-error = {:?}",
+                value_name(args.on_parse_error)
+            ));
+            lines.push(format!("size = {}", size));
+        }
+        None => {
+            lines.push(format!("size = {}", size));
+            lines.push(format!("max-value = {}", max_value));
+            lines.push(format!("seed = {}", seed));
+        }
+    }
+    if let Some(max_devices) = args.max_devices {
+        lines.push(format!("max-devices = {}", max_devices));
+    }
+    if let Some(chosen) = chosen {
+        lines.push(format!("# chosen-algorithm = \"{}\"", chosen));
+    }
+    lines.push(format!("# crate-version = {:?}", env!("CARGO_PKG_VERSION")));
+    lines.push(format!("# device = {:?}", device.name));
+    lines.push(format!("# device-vendor = {:?}", device.vendor));
+    lines.push(format!("# device-version = {:?}", device.version));
+    lines.push(format!("# driver-version = {:?}", device.driver_version));
+    lines.push(format!("# build-options = {:?}", BUILD_OPTIONS));
+    lines.join("\n")
+}
+
+/// The name `value` is given as on the command line.
+fn value_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map_or(String::new(), |value| value.get_name().to_string())
+}
+
+/// Times `runs` sorts of `input` with `algorithm` on `sorter`, or for a
+/// multi-device sort on `multi`, which must then be given, in nanoseconds of
+/// host wall-clock time each.
+fn time_runs(
+    algorithm: Algorithm,
+    sorter: &GpuSorter,
+    multi: Option<&MultiSorter>,
+    input: &[i32],
+    runs: usize,
+) -> Result<Vec<u64>> {
+    (0..runs)
+        .map(|_| {
+            let start = SystemTime::now();
+            match (algorithm, multi) {
+                (Algorithm::Rank, _) => sorter.sort(input)?,
+                (Algorithm::Radix, _) => sorter.sort_radix(input, None)?,
+                (Algorithm::Auto, _) => sorter.sort_auto(input)?.0,
+                (Algorithm::Bitonic, _) => sorter.sort_bitonic(input)?,
+                (Algorithm::MultiDevice, Some(multi)) => multi.sort(input)?,
+                (Algorithm::MultiDevice, None) => {
+                    return Err(SortError::InvalidInput(
+                        "a multi-device sort needs a multi-device sorter".to_string(),
+                    ))
+                }
+            };
+            let end = SystemTime::now();
+            Ok(end.duration_since(start).unwrap_or_default().as_nanos() as u64)
+        })
+        .collect()
+}
+
+/// A sorter on `device` that launches in work-groups of `fixed_local_size`,
+/// if given.
+fn run_sorter(device: &Device, fixed_local_size: Option<usize>) -> Result<GpuSorter> {
+    match fixed_local_size {
+        Some(local) => GpuSorter::builder()
+            .round_work_size(local)
+            .build_for(device),
+        None => GpuSorter::from_device(device),
+    }
+}
+
+/// A sorter across every GPU, or those on the platform at `platform`, or the
+/// `max_devices` most capable, launching in work-groups of
+/// `fixed_local_size` if given.
+fn multi_sorter(
+    platform: Option<usize>,
+    max_devices: Option<usize>,
+    fixed_local_size: Option<usize>,
+) -> Result<MultiSorter> {
+    let mut builder = GpuSorter::builder();
+    if let Some(index) = platform {
+        builder = builder.platform_index(index);
+    }
+    if let Some(local) = fixed_local_size {
+        builder = builder.round_work_size(local);
+    }
+    match max_devices {
+        Some(n) => builder.max_devices(n).build_multi(),
+        None => builder.build_multi(),
+    }
+}
+
+/// Prints the numbers in the text file at `path`, or in stdin if it is `-`,
+/// sorted as `f64`s, one per line with [`float_lines`].
+fn sort_floats(path: &Path, precision: Option<usize>) -> Result<()> {
+    let text = if path.as_os_str() == "-" {
+        io::read_to_string(io::stdin().lock())?
+    } else {
+        fs::read_to_string(path)?
+    };
+    let sorted = GpuSorter::new()?.sort_f64(&parse_floats(&text)?, NanPolicy::Last)?;
+    print!("{}", float_lines(&sorted, precision));
+    Ok(())
+}
+
+/// `values` one per line, formatted by [`format_float`] with `precision`.
+fn float_lines(values: &[f64], precision: Option<usize>) -> String {
+    values
+        .iter()
+        .map(|&value| format_float(value, precision) + "\n")
+        .collect()
+}
+
+/// Prints the lines of the text file at `path`, or of stdin if it is `-`,
+/// stably sorted by the integer in field `field` of each.
+fn sort_lines(path: &Path, field: usize, delimiter: char, radix: u32) -> Result<()> {
+    let text = if path.as_os_str() == "-" {
+        io::read_to_string(io::stdin().lock())?
+    } else {
+        fs::read_to_string(path)?
+    };
+    let lines = sorted_lines(&GpuSorter::new()?, &text, field, delimiter, radix)?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    for line in lines {
+        writeln!(out, "{}", line)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// The lines of `text` stably sorted by the integer in field `field` of
+/// each, for [`sort_lines`].
+fn sorted_lines<'a>(
+    sorter: &GpuSorter,
+    text: &'a str,
+    field: usize,
+    delimiter: char,
+    radix: u32,
+) -> Result<Vec<&'a str>> {
+    let keys = field_keys(text, field, delimiter, radix)?;
+    let indices: Vec<i32> = (0..keys.len() as i32).collect();
+    let (_, order) = sorter.sort_stable(&keys, &indices)?;
+    let lines: Vec<&str> = text.lines().collect();
+    Ok(order.iter().map(|&index| lines[index as usize]).collect())
+}
+
+/// A CSV timeline of the commands in `phases`, one row each under
+/// [`TRACE_HEADER`], in nanoseconds since the first of them was queued, so the
+/// phases can be told apart and lined up. The gap from queued to submit is
+/// time spent waiting in the host queue, from submit to start time waiting on
+/// the device, and from start to end the command itself.
+fn trace_csv(phases: &[(&str, &Event)]) -> Result<String> {
+    let mut times = Vec::with_capacity(phases.len());
+    for (phase, event) in phases {
+        times.push((
+            phase,
+            [
+                event.profiling_command_queued()?,
+                event.profiling_command_submit()?,
+                event.profiling_command_start()?,
+                event.profiling_command_end()?,
+            ],
+        ));
+    }
+    let origin = times
+        .iter()
+        .map(|(_, [queued, ..])| *queued)
+        .min()
+        .unwrap_or_default();
+    let mut csv = format!("{}\n", TRACE_HEADER);
+    for (phase, stamps) in times {
+        let stamps: Vec<String> = stamps.iter().map(|t| (t - origin).to_string()).collect();
+        csv += &format!("{},{}\n", phase, stamps.join(","));
+    }
+    Ok(csv)
+}
+
+/// The columns of a [`trace_csv`] timeline.
+const TRACE_HEADER: &str = "phase,queued_ns,submit_ns,start_ns,end_ns";
+
+/// A timing for output, or `-` if the driver didn't report it.
+fn or_unknown(ns: Option<u64>) -> String {
+    ns.map_or("-".to_string(), |ns| ns.to_string())
+}
+
+/// A timing for people to read, or `-` if the driver didn't report it.
+fn duration_or_unknown(ns: Option<u64>) -> String {
+    ns.map_or("-".to_string(), format_duration)
+}
+
+/// Reports the first of `timings` on its own, since it pays for the driver
+/// finalizing the kernels on their first launch, and summarizes the rest as
+/// the steady state.
+fn describe_runs(timings: &[u64]) -> String {
+    let first = format!(
+        "First run, including one-time setup: {}",
+        format_duration(timings[0])
+    );
+    let warmed = &timings[1..];
+    match warmed.iter().min() {
+        Some(min) => format!(
+            "{}\nSteady state over {} runs: mean {}, min {}\n",
+            first,
+            warmed.len(),
+            format_duration(warmed.iter().sum::<u64>() / warmed.len() as u64),
+            format_duration(*min)
+        ),
+        None => format!(
+            "{}\nSteady state: no warmed runs, use --repeat 2 or more\n",
+            first
+        ),
+    }
+}
+
+/// Reports on stderr how many invalid tokens --on-parse-error let through.
+fn warn_invalid_tokens(invalid: usize, on_parse_error: OnParseError) {
+    match on_parse_error {
+        _ if invalid == 0 => {}
+        OnParseError::Skip => eprintln!("Warning: skipped {} invalid tokens", invalid),
+        OnParseError::Zero => eprintln!("Warning: read {} invalid tokens as 0", invalid),
+        OnParseError::Fail => {}
+    }
+}
+
+/// Parses the argument of --input-radix.
+fn parse_radix(value: &str) -> std::result::Result<u32, String> {
+    match value.parse() {
+        Ok(radix @ (2 | 8 | 10 | 16)) => Ok(radix),
+        _ => Err(format!("expected 2, 8, 10 or 16, got {:?}", value)),
+    }
+}
+
+/// Converts a requested element count to `usize`, rejecting empty and
+/// negative counts and ones too large for a single 4-byte-element buffer on
+/// `device`, before anything is allocated for them.
+fn checked_size(size: i32, device: &Device) -> Result<usize> {
+    let max = usize::try_from(device.max_mem_alloc_size()? / mem::size_of::<cl_int>() as u64)
+        .unwrap_or(usize::MAX);
+    size_in_range(size, max)
+}
+
+/// [`checked_size`] for a device buffer of at most `max` elements.
+fn size_in_range(size: i32, max: usize) -> Result<usize> {
+    match usize::try_from(size) {
+        Ok(size) if (1..=max).contains(&size) => Ok(size),
+        _ => Err(SortError::InvalidSize {
+            size: size.into(),
+            max,
+        }),
+    }
+}
+
+/// Number of times a prompt is repeated before giving up on bad input.
+const PROMPT_ATTEMPTS: usize = 3;
+
+/// Prompts for a non-negative integer read from `input`, asking again if the
+/// answer doesn't parse. In timing-only mode the prompts go to stderr to
+/// keep stdout clean.
+fn prompt_value(message: &str, timing_only: bool, input: &mut impl BufRead) -> Result<i32> {
+    let say = |text: &str| {
+        if timing_only {
+            eprintln!("{}", text);
+        } else {
+            println!("{}", text);
+        }
+    };
+
+    say(message);
+    let mut answer = String::new();
+    for attempt in 1..=PROMPT_ATTEMPTS {
+        answer.clear();
+        if input.read_line(&mut answer)? == 0 {
+            break;
+        }
+        match answer.trim().parse::<i32>() {
+            Ok(value) if value >= 0 => return Ok(value),
+            _ if attempt < PROMPT_ATTEMPTS => say("Please enter a non-negative integer:"),
+            _ => {}
+        }
+    }
+    Err(SortError::InvalidInput(format!(
+        "expected a non-negative integer, got {:?}",
+        answer.trim()
+    )))
+}
+
+/// Prints the profiled time of a blocking write and read of buffers from
+/// 1024 elements up to `max_elements`, with the round trip's bandwidth. This
+/// is the transfer floor under every sort's own timings.
+fn bench_transfer(max_elements: usize) -> Result<()> {
+    let sorter = GpuSorter::new()?;
+    let queue = sorter.queue();
+    println!("elements,bytes,write_ns,read_ns,round_trip_gb_per_s");
+    let mut elements = 1024;
+    while elements <= max_elements {
+        let data = vec![0; elements];
+        let mut buffer = Buffer::<cl_int>::create(
+            sorter.context(),
+            CL_MEM_READ_WRITE,
+            elements,
+            ptr::null_mut(),
+        )?;
+        let write_event = write_i32(queue, &mut buffer, "transfer", &data)?;
+        let mut read_back = vec![0; elements];
+        let read_event = queue.enqueue_read_buffer(&buffer, CL_BLOCKING, 0, &mut read_back, &[])?;
+        let write_ns =
+            write_event.profiling_command_end()? - write_event.profiling_command_start()?;
+        let read_ns = read_event.profiling_command_end()? - read_event.profiling_command_start()?;
+        let bytes = elements * mem::size_of::<cl_int>();
+        // Bytes per nanosecond are gigabytes per second.
+        let bandwidth = (2 * bytes) as f64 / (write_ns + read_ns).max(1) as f64;
+        println!(
+            "{},{},{},{},{:.3}",
+            elements, bytes, write_ns, read_ns, bandwidth
+        );
+        elements *= 4;
+    }
+    Ok(())
+}
+
+/// The strategies [`bench_compare_kernels`] times, each with its own compare
+/// kernel.
+const COMPARE_STRATEGIES: [(&str, RankStrategy); 3] = [
+    ("atomic", RankStrategy::Atomic),
+    ("tiled", RankStrategy::Tiled),
+    ("loop", RankStrategy::Loop),
+];
+
+/// Prints the fastest of `runs` warmed launches of each compare kernel in
+/// [`COMPARE_STRATEGIES`], by device time, for sizes from 1024 up to
+/// `max_elements`. Each size is timed on distinct values, on `n / 16`
+/// distinct values and on 16, as duplicates make the atomic kernel's
+/// increments contend, and each row names the kernel that won it.
+fn bench_compare_kernels(max_elements: usize, runs: usize) -> Result<()> {
+    let sorters = COMPARE_STRATEGIES
+        .iter()
+        .map(|&(_, strategy)| GpuSorter::builder().rank_strategy(strategy).build())
+        .collect::<Result<Vec<_>>>()?;
+    let mut rng = StdRng::seed_from_u64(0);
+    println!("elements,distinct_values,atomic_ns,tiled_ns,loop_ns,fastest");
+    let mut elements = 1024;
+    while elements <= max_elements {
+        for distinct in [i32::MAX, (elements / 16) as i32, 16] {
+            let input = random_input(elements, distinct, &mut rng);
+            let mut timings = Vec::with_capacity(sorters.len());
+            for sorter in &sorters {
+                // The first launch pays for the driver finalizing the kernel.
+                let mut best = u64::MAX;
+                for _ in 0..=runs {
+                    let (_, report) = sorter.ranks_profiled(&input)?;
+                    best = best.min(report.compare_ns.unwrap_or(report.host_wall_ns));
+                }
+                timings.push(best);
+            }
+            let fastest = (0..timings.len())
+                .min_by_key(|&index| timings[index])
+                .map_or("", |index| COMPARE_STRATEGIES[index].0);
+            println!(
+                "{},{},{},{},{},{}",
+                elements, distinct, timings[0], timings[1], timings[2], fastest
+            );
+        }
+        elements *= 4;
+    }
+    Ok(())
+}
+
+/// Header of the CSV that [`bench`] prints and keeps as a baseline.
+const BENCH_HEADER: &str = "elements,ns";
+
+/// Prints the fastest of `runs` warmed sorts of random input with
+/// `algorithm`, for sizes from 1024 up to `max_elements`. The inputs are
+/// saved to or loaded from a `corpus` directory if one is given. If `baseline`
+/// exists, each size is checked against its timing there and any that got
+/// more than `threshold` percent slower is reported on stderr; otherwise the
+/// timings are written to it. Returns whether no size regressed.
+fn bench(
+    algorithm: Algorithm,
+    max_elements: usize,
+    runs: usize,
+    baseline: Option<&Path>,
+    threshold: f64,
+    corpus: Option<&Corpus>,
+    read_strategy: ReadStrategy,
+) -> Result<bool> {
+    if let Some(Corpus::Save(dir)) = corpus {
+        fs::create_dir_all(dir)?;
+    }
+    // One sorter times every size, so only the very first sort pays for
+    // the driver finalizing the kernels.
+    let sorter = GpuSorter::builder().read_strategy(read_strategy).build()?;
+    let multi = match algorithm {
+        Algorithm::MultiDevice => Some(multi_sorter(None, None, None)?),
+        _ => None,
+    };
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut timings = Vec::new();
+    println!("{}", BENCH_HEADER);
+    let mut elements = 1024;
+    while elements <= max_elements {
+        let input = corpus_input(corpus, elements, &mut rng)?;
+        // The first sort pays for the driver finalizing the kernels.
+        let ns = time_runs(algorithm, &sorter, multi.as_ref(), &input, runs + 1)?[1..]
+            .iter()
+            .copied()
+            .min()
+            .unwrap_or_default();
+        println!("{},{}", elements, ns);
+        timings.push((elements, ns));
+        elements *= 4;
+    }
+
+    let Some(path) = baseline else {
+        return Ok(true);
+    };
+    if !path.exists() {
+        let mut csv = vec![BENCH_HEADER.to_string()];
+        csv.extend(
+            timings
+                .iter()
+                .map(|(elements, ns)| format!("{},{}", elements, ns)),
+        );
+        fs::write(path, csv.join("\n") + "\n")?;
+        eprintln!("Wrote baseline {}", path.display());
+        return Ok(true);
+    }
+    let regressed = regressions(
+        &parse_bench_csv(&fs::read_to_string(path)?)?,
+        &timings,
+        threshold,
+    );
+    for (elements, was, now) in &regressed {
+        eprintln!(
+            "Regression at {} elements: {} ns, baseline {} ns (+{:.1}%)",
+            elements,
+            now,
+            was,
+            (*now as f64 / *was as f64 - 1.0) * 100.0
+        );
+    }
+    Ok(regressed.is_empty())
+}
+
+/// [`bench`]'s input of `elements` values: loaded from the corpus, or drawn
+/// from `rng` and saved to the corpus if there is one to save to.
+fn corpus_input(corpus: Option<&Corpus>, elements: usize, rng: &mut StdRng) -> Result<Vec<i32>> {
+    let input = match corpus {
+        Some(Corpus::Load(dir)) => {
+            let path = dir.join(format!("{}.bin", elements));
+            let input = read_framed(&path)?;
+            if input.len() != elements {
+                return Err(SortError::InvalidInput(format!(
+                    "{} holds {} values, not {}",
+                    path.display(),
+                    input.len(),
+                    elements
+                )));
+            }
+            input
+        }
+        _ => random_input(elements, i32::MAX, rng),
+    };
+    if let Some(Corpus::Save(dir)) = corpus {
+        let mut file = BufWriter::new(File::create(dir.join(format!("{}.bin", elements)))?);
+        write_framed(&mut file, &input)?;
+        file.flush()?;
+    }
+    Ok(input)
+}
+
+/// Parses `elements,ns` rows of a CSV printed by [`bench`].
+fn parse_bench_csv(csv: &str) -> Result<Vec<(usize, u64)>> {
+    csv.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && *line != BENCH_HEADER)
+        .map(|line| {
+            line.split_once(',')
+                .and_then(|(elements, ns)| Some((elements.parse().ok()?, ns.parse().ok()?)))
+                .ok_or_else(|| SortError::InvalidInput(format!("invalid baseline row {:?}", line)))
+        })
+        .collect()
+}
+
+/// The sizes in `current` more than `threshold` percent slower than in
+/// `baseline`, with the baseline's and the current timing. Sizes missing
+/// from the baseline are not compared.
+fn regressions(
+    baseline: &[(usize, u64)],
+    current: &[(usize, u64)],
+    threshold: f64,
+) -> Vec<(usize, u64, u64)> {
+    let baseline: HashMap<usize, u64> = baseline.iter().copied().collect();
+    current
+        .iter()
+        .filter_map(|&(elements, now)| {
+            let was = *baseline.get(&elements)?;
+            (now as f64 > was as f64 * (1.0 + threshold / 100.0)).then_some((elements, was, now))
+        })
+        .collect()
+}
+
+/// Builds the kernels on every device and checks a small sort of each
+/// supported type against the CPU, printing a result per check. Returns
+/// whether everything passed.
+fn selftest() -> Result<bool> {
+    let mut rng = StdRng::seed_from_u64(0);
+    let ints: Vec<i32> = (0..257).map(|_| rng.gen()).collect();
+    let longs: Vec<u64> = (0..257).map(|_| rng.gen()).collect();
+    let shorts: Vec<i16> = (0..257).map(|_| rng.gen()).collect();
+    let bytes: Vec<u8> = (0..257).map(|_| rng.gen()).collect();
+    let floats: Vec<f64> = (0..257).map(|_| rng.gen_range(-1e6..1e6)).collect();
+    let words: Vec<String> = ["pear", "fig", "apple", "kiwi", "date", "plum", "lime"]
+        .iter()
+        .map(|word| word.to_string())
+        .collect();
+
+    let device_ids = find_devices(CL_DEVICE_TYPE_ALL)?;
+    if device_ids.is_empty() {
+        println!("FAIL no OpenCL devices found");
+        return Ok(false);
+    }
+
+    let mut passed = true;
+    for device_id in device_ids {
+        let device = Device::new(device_id);
+        println!("{}", device.name()?);
+
+        let sorter = match GpuSorter::from_device(&device) {
+            Ok(sorter) => sorter,
+            Err(e) => {
+                println!("\tFAIL kernel build: {}", e);
+                passed = false;
+                continue;
+            }
+        };
+
+        let mut checks: Vec<(&str, Result<Option<&str>>)> = vec![
+            (
+                "i32 rank",
+                sorter
+                    .sort(&ints)
+                    .map(|r| sort_fault(&r, &ints, SortOrder::Ascending)),
+            ),
+            (
+                "i32 radix",
+                sorter
+                    .sort_radix(&ints, None)
+                    .map(|r| sort_fault(&r, &ints, SortOrder::Ascending)),
+            ),
+            (
+                "u64",
+                sorter
+                    .sort_u64(&longs)
+                    .map(|r| sort_fault(&r, &longs, SortOrder::Ascending)),
+            ),
+            (
+                "i16",
+                sorter
+                    .sort_i16(&shorts)
+                    .map(|r| sort_fault(&r, &shorts, SortOrder::Ascending)),
+            ),
+            (
+                "u8",
+                sorter
+                    .sort_u8(&bytes)
+                    .map(|r| sort_fault(&r, &bytes, SortOrder::Ascending)),
+            ),
+            (
+                "strings",
+                sorter
+                    .sort_strings(&words, 8)
+                    .map(|r| sort_fault(&r, &words, SortOrder::Ascending)),
+            ),
+        ];
+        if supported_types(&device)?.contains(&ElementType::F64) {
+            let mut expected = floats.clone();
+            expected.sort_by(f64::total_cmp);
+            checks.push((
+                "f64",
+                sorter.sort_f64(&floats, NanPolicy::Last).map(|r| {
+                    (!r.iter()
+                        .map(|v| v.to_bits())
+                        .eq(expected.iter().map(|v| v.to_bits())))
+                    .then_some("result differs from the CPU sort")
+                }),
+            ));
+        }
+        for (name, check) in checks {
+            match check {
+                Ok(None) => println!("\tPASS {}", name),
+                Ok(Some(fault)) => {
+                    println!("\tFAIL {}: {}", name, fault);
+                    passed = false;
+                }
+                Err(e) => {
+                    println!("\tFAIL {}: {}", name, e);
+                    passed = false;
+                }
+            }
+        }
+    }
+    Ok(passed)
+}
+
+/// The part of a sorted result of `len` elements that `--head` or `--tail`
+/// asks for, and that the sort then reads back from the device: the first
+/// or last `head` or `tail` elements, or all of them.
+fn output_window(head: Option<usize>, tail: Option<usize>, len: usize) -> Range<usize> {
+    match (head, tail) {
+        (Some(head), _) => 0..head.min(len),
+        (_, Some(tail)) => len - tail.min(len)..len,
+        _ => 0..len,
+    }
+}
+
+fn sorted<T: Clone + Ord>(values: &[T]) -> Vec<T> {
+    let mut sorted = values.to_vec();
+    sorted.sort();
+    sorted
+}
+
+/// Whether `output` holds the same multiset of values as `input`. A sorted
+/// output can still be wrong by dropping values or leaving sentinels behind,
+/// which this catches.
+fn is_permutation<T: Clone + Ord>(output: &[T], input: &[T]) -> bool {
+    sorted(output) == sorted(input)
+}
+
+/// Describes what is wrong with `output` as a sort of `input` in `order`,
+/// or returns `None` if it is correct: it must be as long as the input, hold
+/// the same values and be in order. Every algorithm's selftest goes through
+/// this, so they are all held to the same invariants.
+fn sort_fault<T: Clone + Ord>(output: &[T], input: &[T], order: SortOrder) -> Option<&'static str> {
+    let ordered = match order {
+        SortOrder::Ascending => output.is_sorted(),
+        SortOrder::Descending => output.iter().rev().is_sorted(),
+    };
+    if output.len() != input.len() {
+        Some("result is not as long as the input")
+    } else if !is_permutation(output, input) {
+        Some("result is not a permutation of the input")
+    } else if !ordered {
+        Some("result is not sorted")
+    } else {
+        None
+    }
+}
+
+/// Lists the candidate GPUs in driver order, marking with `*` those a sort
+/// with `algorithm` runs on: the first, or for a multi-device sort the
+/// `max_devices` highest scoring, ties going to the earlier device.
+fn explain_device(algorithm: Algorithm, max_devices: Option<usize>) -> Result<String> {
+    Ok(explain_candidates(
+        &device_candidates()?,
+        algorithm,
+        max_devices,
+    ))
+}
+
+/// Formats [`explain_device`]'s listing of `candidates`.
+fn explain_candidates(
+    candidates: &[DeviceCandidate],
+    algorithm: Algorithm,
+    max_devices: Option<usize>,
+) -> String {
+    let mut ranked: Vec<usize> = (0..candidates.len()).collect();
+    let chosen = match algorithm {
+        Algorithm::MultiDevice => {
+            ranked.sort_by_key(|&index| Reverse(candidates[index].score));
+            max_devices.unwrap_or(usize::MAX).max(1)
+        }
+        _ => 1,
+    };
+    ranked.truncate(chosen);
+
+    let mut lines = vec!["Candidate devices:".to_string()];
+    for (index, candidate) in candidates.iter().enumerate() {
+        let mark = if ranked.contains(&index) { '*' } else { ' ' };
+        lines.push(format!("{} {}: {}", mark, index, candidate));
+    }
+    lines.join("\n")
+}
+
+fn device_info(device: &Device) -> Result<String> {
+    let vendor_id = device.vendor_id()?;
+    let device_type = device.dev_type()?;
+    Ok(format!(
+        "\tCL_DEVICE_VENDOR: {}\n\
+         \tCL_DEVICE_VENDOR_ID: {:X}, {}\n\
+         \tCL_DEVICE_NAME: {}\n\
+         \tCL_DEVICE_VERSION: {}\n\
+         \tCL_DEVICE_TYPE: {:X}, {}\n\
+         \tCL_DEVICE_PROFILE: {}\n\
+         \tCL_DEVICE_EXTENSIONS: {}\n\
+         \tCL_DEVICE_OPENCL_C_VERSION: {:?}\n\
+         \tCL_DEVICE_BUILT_IN_KERNELS: {}\n\
+         \tCL_DEVICE_SVM_CAPABILITIES: {:X}\n",
+        device.vendor()?,
+        vendor_id,
+        vendor_id_text(vendor_id),
+        device.name()?,
+        device.version()?,
+        device_type,
+        device_type_text(device_type),
+        device.profile()?,
+        device.extensions()?,
+        device.opencl_c_version()?,
+        device.built_in_kernels()?,
+        device.svm_mem_capability()
+    ))
+}
+
+fn merge_sort(vec: &[i32]) -> Vec<i32> {
     if vec.len() < 2 {
         vec.to_vec()
     } else {
         let size = vec.len() / 2;
-        let left = merge_sort(&vec[0..size].to_vec());
-        let right = merge_sort(&vec[size..].to_vec());
-        let merged = merge(&left, &right);
+        let left = merge_sort(&vec[0..size]);
+        let right = merge_sort(&vec[size..]);
 
-        merged
+        merge(&left, &right)
     }
 }
 
-fn merge(left: &Vec<i32>, right: &Vec<i32>) -> Vec<i32> {
+fn merge(left: &[i32], right: &[i32]) -> Vec<i32> {
     let mut i = 0;
     let mut j = 0;
     let mut merged: Vec<i32> = Vec::new();
@@ -260,26 +1857,458 @@ fn merge(left: &Vec<i32>, right: &Vec<i32>) -> Vec<i32> {
     while i < left.len() && j < right.len() {
         if left[i] < right[j] {
             merged.push(left[i]);
-            i = i + 1;
+            i += 1;
         } else {
             merged.push(right[j]);
-            j = j + 1;
+            j += 1;
         }
     }
 
     if i < left.len() {
         while i < left.len() {
             merged.push(left[i]);
-            i = i + 1;
+            i += 1;
         }
     }
 
     if j < right.len() {
         while j < right.len() {
             merged.push(right[j]);
-            j = j + 1;
+            j += 1;
         }
     }
 
     merged
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ocl_sort::fill_i32;
+
+    #[test]
+    fn float_lines_parse_back_to_the_same_values() {
+        let values = [
+            -1.5e300,
+            -0.0,
+            0.0,
+            0.1,
+            1.0 / 3.0,
+            f64::MIN_POSITIVE,
+            2.5e-320,
+        ];
+        let parsed = parse_floats(&float_lines(&values, None)).unwrap();
+        let bits: Vec<u64> = values.iter().map(|v| v.to_bits()).collect();
+        let parsed_bits: Vec<u64> = parsed.iter().map(|v| v.to_bits()).collect();
+        assert_eq!(parsed_bits, bits);
+    }
+
+    #[test]
+    fn float_lines_use_the_precision_and_special_names() {
+        let values = [f64::NEG_INFINITY, 1.0 / 3.0, f64::INFINITY, f64::NAN];
+        assert_eq!(float_lines(&values, Some(2)), "-inf\n0.33\ninf\nnan\n");
+        let parsed = parse_floats(&float_lines(&values, Some(2))).unwrap();
+        assert_eq!(parsed[..3], [f64::NEG_INFINITY, 0.33, f64::INFINITY]);
+        assert!(parsed[3].is_nan());
+    }
+
+    #[test]
+    fn head_and_tail_select_the_window_to_read_back() {
+        let args = Args::try_parse_from(["ocl-sort", "--head", "5"]).unwrap();
+        assert_eq!(output_window(args.head, args.tail, 100), 0..5);
+        assert_eq!(output_window(args.head, args.tail, 3), 0..3);
+        let args = Args::try_parse_from(["ocl-sort", "--tail", "5"]).unwrap();
+        assert_eq!(output_window(args.head, args.tail, 100), 95..100);
+        assert_eq!(output_window(args.head, args.tail, 3), 0..3);
+        assert_eq!(output_window(None, None, 100), 0..100);
+    }
+
+    #[test]
+    fn is_permutation_catches_corrupted_output() {
+        let input = [5, -3, 5, 0, 9, i32::MIN];
+        let sorted_input = sorted(&input);
+        assert!(is_permutation(&sorted_input, &input));
+        // Sorted, but a duplicate replaced a value.
+        assert!(!is_permutation(&[i32::MIN, -3, 0, 5, 9, 9], &input));
+        // Sorted, but a fill sentinel was left behind.
+        assert!(!is_permutation(&[-3, 0, 5, 5, 9, i32::MAX], &input));
+        // A value lost or one too many.
+        assert!(!is_permutation(&sorted_input[1..], &input));
+        assert!(!is_permutation(&[i32::MIN, -3, 0, 0, 5, 5, 9], &input));
+
+        assert_eq!(
+            sort_fault(&sorted_input, &input, SortOrder::Ascending),
+            None
+        );
+        assert_eq!(
+            sort_fault(&[i32::MIN, -3, 0, 5, 9, 9], &input, SortOrder::Ascending),
+            Some("result is not a permutation of the input")
+        );
+    }
+
+    #[test]
+    fn describe_runs_reports_the_first_run_apart_from_the_steady_state() {
+        let summary = describe_runs(&[9_000_000, 2_000, 4_000, 3_000]);
+        let mut lines = summary.lines();
+        assert_eq!(
+            lines.next(),
+            Some(
+                format!(
+                    "First run, including one-time setup: {}",
+                    format_duration(9_000_000)
+                )
+                .as_str()
+            )
+        );
+        assert_eq!(
+            lines.next(),
+            Some(
+                format!(
+                    "Steady state over 3 runs: mean {}, min {}",
+                    format_duration(3_000),
+                    format_duration(2_000)
+                )
+                .as_str()
+            )
+        );
+        assert!(describe_runs(&[5_000]).contains("Steady state: no warmed runs"));
+    }
+
+    #[test]
+    fn max_value_must_not_be_negative() {
+        assert!(Args::try_parse_from(["ocl-sort", "--max-value", "-1"]).is_err());
+        let args = Args::try_parse_from(["ocl-sort", "--max-value", "0"]).unwrap();
+        assert_eq!(args.max_value, Some(0));
+    }
+
+    #[test]
+    fn compare_kernels_is_a_bench_subcommand() {
+        let args =
+            Args::try_parse_from(["ocl-sort", "bench", "compare-kernels", "--runs", "2"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Bench {
+                command: Some(BenchCommand::CompareKernels {
+                    max_elements: 16384,
+                    runs: 2
+                }),
+                ..
+            })
+        ));
+        let args = Args::try_parse_from(["ocl-sort", "bench", "--runs", "3"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Bench {
+                command: None,
+                runs: 3,
+                ..
+            })
+        ));
+        assert!(
+            Args::try_parse_from(["ocl-sort", "bench", "--runs", "3", "compare-kernels"]).is_err()
+        );
+        assert!(Args::try_parse_from(["ocl-sort", "bench-compare-kernels"]).is_err());
+    }
+
+    const SAMPLE_CONFIG: &str = "
+# A sample experiment.
+size = 1_000
+max_value = 50 # inline comment
+seed = 42
+algorithm = \"radix\"
+output-format = \"varint\"
+verbose = true
+timing_only = false
+no_such_key = 3
+";
+
+    #[test]
+    fn parse_config_turns_keys_into_flags() {
+        let flags = parse_config(SAMPLE_CONFIG, Path::new("sample.toml")).unwrap();
+        assert_eq!(
+            flags,
+            [
+                "--size=1000",
+                "--max-value=50",
+                "--seed=42",
+                "--algorithm=radix",
+                "--output-format=varint",
+                "--verbose",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_config_rejects_malformed_lines() {
+        let path = Path::new("bad.toml");
+        for text in [
+            "[table]",
+            "size 10",
+            "algorithm = \"radix",
+            "seed = \"1\" 2",
+        ] {
+            assert!(matches!(
+                parse_config(text, path),
+                Err(SortError::InvalidInput(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn command_line_flags_override_the_config() {
+        let config = parse_config(SAMPLE_CONFIG, Path::new("sample.toml")).unwrap();
+        let argv = ["ocl-sort", "--size", "5", "--algorithm", "bitonic"].map(OsString::from);
+        let args = Args::try_parse_from(with_config(argv.to_vec(), config)).unwrap();
+        assert_eq!(args.size, Some(5));
+        assert!(matches!(args.algorithm, Algorithm::Bitonic));
+        assert_eq!(args.max_value, Some(50));
+        assert_eq!(args.seed, Some(42));
+        assert!(matches!(args.output_format, OutputFormat::Varint));
+        assert!(args.verbose);
+    }
+
+    #[test]
+    fn a_manifest_read_back_as_a_config_reproduces_the_run() {
+        let device = DeviceInfo {
+            name: "Device".to_string(),
+            vendor: "Vendor".to_string(),
+            version: "OpenCL 3.0".to_string(),
+            driver_version: "1.0".to_string(),
+        };
+        let argv = [
+            "ocl-sort",
+            "--size",
+            "100",
+            "--max-value",
+            "50",
+            "--seed",
+            "7",
+            "--algorithm",
+            "multi-device",
+            "--max-devices",
+            "2",
+        ]
+        .map(OsString::from);
+        let args = Args::try_parse_from(argv).unwrap();
+        let first = manifest(&args, &device, 7, 100, 50, None);
+
+        let path = Path::new("manifest.toml");
+        let config = parse_config(&first, path).unwrap();
+        let replayed =
+            Args::try_parse_from(with_config(vec![OsString::from("ocl-sort")], config)).unwrap();
+        assert_eq!(replayed.size, Some(100));
+        assert_eq!(replayed.max_value, Some(50));
+        assert_eq!(replayed.seed, Some(7));
+        assert!(matches!(replayed.algorithm, Algorithm::MultiDevice));
+        assert_eq!(replayed.max_devices, Some(2));
+        assert_eq!(manifest(&replayed, &device, 7, 100, 50, None), first);
+    }
+
+    #[test]
+    fn regressions_flag_sizes_slower_than_the_threshold() {
+        let baseline = [(1024, 1000), (4096, 4000), (16384, 16000)];
+        let current = [(1024, 1150), (4096, 4601), (16384, 12000)];
+        // 15% over 1000 is exactly the threshold, so only 4096 regressed.
+        assert_eq!(regressions(&baseline, &current, 15.0), [(4096, 4000, 4601)]);
+        assert_eq!(
+            regressions(&baseline, &current, 10.0),
+            [(1024, 1000, 1150), (4096, 4000, 4601)]
+        );
+        assert!(regressions(&baseline, &current, 20.0).is_empty());
+    }
+
+    #[test]
+    fn regressions_skip_sizes_missing_from_the_baseline() {
+        let baseline = [(1024, 1000)];
+        let current = [(1024, 900), (65536, 1_000_000)];
+        assert!(regressions(&baseline, &current, 0.0).is_empty());
+        assert!(regressions(&[], &current, 0.0).is_empty());
+    }
+
+    #[test]
+    fn parse_bench_csv_reads_what_bench_prints() {
+        let csv = format!("{}\n1024,1500\n 4096,6200 \n\n", BENCH_HEADER);
+        assert_eq!(parse_bench_csv(&csv).unwrap(), [(1024, 1500), (4096, 6200)]);
+        for bad in ["1024", "1024,fast", "-1,5"] {
+            assert!(matches!(
+                parse_bench_csv(bad),
+                Err(SortError::InvalidInput(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn prompt_value_asks_again_for_a_negative_answer() {
+        let mut answers = "-5\n7\n".as_bytes();
+        assert_eq!(prompt_value("max?", true, &mut answers).unwrap(), 7);
+        let mut answers = "-1\n-2\n-3\n".as_bytes();
+        assert!(prompt_value("max?", true, &mut answers).is_err());
+    }
+
+    #[test]
+    fn size_in_range_accepts_sizes_up_to_the_max() {
+        assert_eq!(size_in_range(1, 10).unwrap(), 1);
+        assert_eq!(size_in_range(10, 10).unwrap(), 10);
+    }
+
+    #[test]
+    fn size_in_range_rejects_empty_negative_and_oversized_counts() {
+        for size in [0, -1, i32::MIN, 11] {
+            assert!(matches!(
+                size_in_range(size, 10),
+                Err(SortError::InvalidSize { max: 10, .. })
+            ));
+        }
+    }
+
+    fn candidate(name: &str, score: u64) -> DeviceCandidate {
+        DeviceCandidate {
+            info: DeviceInfo {
+                name: name.to_string(),
+                vendor: "Vendor".to_string(),
+                version: "OpenCL 3.0".to_string(),
+                driver_version: "1.0".to_string(),
+            },
+            compute_units: score as u32,
+            clock_mhz: 1,
+            global_mem_bytes: 1 << 30,
+            integrated: false,
+            score,
+        }
+    }
+
+    #[test]
+    fn explain_candidates_marks_the_chosen_devices() {
+        let candidates = [candidate("a", 10), candidate("b", 40), candidate("c", 40)];
+        let marked = |algorithm, max_devices| -> Vec<String> {
+            let listing = explain_candidates(&candidates, algorithm, max_devices);
+            let lines: Vec<&str> = listing.lines().collect();
+            assert_eq!(lines[0], "Candidate devices:");
+            assert_eq!(lines.len(), candidates.len() + 1, "{}", listing);
+            lines[1..]
+                .iter()
+                .filter(|line| line.starts_with('*'))
+                .map(|line| line[2..].to_string())
+                .collect()
+        };
+        // A single-device sort runs on the first in driver order...
+        assert_eq!(
+            marked(Algorithm::Rank, None),
+            ["0: a (Vendor): score 10 = 10 compute units x 1 MHz, discrete, 1024 MiB"]
+        );
+        assert_eq!(marked(Algorithm::Rank, Some(3)).len(), 1);
+        // ...and a multi-device one on the highest scoring, ties going to
+        // the earlier device.
+        let best = marked(Algorithm::MultiDevice, Some(1));
+        assert_eq!(best.len(), 1);
+        assert!(best[0].starts_with("1: b "), "{:?}", best);
+        assert_eq!(marked(Algorithm::MultiDevice, Some(0)).len(), 1);
+        assert_eq!(marked(Algorithm::MultiDevice, Some(2)).len(), 2);
+        assert_eq!(marked(Algorithm::MultiDevice, None).len(), 3);
+    }
+
+    #[test]
+    fn sorted_lines_orders_a_csv_by_its_second_column() {
+        let sorter = match GpuSorter::new() {
+            Ok(sorter) => sorter,
+            Err(SortError::NoOpenCl | SortError::NoDevice) => {
+                eprintln!("skipping: no OpenCL GPU to test on");
+                return;
+            }
+            Err(e) => panic!("failed to create a sorter: {}", e),
+        };
+        let csv = "kiwi,10\npear,2\nplum, 10\nfig,-4\nlime,10\n";
+        // The three 10s keep their input order.
+        assert_eq!(
+            sorted_lines(&sorter, csv, 1, ',', 10).unwrap(),
+            ["fig,-4", "pear,2", "kiwi,10", "plum, 10", "lime,10"]
+        );
+        assert_eq!(
+            sorted_lines(&sorter, csv, 0, ',', 36).unwrap(),
+            ["fig,-4", "kiwi,10", "lime,10", "pear,2", "plum, 10"]
+        );
+        assert!(sorted_lines(&sorter, csv, 2, ',', 10).is_err());
+    }
+
+    #[test]
+    fn a_saved_corpus_loads_back_the_same_inputs() {
+        let dir = env::temp_dir().join(format!("ocl-sort-corpus-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let save = Corpus::Save(dir.clone());
+        let load = Corpus::Load(dir.clone());
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let saved: Vec<Vec<i32>> = [1024, 4096]
+            .iter()
+            .map(|&elements| corpus_input(Some(&save), elements, &mut rng).unwrap())
+            .collect();
+        assert_eq!(saved[0].len(), 1024);
+        assert_eq!(saved[1].len(), 4096);
+        // Loading ignores the generator, so a fresh one changes nothing.
+        let mut rng = StdRng::seed_from_u64(1);
+        for input in &saved {
+            assert_eq!(
+                &corpus_input(Some(&load), input.len(), &mut rng).unwrap(),
+                input
+            );
+        }
+        // A size that wasn't saved, or a file of the wrong size, is an
+        // error.
+        assert!(corpus_input(Some(&load), 16384, &mut rng).is_err());
+        fs::copy(dir.join("1024.bin"), dir.join("16384.bin")).unwrap();
+        assert!(matches!(
+            corpus_input(Some(&load), 16384, &mut rng),
+            Err(SortError::InvalidInput(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn trace_csv_has_a_row_per_phase() {
+        assert_eq!(trace_csv(&[]).unwrap(), format!("{}\n", TRACE_HEADER));
+
+        let sorter = match GpuSorter::new() {
+            Ok(sorter) => sorter,
+            Err(SortError::NoOpenCl | SortError::NoDevice) => {
+                eprintln!("skipping: no OpenCL GPU to test on");
+                return;
+            }
+            Err(e) => panic!("failed to create a sorter: {}", e),
+        };
+        let input: Vec<i32> = (0..1000).collect();
+        let queue = sorter.queue();
+        let mut buffer = Buffer::<cl_int>::create(
+            sorter.context(),
+            CL_MEM_READ_WRITE,
+            input.len(),
+            ptr::null_mut(),
+        )
+        .unwrap();
+        let write_event = write_i32(queue, &mut buffer, "trace", &input).unwrap();
+        let fill_event = fill_i32(queue, &mut buffer, 0, input.len()).unwrap();
+        let mut values = vec![1; input.len()];
+        let read_event = queue
+            .enqueue_read_buffer(&buffer, CL_BLOCKING, 0, &mut values, &[fill_event.get()])
+            .unwrap();
+        assert_eq!(values, vec![0; input.len()]);
+
+        let csv = trace_csv(&[
+            ("write", &write_event),
+            ("fill", &fill_event),
+            ("read", &read_event),
+        ])
+        .unwrap();
+        let mut rows = csv.lines();
+        assert_eq!(rows.next(), Some(TRACE_HEADER));
+        let rows: Vec<Vec<&str>> = rows.map(|row| row.split(',').collect()).collect();
+        assert_eq!(rows.len(), 3, "{}", csv);
+        for (row, phase) in rows.iter().zip(["write", "fill", "read"]) {
+            assert_eq!(row.len(), 5, "{}", csv);
+            assert_eq!(row[0], phase);
+            let stamps: Vec<u64> = row[1..].iter().map(|t| t.parse().unwrap()).collect();
+            assert!(stamps.is_sorted(), "{}", csv);
+        }
+        assert_eq!(rows[0][1], "0");
+    }
+}
@@ -0,0 +1,86 @@
+use crate::buffer::upload;
+use crate::error::Result;
+use crate::gpu_vec::GpuVec;
+use crate::launch::SortKernel;
+use crate::sorter::GpuSorter;
+use opencl3::memory::{Buffer, CL_MEM_READ_WRITE};
+use opencl3::types::{cl_int, cl_uint};
+use std::mem;
+use std::ptr;
+
+impl GpuSorter {
+    /// Merges sorted `runs` into one sorted vector in a single launch.
+    ///
+    /// The runs are copied end to end on the device; then each element finds
+    /// its output position by binary searching every other run for how many
+    /// of its elements go before it. Equal elements keep the order of their
+    /// runs, so the merge is stable.
+    pub fn kway_merge(&self, runs: &[GpuVec<i32>]) -> Result<GpuVec<i32>> {
+        let n: usize = runs.iter().map(GpuVec::len).sum();
+        let mut starts = Vec::with_capacity(runs.len() + 1);
+        starts.push(0);
+        for run in runs {
+            starts.push(starts[starts.len() - 1] + run.len() as cl_uint);
+        }
+        if n == 0 {
+            return self.to_device(&[]);
+        }
+
+        let mut source =
+            Buffer::<cl_int>::create(&self.context, CL_MEM_READ_WRITE, n, ptr::null_mut())?;
+        let mut copy_events = Vec::with_capacity(runs.len());
+        for (run, &start) in runs.iter().zip(&starts) {
+            if !run.is_empty() {
+                copy_events.push(self.queue.enqueue_copy_buffer(
+                    run.buffer(),
+                    &mut source,
+                    0,
+                    start as usize * mem::size_of::<cl_int>(),
+                    run.len() * mem::size_of::<cl_int>(),
+                    &[],
+                )?);
+            }
+        }
+
+        let start_buffer = upload(&self.context, &starts)?;
+        let results =
+            Buffer::<cl_int>::create(&self.context, CL_MEM_READ_WRITE, n, ptr::null_mut())?;
+
+        let mut merge = SortKernel::new(&self.kway_merge_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&source)?
+            .buffer_of(&start_buffer, starts.len())?
+            .scalar(&(runs.len() as cl_uint))
+            .buffer(&results)?;
+        for event in &copy_events {
+            merge = merge.wait(event);
+        }
+        merge.enqueue(&self.queue)?.wait()?;
+
+        Ok(GpuVec::from_buffer(results, n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stable::SortOrder;
+    use crate::test_util::{assert_valid_sort, pseudo_random, test_sorter};
+
+    #[test]
+    fn kway_merge_merges_random_runs() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let mut input = Vec::new();
+        let mut runs = Vec::new();
+        for (seed, n) in [0, 1, 250, 17, 600].into_iter().enumerate() {
+            let mut run = pseudo_random(n, seed as u64);
+            input.extend_from_slice(&run);
+            run.sort_unstable();
+            runs.push(sorter.to_device(&run).unwrap());
+        }
+        let output = sorter.to_host(&sorter.kway_merge(&runs).unwrap()).unwrap();
+        assert_valid_sort(&input, &output, SortOrder::Ascending);
+    }
+}
@@ -0,0 +1,225 @@
+use crate::error::Result;
+use crate::sorter::{check_window, GpuSorter};
+use opencl3::device::Device;
+use opencl3::memory::CL_MEM_READ_WRITE;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::ops::Range;
+
+/// A rough measure of how fast `device` sorts, used to rank devices: its
+/// compute units times its clock frequency in MHz.
+pub(crate) fn device_capability(device: &Device) -> Result<u64> {
+    Ok(u64::from(device.max_compute_units()?) * u64::from(device.max_clock_frequency()?))
+}
+
+/// Sorts across several GPUs, each with its own [`GpuSorter`]. Built with
+/// [`GpuSorterBuilder::build_multi`](crate::GpuSorterBuilder::build_multi),
+/// most capable device first.
+pub struct MultiSorter {
+    sorters: Vec<GpuSorter>,
+}
+
+impl MultiSorter {
+    pub(crate) fn new(sorters: Vec<GpuSorter>) -> Self {
+        Self { sorters }
+    }
+
+    /// The per-device sorters, most capable first.
+    pub fn sorters(&self) -> &[GpuSorter] {
+        &self.sorters
+    }
+
+    /// Sorts `input` by splitting it into one contiguous part per device,
+    /// radix sorting every part and merging the sorted parts on the host.
+    /// All of the parts are enqueued before any is read back, so the devices
+    /// sort at the same time. With one device this is a plain radix sort.
+    pub fn sort(&self, input: &[i32]) -> Result<Vec<i32>> {
+        self.sort_window(input, 0..input.len())
+    }
+
+    /// Like [`MultiSorter::sort`], but returns only `window` of the sorted
+    /// result. Only the [`RunWindow`] of each device's sorted part that the
+    /// window can come from is read back and merged.
+    ///
+    /// Returns [`SortError::InvalidRange`](crate::SortError::InvalidRange)
+    /// if `window` doesn't fit in `input`.
+    pub fn sort_window(&self, input: &[i32], window: Range<usize>) -> Result<Vec<i32>> {
+        check_window(&window, input.len())?;
+        let span = RunWindow::new(&window, input.len());
+        let part_len = input.len().div_ceil(self.sorters.len()).max(1);
+        let parts: Vec<&[i32]> = input.chunks(part_len).collect();
+
+        let mut pending = Vec::with_capacity(parts.len());
+        for (sorter, part) in self.sorters.iter().zip(&parts) {
+            pending.push(if part.len() < 2 {
+                None
+            } else {
+                Some(sorter.radix_sort_buffer(part, None)?)
+            });
+        }
+
+        let mut runs = Vec::with_capacity(parts.len());
+        for ((sorter, part), pending) in self.sorters.iter().zip(&parts).zip(pending) {
+            let range = span.of(part.len());
+            runs.push(match pending {
+                None => part[range].to_vec(),
+                Some((sorted, event)) => {
+                    let mut run = vec![0; range.len()];
+                    sorter.read_result(&sorted, range.start, &mut run, &event)?;
+                    sorter.pool.give(part.len(), CL_MEM_READ_WRITE, sorted);
+                    run
+                }
+            });
+        }
+        Ok(span.merge(&runs, window))
+    }
+}
+
+/// The part of each of several sorted runs, `len` elements in all, that a
+/// window of their merge can come from: the first `window.end` elements of
+/// every run, or the last `len - window.start`, whichever is fewer. Reading
+/// only that much of each run is enough to merge the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct RunWindow {
+    from_end: bool,
+    keep: usize,
+    len: usize,
+}
+
+impl RunWindow {
+    /// The run window for `window` of a merge of `len` elements.
+    pub(crate) fn new(window: &Range<usize>, len: usize) -> Self {
+        let tail = len - window.start;
+        RunWindow {
+            from_end: tail < window.end,
+            keep: tail.min(window.end),
+            len,
+        }
+    }
+
+    /// The range of a sorted run of `run_len` elements to keep.
+    pub(crate) fn of(&self, run_len: usize) -> Range<usize> {
+        let keep = self.keep.min(run_len);
+        if self.from_end {
+            run_len - keep..run_len
+        } else {
+            0..keep
+        }
+    }
+
+    /// Merges the kept part of every run and returns `window` of the merge
+    /// of the whole runs, which must be the window this was made for.
+    pub(crate) fn merge(&self, runs: &[Vec<i32>], window: Range<usize>) -> Vec<i32> {
+        let merged = merge_runs(runs);
+        if self.from_end {
+            // Only the last `keep` elements of the merge are the last `keep`
+            // of the whole, and the window lies within them.
+            let start = window.start + merged.len() - self.len;
+            merged[start..start + window.len()].to_vec()
+        } else {
+            merged[window].to_vec()
+        }
+    }
+}
+
+/// Merges sorted `runs` with a min-heap of each run's next element.
+pub(crate) fn merge_runs(runs: &[Vec<i32>]) -> Vec<i32> {
+    let mut merged = Vec::with_capacity(runs.iter().map(Vec::len).sum());
+    let mut heads: BinaryHeap<Reverse<(i32, usize, usize)>> = runs
+        .iter()
+        .enumerate()
+        .filter(|(_, run)| !run.is_empty())
+        .map(|(run, values)| Reverse((values[0], run, 0)))
+        .collect();
+    while let Some(Reverse((value, run, index))) = heads.pop() {
+        merged.push(value);
+        if let Some(&next) = runs[run].get(index + 1) {
+            heads.push(Reverse((next, run, index + 1)));
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::SortError;
+    use crate::stable::SortOrder;
+    use crate::test_util::{assert_valid_sort, pseudo_random};
+
+    #[test]
+    fn merge_runs_interleaves_sorted_runs() {
+        let runs = [vec![1, 4, 9], vec![], vec![2, 4, 10, 11], vec![-3]];
+        assert_eq!(merge_runs(&runs), vec![-3, 1, 2, 4, 4, 9, 10, 11]);
+        assert!(merge_runs(&[]).is_empty());
+    }
+
+    #[test]
+    fn run_windows_merge_to_the_window_of_the_whole() {
+        let runs = [vec![1, 4, 9], vec![], vec![2, 4, 10, 11], vec![-3]];
+        let whole = merge_runs(&runs);
+        let len = whole.len();
+        for start in 0..=len {
+            for end in start..=len {
+                let span = RunWindow::new(&(start..end), len);
+                let kept: Vec<Vec<i32>> = runs
+                    .iter()
+                    .map(|run| run[span.of(run.len())].to_vec())
+                    .collect();
+                assert_eq!(span.merge(&kept, start..end), whole[start..end]);
+            }
+        }
+    }
+
+    #[test]
+    fn run_windows_keep_only_the_head_or_tail() {
+        // A head of 2 needs at most 2 elements of each run...
+        let head = RunWindow::new(&(0..2), 100);
+        assert_eq!(head.of(50), 0..2);
+        assert_eq!(head.of(1), 0..1);
+        // ...and a tail of 3 at most the last 3.
+        let tail = RunWindow::new(&(97..100), 100);
+        assert_eq!(tail.of(50), 47..50);
+        assert_eq!(tail.of(2), 0..2);
+        assert_eq!(RunWindow::new(&(0..0), 100).of(50), 0..0);
+    }
+
+    #[test]
+    fn multi_device_windows_match_the_full_sort() {
+        let sorter = match GpuSorter::builder().build_multi() {
+            Ok(sorter) => sorter,
+            Err(SortError::NoOpenCl | SortError::NoDevice) => {
+                eprintln!("skipping: no OpenCL GPU to test on");
+                return;
+            }
+            Err(e) => panic!("failed to create a sorter: {}", e),
+        };
+        let input = pseudo_random(1001, 5);
+        let sorted = sorter.sort(&input).unwrap();
+        assert_valid_sort(&input, &sorted, SortOrder::Ascending);
+        for window in [0..5, 996..1001, 400..600, 0..0] {
+            assert_eq!(
+                sorter.sort_window(&input, window.clone()).unwrap(),
+                sorted[window]
+            );
+        }
+    }
+
+    #[test]
+    fn a_cap_of_one_device_sorts_like_a_single_sorter() {
+        let sorter = match GpuSorter::builder().max_devices(1).build_multi() {
+            Ok(sorter) => sorter,
+            Err(SortError::NoOpenCl | SortError::NoDevice) => {
+                eprintln!("skipping: no OpenCL GPU to test on");
+                return;
+            }
+            Err(e) => panic!("failed to create a sorter: {}", e),
+        };
+        assert_eq!(sorter.sorters().len(), 1);
+        let input = pseudo_random(1001, 6);
+        let single = sorter.sorters()[0].sort(&input).unwrap();
+        assert_valid_sort(&input, &single, SortOrder::Ascending);
+        assert_eq!(sorter.sort(&input).unwrap(), single);
+        assert_eq!(sorter.sort_window(&input, 10..20).unwrap(), single[10..20]);
+    }
+}
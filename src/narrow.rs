@@ -0,0 +1,131 @@
+use crate::buffer::{upload, SizedBuffer};
+use crate::error::Result;
+use crate::launch::SortKernel;
+use crate::sorter::GpuSorter;
+use opencl3::memory::{CL_MEM_READ_WRITE, CL_MEM_WRITE_ONLY};
+use opencl3::types::{cl_short, cl_uchar};
+
+/// Number of distinct `u8` values, and so the bin count of the counting sort.
+const U8_BINS: usize = 256;
+
+impl GpuSorter {
+    /// Sorts bytes with a counting sort: a histogram of the 256 possible
+    /// values followed by one work-item per value writing its run.
+    pub fn sort_u8(&self, input: &[u8]) -> Result<Vec<u8>> {
+        let n = input.len();
+        if n < 2 {
+            return Ok(input.to_vec());
+        }
+
+        let input_buffer = upload(&self.context, input)?;
+        let mut bin_buffer = self
+            .pool
+            .take_sized(&self.context, U8_BINS, CL_MEM_READ_WRITE)?;
+        let result_buffer = SizedBuffer::<cl_uchar>::create(&self.context, CL_MEM_WRITE_ONLY, n)?;
+
+        let bin_fill_event = bin_buffer.fill(&self.queue, 0)?;
+
+        let histogram_kernel_event = SortKernel::new(&self.histogram_u8_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&input_buffer)?
+            .buffer_of(bin_buffer.buffer(), U8_BINS)?
+            .wait(&bin_fill_event)
+            .enqueue(&self.queue)?;
+
+        let expand_kernel_event = SortKernel::new(&self.expand_u8_kernel, U8_BINS)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(bin_buffer.buffer())?
+            .buffer_of(result_buffer.buffer(), n)?
+            .wait(&histogram_kernel_event)
+            .enqueue(&self.queue)?;
+
+        let result = self.read_result_range(
+            result_buffer.buffer(),
+            0,
+            result_buffer.len(),
+            &expand_kernel_event,
+        )?;
+
+        self.pool.give_sized(CL_MEM_READ_WRITE, bin_buffer);
+        Ok(result)
+    }
+
+    /// Sorts 16-bit integers with the rank sort, transferring them as
+    /// `short`s and keeping the ranks as `int`s.
+    pub fn sort_i16(&self, input: &[i16]) -> Result<Vec<i16>> {
+        let n = input.len();
+        if n < 2 {
+            return Ok(input.to_vec());
+        }
+
+        let input_buffer = upload(&self.context, input)?;
+        let mut rank_buffer = self.pool.take_sized(&self.context, n, CL_MEM_READ_WRITE)?;
+        let result_buffer = SizedBuffer::<cl_short>::create(&self.context, CL_MEM_WRITE_ONLY, n)?;
+
+        let rank_fill_event = rank_buffer.fill(&self.queue, 0)?;
+
+        let rank_kernel_event = SortKernel::pairs(&self.rank_i16_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&input_buffer)?
+            .buffer(rank_buffer.buffer())?
+            .wait(&rank_fill_event)
+            .enqueue(&self.queue)?;
+
+        let scatter_kernel_event = SortKernel::new(&self.scatter_i16_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&input_buffer)?
+            .buffer(rank_buffer.buffer())?
+            .buffer(result_buffer.buffer())?
+            .wait(&rank_kernel_event)
+            .enqueue(&self.queue)?;
+
+        let result = self.read_result_range(
+            result_buffer.buffer(),
+            0,
+            result_buffer.len(),
+            &scatter_kernel_event,
+        )?;
+
+        self.pool.give_sized(CL_MEM_READ_WRITE, rank_buffer);
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{pseudo_random, test_sorter};
+
+    #[test]
+    fn sort_u8_keeps_the_saturated_bins() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let mut input: Vec<u8> = pseudo_random(1000, 47).iter().map(|&v| v as u8).collect();
+        input.extend([255, 0, 255, 0, 0]);
+        let mut expected = input.clone();
+        expected.sort_unstable();
+        assert_eq!(sorter.sort_u8(&input).unwrap(), expected);
+        assert_eq!(sorter.sort_u8(&[255, 0]).unwrap(), [0, 255]);
+        assert_eq!(sorter.sort_u8(&[255; 300]).unwrap(), [255; 300]);
+    }
+
+    #[test]
+    fn sort_i16_keeps_the_extremes() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let mut input: Vec<i16> = pseudo_random(1000, 53).iter().map(|&v| v as i16).collect();
+        input.extend([i16::MAX, i16::MIN, 0, i16::MIN, i16::MAX, -1]);
+        let mut expected = input.clone();
+        expected.sort_unstable();
+        assert_eq!(sorter.sort_i16(&input).unwrap(), expected);
+        assert_eq!(
+            sorter.sort_i16(&[i16::MAX, i16::MIN]).unwrap(),
+            [i16::MIN, i16::MAX]
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Sorting networks as lists of compare-exchange stages, for inspecting the
+//! wiring a network sort uses.
+
+use crate::error::{Result, SortError};
+use std::fmt::Write;
+
+/// A comparator between two wires that leaves the smaller value on `low`
+/// and the larger on `high`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompareExchange {
+    pub low: usize,
+    pub high: usize,
+}
+
+/// The stages of the bitonic sorting network for `n` wires, each a set of
+/// comparators on disjoint wires that can run in parallel. `n` must be a
+/// power of two.
+pub fn bitonic_network(n: usize) -> Result<Vec<Vec<CompareExchange>>> {
+    if !n.is_power_of_two() {
+        return Err(SortError::InvalidInput(format!(
+            "a bitonic network needs a power-of-two size, got {}",
+            n
+        )));
+    }
+
+    let mut stages = Vec::new();
+    let mut k = 2;
+    while k <= n {
+        let mut j = k / 2;
+        while j > 0 {
+            stages.push(
+                (0..n)
+                    .filter(|&i| i ^ j > i)
+                    .map(|i| {
+                        let partner = i ^ j;
+                        if i & k == 0 {
+                            CompareExchange {
+                                low: i,
+                                high: partner,
+                            }
+                        } else {
+                            CompareExchange {
+                                low: partner,
+                                high: i,
+                            }
+                        }
+                    })
+                    .collect(),
+            );
+            j /= 2;
+        }
+        k *= 2;
+    }
+    Ok(stages)
+}
+
+/// Renders a network on `n` wires as a Graphviz DOT graph drawn left to
+/// right: each wire is a row of points, one per stage boundary, and each
+/// comparator a red edge pointing at the wire that receives the larger value.
+pub fn network_dot(n: usize, stages: &[Vec<CompareExchange>]) -> String {
+    let mut dot = String::new();
+    writeln!(dot, "digraph network {{").unwrap();
+    writeln!(dot, "    rankdir=LR;").unwrap();
+    writeln!(dot, "    node [shape=point];").unwrap();
+    for column in 0..=stages.len() {
+        let points: Vec<String> = (0..n).map(|wire| format!("w{}_{}", wire, column)).collect();
+        writeln!(dot, "    {{ rank=same; {}; }}", points.join("; ")).unwrap();
+    }
+    for wire in 0..n {
+        for column in 0..stages.len() {
+            writeln!(
+                dot,
+                "    w{}_{} -> w{}_{} [arrowhead=none];",
+                wire,
+                column,
+                wire,
+                column + 1
+            )
+            .unwrap();
+        }
+    }
+    for (stage, comparators) in stages.iter().enumerate() {
+        for comparator in comparators {
+            writeln!(
+                dot,
+                "    w{}_{} -> w{}_{} [color=red, constraint=false];",
+                comparator.low,
+                stage + 1,
+                comparator.high,
+                stage + 1
+            )
+            .unwrap();
+        }
+    }
+    writeln!(dot, "}}").unwrap();
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitonic_network_sorts_every_zero_one_input() {
+        let stages = bitonic_network(8).unwrap();
+        assert_eq!(stages.len(), 6);
+        for stage in &stages {
+            assert_eq!(stage.len(), 4);
+            let mut wires: Vec<usize> = stage.iter().flat_map(|c| [c.low, c.high]).collect();
+            wires.sort_unstable();
+            assert_eq!(wires, (0..8).collect::<Vec<_>>(), "a wire is used twice");
+        }
+        // By the zero-one principle, sorting every 0/1 input sorts any input.
+        for bits in 0u32..256 {
+            let mut wires: Vec<u32> = (0..8).map(|wire| bits >> wire & 1).collect();
+            for comparator in stages.iter().flatten() {
+                if wires[comparator.low] > wires[comparator.high] {
+                    wires.swap(comparator.low, comparator.high);
+                }
+            }
+            assert!(wires.is_sorted(), "{:08b} sorted to {:?}", bits, wires);
+        }
+    }
+
+    #[test]
+    fn bitonic_network_needs_a_power_of_two() {
+        assert!(matches!(
+            bitonic_network(6),
+            Err(SortError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            bitonic_network(0),
+            Err(SortError::InvalidInput(_))
+        ));
+        assert_eq!(bitonic_network(1).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn network_dot_draws_every_wire_and_comparator() {
+        let stages = bitonic_network(8).unwrap();
+        let dot = network_dot(8, &stages);
+        let lines: Vec<&str> = dot.lines().collect();
+        assert_eq!(lines.first(), Some(&"digraph network {"));
+        assert_eq!(lines.last(), Some(&"}"));
+        assert_eq!(dot.matches('{').count(), dot.matches('}').count());
+
+        let ranks = lines
+            .iter()
+            .filter(|line| line.contains("rank=same"))
+            .count();
+        let segments = lines
+            .iter()
+            .filter(|line| line.contains("arrowhead=none"))
+            .count();
+        let comparators: Vec<&str> = lines
+            .iter()
+            .copied()
+            .filter(|line| line.contains("color=red"))
+            .collect();
+        assert_eq!(ranks, stages.len() + 1);
+        assert_eq!(segments, 8 * stages.len());
+        assert_eq!(comparators.len(), 24);
+        // The first stage compares neighbours into ascending pairs.
+        assert!(comparators.contains(&"    w0_1 -> w1_1 [color=red, constraint=false];"));
+        assert!(comparators.contains(&"    w3_1 -> w2_1 [color=red, constraint=false];"));
+        // Every statement is terminated, rank groups by their closing brace.
+        assert!(lines[1..lines.len() - 1]
+            .iter()
+            .all(|line| line.ends_with(';') || line.ends_with("; }")));
+    }
+}
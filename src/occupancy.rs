@@ -0,0 +1,120 @@
+use crate::error::Result;
+use opencl3::device::Device;
+use opencl3::kernel::Kernel;
+use std::fmt;
+
+/// How large a work-group a compiled kernel can run in on a device, and the
+/// resources that bound it.
+///
+/// A kernel whose `work_group_size` falls short of the device's maximum is
+/// held back by the registers or local memory each work-item needs, which
+/// caps the work-items resident per compute unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KernelOccupancy {
+    /// `CL_KERNEL_WORK_GROUP_SIZE`: the largest work-group the kernel can be
+    /// launched with.
+    pub work_group_size: usize,
+    /// `CL_KERNEL_PREFERRED_WORK_GROUP_SIZE_MULTIPLE`: the SIMD width that
+    /// work-group sizes should be a multiple of.
+    pub preferred_multiple: usize,
+    /// `CL_KERNEL_LOCAL_MEM_SIZE`: local memory per work-group, in bytes.
+    pub local_mem_bytes: u64,
+    /// `CL_KERNEL_PRIVATE_MEM_SIZE`: private memory per work-item, in bytes.
+    pub private_mem_bytes: u64,
+    /// `CL_DEVICE_MAX_WORK_GROUP_SIZE`.
+    pub device_max_work_group_size: usize,
+}
+
+impl KernelOccupancy {
+    /// Queries `kernel`'s work-group limits on `device`, which it must have
+    /// been built for.
+    pub fn query(kernel: &Kernel, device: &Device) -> Result<Self> {
+        let id = device.id();
+        Ok(KernelOccupancy {
+            work_group_size: kernel.get_work_group_size(id)?,
+            preferred_multiple: kernel.get_work_group_size_multiple(id)?,
+            local_mem_bytes: kernel.get_local_mem_size(id)?,
+            private_mem_bytes: kernel.get_private_mem_size(id)?,
+            device_max_work_group_size: device.max_work_group_size()?,
+        })
+    }
+
+    /// The kernel's largest work-group as a fraction of the device's, 1.0
+    /// when nothing but the device limits it.
+    pub fn occupancy(&self) -> f64 {
+        self.work_group_size as f64 / self.device_max_work_group_size.max(1) as f64
+    }
+}
+
+impl fmt::Display for KernelOccupancy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "work-group size {} of {} ({:.0}%), preferred multiple {}, \
+             {} B local memory, {} B private memory",
+            self.work_group_size,
+            self.device_max_work_group_size,
+            self.occupancy() * 100.0,
+            self.preferred_multiple,
+            self.local_mem_bytes,
+            self.private_mem_bytes
+        )?;
+        if self.work_group_size < self.device_max_work_group_size {
+            if self.local_mem_bytes > 0 {
+                write!(f, ", bound by local memory or registers")?;
+            } else {
+                write!(f, ", bound by registers")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_sorter;
+
+    #[test]
+    fn rank_kernels_report_nonzero_occupancy() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let (compare, assign) = sorter.rank_occupancy().unwrap();
+        for occupancy in [compare, assign] {
+            assert!(occupancy.work_group_size > 0, "{}", occupancy);
+            assert!(occupancy.preferred_multiple > 0, "{}", occupancy);
+            assert!(occupancy.device_max_work_group_size >= occupancy.work_group_size);
+            assert!(occupancy.occupancy() > 0.0 && occupancy.occupancy() <= 1.0);
+        }
+    }
+
+    #[test]
+    fn occupancy_names_what_bounds_the_work_group() {
+        let full = KernelOccupancy {
+            work_group_size: 256,
+            preferred_multiple: 32,
+            local_mem_bytes: 0,
+            private_mem_bytes: 16,
+            device_max_work_group_size: 256,
+        };
+        assert_eq!(full.occupancy(), 1.0);
+        assert_eq!(
+            full.to_string(),
+            "work-group size 256 of 256 (100%), preferred multiple 32, \
+             0 B local memory, 16 B private memory"
+        );
+        let tiled = KernelOccupancy {
+            work_group_size: 64,
+            local_mem_bytes: 1024,
+            ..full
+        };
+        assert_eq!(tiled.occupancy(), 0.25);
+        assert!(tiled.to_string().ends_with("(25%), preferred multiple 32, 1024 B local memory, 16 B private memory, bound by local memory or registers"));
+        let registers = KernelOccupancy {
+            work_group_size: 128,
+            ..full
+        };
+        assert!(registers.to_string().ends_with(", bound by registers"));
+    }
+}
@@ -0,0 +1,128 @@
+use crate::error::Result;
+use crate::launch::SortKernel;
+use crate::sorter::GpuSorter;
+use opencl3::memory::CL_MEM_READ_WRITE;
+use opencl3::types::cl_uint;
+
+impl GpuSorter {
+    /// Sorts `input` with odd-even transposition: alternating passes
+    /// compare-exchange the even and then the odd adjacent pairs.
+    ///
+    /// After each even and odd round the host reads back a flag the kernel
+    /// sets on any swap, stopping as soon as a round makes none. Sorted input
+    /// finishes after two passes, while reversed input, the worst case, runs
+    /// all `n` rounded up to an even number.
+    ///
+    /// If given, `progress` is called on the host after each even and odd
+    /// round has been read back with the number of rounds done and the most
+    /// there can be, `n / 2` rounded up. It isn't called for the rounds an
+    /// early stop skips.
+    pub fn sort_odd_even(
+        &self,
+        input: &[i32],
+        progress: Option<&dyn Fn(usize, usize)>,
+    ) -> Result<Vec<i32>> {
+        let n = input.len();
+        if n < 2 {
+            return Ok(input.to_vec());
+        }
+
+        let mut data = self.pool.take_sized(&self.context, n, CL_MEM_READ_WRITE)?;
+        let mut swapped = self.pool.take_sized(&self.context, 1, CL_MEM_READ_WRITE)?;
+        let mut event = data.write(&self.queue, "odd-even data", input)?;
+
+        let rounds = n.div_ceil(2);
+        for round in 0..rounds {
+            self.check_cancelled()?;
+            event = swapped.fill(&self.queue, 0)?;
+            for phase in 0..2 {
+                event = SortKernel::new(&self.odd_even_kernel, n / 2)
+                    .checked(self.checked)
+                    .round_to(self.work_group_size)
+                    .buffer(data.buffer())?
+                    .scalar(&(n as cl_uint))
+                    .scalar(&(phase as cl_uint))
+                    .arg(swapped.buffer())
+                    .wait(&event)
+                    .enqueue(&self.queue)?;
+            }
+            let sorted = swapped.read(&self.queue, &event)?[0] == 0;
+            if let Some(progress) = progress {
+                progress(round + 1, rounds);
+            }
+            if sorted {
+                break;
+            }
+        }
+
+        let result = self.read_result_range(data.buffer(), 0, data.len(), &event)?;
+        self.pool.give_sized(CL_MEM_READ_WRITE, data);
+        self.pool.give_sized(CL_MEM_READ_WRITE, swapped);
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stable::SortOrder;
+    use crate::test_util::{assert_valid_sort, pseudo_random, test_sorter};
+    use std::cell::RefCell;
+
+    #[test]
+    fn odd_even_sorts_random_input() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        // Odd-even makes up to n rounds, so the input stays small.
+        for n in [0, 1, 2, 3, 301] {
+            let input = pseudo_random(n, n as u64);
+            let output = sorter.sort_odd_even(&input, None).unwrap();
+            assert_valid_sort(&input, &output, SortOrder::Ascending);
+        }
+    }
+
+    #[test]
+    fn odd_even_reports_every_round_it_runs() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let rounds = |input: &[i32]| {
+            let calls = RefCell::new(Vec::new());
+            let progress = |done, total| calls.borrow_mut().push((done, total));
+            let output = sorter.sort_odd_even(input, Some(&progress)).unwrap();
+            assert_valid_sort(input, &output, SortOrder::Ascending);
+            calls.into_inner()
+        };
+        // Sorted input stops after its first round, of two passes.
+        let increasing: Vec<i32> = (0..101).collect();
+        assert_eq!(rounds(&increasing), [(1, 51)]);
+        // Reversed input swaps in every round, so runs all of them.
+        for (n, total) in [(100, 50), (101, 51)] {
+            let decreasing: Vec<i32> = (0..n).rev().collect();
+            let expected: Vec<(usize, usize)> = (1..=total).map(|done| (done, total)).collect();
+            assert_eq!(rounds(&decreasing), expected);
+        }
+    }
+
+    #[test]
+    fn odd_even_stops_on_the_first_round_without_a_swap() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let rounds = |input: &[i32]| {
+            let done = RefCell::new(0);
+            let progress = |round, _| *done.borrow_mut() = round;
+            let output = sorter.sort_odd_even(input, Some(&progress)).unwrap();
+            assert_valid_sort(input, &output, SortOrder::Ascending);
+            done.into_inner()
+        };
+        // Equal neighbours are never swapped.
+        assert_eq!(rounds(&[4; 200]), 1);
+        // One swapped pair is fixed by the first round, and the second finds
+        // nothing left to swap.
+        let mut one_swap: Vec<i32> = (0..200).collect();
+        one_swap.swap(10, 11);
+        assert_eq!(rounds(&one_swap), 2);
+        assert_eq!(rounds(&[2, 1]), 1);
+    }
+}
@@ -0,0 +1,114 @@
+//! Text formatting of sorted values and timings.
+
+use std::fmt::Display;
+
+/// Formats a float for the text output formats.
+///
+/// With `precision`, prints that many decimal digits. Without it, prints
+/// the shortest representation that parses back to exactly the same value.
+/// Special values always print as `nan`, `inf` and `-inf`.
+pub fn format_float<T>(value: T, precision: Option<usize>) -> String
+where
+    T: Copy + Display + Into<f64>,
+{
+    let wide: f64 = value.into();
+    if wide.is_nan() {
+        "nan".to_string()
+    } else if wide.is_infinite() {
+        if wide > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        match precision {
+            Some(precision) => format!("{:.*}", precision, value),
+            None => value.to_string(),
+        }
+    }
+}
+
+/// Formats a duration of `ns` nanoseconds for people to read, in ns, µs, ms
+/// or s, whichever keeps the number below 1000, with three significant
+/// digits: `500 ns`, `1.50 ms`, `2.30 s`. Machine-readable output should
+/// keep raw nanoseconds.
+pub fn format_duration(ns: u64) -> String {
+    // The bounds fall where rounding to three digits would reach 1000.
+    let (value, unit) = match ns {
+        0..=999 => return format!("{} ns", ns),
+        1_000..=999_499 => (ns as f64 / 1e3, "µs"),
+        999_500..=999_499_999 => (ns as f64 / 1e6, "ms"),
+        _ => (ns as f64 / 1e9, "s"),
+    };
+    let precision = if value >= 99.95 {
+        0
+    } else if value >= 9.995 {
+        1
+    } else {
+        2
+    };
+    format!("{:.*} {}", precision, value, unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_duration_picks_the_unit_by_magnitude() {
+        assert_eq!(format_duration(0), "0 ns");
+        assert_eq!(format_duration(500), "500 ns");
+        assert_eq!(format_duration(1_500_000), "1.50 ms");
+        assert_eq!(format_duration(2_300_000_000), "2.30 s");
+        assert_eq!(format_duration(125_000_000_000), "125 s");
+    }
+
+    #[test]
+    fn format_duration_keeps_three_significant_digits() {
+        assert_eq!(format_duration(1_000), "1.00 µs");
+        assert_eq!(format_duration(12_340), "12.3 µs");
+        assert_eq!(format_duration(123_400), "123 µs");
+        assert_eq!(format_duration(45_670_000), "45.7 ms");
+    }
+
+    #[test]
+    fn format_duration_moves_up_a_unit_instead_of_reaching_1000() {
+        assert_eq!(format_duration(999), "999 ns");
+        assert_eq!(format_duration(999_499), "999 µs");
+        assert_eq!(format_duration(999_500), "1.00 ms");
+        assert_eq!(format_duration(999_499_999), "999 ms");
+        assert_eq!(format_duration(999_500_000), "1.00 s");
+    }
+
+    #[test]
+    fn format_float_round_trips_f32_bit_patterns() {
+        let values = [
+            1.0f32,
+            -0.0,
+            0.1,
+            1.0 / 3.0,
+            f32::MAX,
+            f32::MIN_POSITIVE,
+            1e-45,
+            16_777_217.0,
+        ];
+        for value in values {
+            let text = format_float(value, None);
+            assert_eq!(
+                text.parse::<f32>().unwrap().to_bits(),
+                value.to_bits(),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn format_float_names_special_values() {
+        assert_eq!(format_float(f32::NAN, None), "nan");
+        assert_eq!(format_float(f64::INFINITY, Some(3)), "inf");
+        assert_eq!(format_float(f32::NEG_INFINITY, None), "-inf");
+    }
+
+    #[test]
+    fn format_float_uses_the_precision() {
+        assert_eq!(format_float(2.0f64 / 3.0, Some(3)), "0.667");
+        assert_eq!(format_float(1.5f32, Some(0)), "2");
+    }
+}
@@ -0,0 +1,248 @@
+use crate::buffer::SizedBuffer;
+use crate::error::Result;
+use opencl3::context::Context;
+use opencl3::memory::Buffer;
+use opencl3::types::{cl_int, cl_mem_flags};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::{mem, ptr};
+
+/// Default of [`GpuSorterBuilder::pool_max_bytes`], 256 MiB of free
+/// buffers.
+///
+/// [`GpuSorterBuilder::pool_max_bytes`]: crate::GpuSorterBuilder::pool_max_bytes
+pub const DEFAULT_POOL_MAX_BYTES: usize = 256 << 20;
+
+type Key = (usize, cl_mem_flags);
+
+/// Free buffers in the order they were returned, oldest first, holding at
+/// most `max_bytes` of `cl_int` elements between them.
+struct FreeLists<B> {
+    entries: VecDeque<(Key, B)>,
+    bytes: usize,
+    max_bytes: usize,
+}
+
+impl<B> FreeLists<B> {
+    fn new(max_bytes: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            bytes: 0,
+            max_bytes,
+        }
+    }
+
+    /// Stores `buffer` under `key`, then evicts the least recently returned
+    /// buffers until the total is within the cap, returning them. A buffer
+    /// larger than the cap on its own is evicted straight away.
+    fn push(&mut self, key: Key, buffer: B) -> Vec<B> {
+        self.bytes += key_bytes(key);
+        self.entries.push_back((key, buffer));
+        let mut evicted = Vec::new();
+        while self.bytes > self.max_bytes {
+            let Some((key, buffer)) = self.entries.pop_front() else {
+                break;
+            };
+            self.bytes -= key_bytes(key);
+            evicted.push(buffer);
+        }
+        evicted
+    }
+
+    /// Removes the most recently returned buffer under `key`.
+    fn pop(&mut self, key: Key) -> Option<B> {
+        let index = self.entries.iter().rposition(|(k, _)| *k == key)?;
+        let (key, buffer) = self.entries.remove(index)?;
+        self.bytes -= key_bytes(key);
+        Some(buffer)
+    }
+
+    fn clear(&mut self) -> Vec<B> {
+        self.bytes = 0;
+        self.entries.drain(..).map(|(_, buffer)| buffer).collect()
+    }
+}
+
+fn key_bytes((size, _): Key) -> usize {
+    size.saturating_mul(mem::size_of::<cl_int>())
+}
+
+/// Recycles scratch `Buffer<cl_int>` objects between sorts, keyed by element
+/// count and memory flags. Buffers handed out by [`BufferPool::take`] keep
+/// whatever contents they had when returned, so callers must initialise them.
+///
+/// The free buffers are capped at a number of bytes, by default
+/// [`DEFAULT_POOL_MAX_BYTES`]; returning one beyond the cap releases the
+/// least recently returned buffers, so sorts of many different lengths don't
+/// keep device memory for each of them.
+pub struct BufferPool {
+    free: RefCell<FreeLists<Buffer<cl_int>>>,
+    allocations: Cell<usize>,
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::with_max_bytes(DEFAULT_POOL_MAX_BYTES)
+    }
+}
+
+impl BufferPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A pool that keeps at most `max_bytes` of free buffers. A cap of 0
+    /// releases every buffer as soon as it is returned.
+    pub fn with_max_bytes(max_bytes: usize) -> Self {
+        Self {
+            free: RefCell::new(FreeLists::new(max_bytes)),
+            allocations: Cell::new(0),
+        }
+    }
+
+    /// Hands out a free buffer of `size` elements with `flags`, creating one
+    /// only when none is available.
+    pub fn take(
+        &self,
+        context: &Context,
+        size: usize,
+        flags: cl_mem_flags,
+    ) -> Result<Buffer<cl_int>> {
+        if let Some(buffer) = self.free.borrow_mut().pop((size, flags)) {
+            return Ok(buffer);
+        }
+
+        let buffer = Buffer::<cl_int>::create(context, flags, size, ptr::null_mut())?;
+        self.allocations.set(self.allocations.get() + 1);
+        Ok(buffer)
+    }
+
+    /// Returns a buffer obtained from [`BufferPool::take`] for reuse,
+    /// releasing older free buffers if the pool is over its cap.
+    pub fn give(&self, size: usize, flags: cl_mem_flags, buffer: Buffer<cl_int>) {
+        self.free.borrow_mut().push((size, flags), buffer);
+    }
+
+    /// Like [`BufferPool::take`], wrapping the buffer with its length.
+    pub fn take_sized(
+        &self,
+        context: &Context,
+        size: usize,
+        flags: cl_mem_flags,
+    ) -> Result<SizedBuffer<cl_int>> {
+        Ok(SizedBuffer::from_buffer(
+            self.take(context, size, flags)?,
+            size,
+        ))
+    }
+
+    /// Returns a buffer obtained from [`BufferPool::take_sized`] for reuse,
+    /// under the length it carries.
+    pub fn give_sized(&self, flags: cl_mem_flags, buffer: SizedBuffer<cl_int>) {
+        self.give(buffer.len(), flags, buffer.into_buffer());
+    }
+
+    /// Releases every free buffer.
+    pub fn clear(&self) {
+        self.free.borrow_mut().clear();
+    }
+
+    /// Bytes of device memory held by the free buffers.
+    pub fn pooled_bytes(&self) -> usize {
+        self.free.borrow().bytes
+    }
+
+    /// The most bytes of free buffers the pool keeps.
+    pub fn max_bytes(&self) -> usize {
+        self.free.borrow().max_bytes
+    }
+
+    /// Number of device buffers the pool has created so far.
+    pub fn allocations(&self) -> usize {
+        self.allocations.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_sorter;
+    use opencl3::memory::CL_MEM_READ_WRITE;
+
+    const FLAGS: cl_mem_flags = CL_MEM_READ_WRITE;
+
+    #[test]
+    fn free_lists_count_bytes() {
+        let mut free = FreeLists::new(usize::MAX);
+        assert!(free.push((10, FLAGS), 'a').is_empty());
+        assert!(free.push((5, FLAGS), 'b').is_empty());
+        assert_eq!(free.bytes, 60);
+        assert_eq!(free.pop((10, FLAGS)), Some('a'));
+        assert_eq!(free.bytes, 20);
+        assert_eq!(free.pop((10, FLAGS)), None);
+    }
+
+    #[test]
+    fn free_lists_match_flags() {
+        let mut free = FreeLists::new(usize::MAX);
+        free.push((10, FLAGS), 'a');
+        assert_eq!(free.pop((10, 0)), None);
+        assert_eq!(free.pop((10, FLAGS)), Some('a'));
+    }
+
+    #[test]
+    fn free_lists_pop_the_most_recent() {
+        let mut free = FreeLists::new(usize::MAX);
+        free.push((10, FLAGS), 'a');
+        free.push((10, FLAGS), 'b');
+        assert_eq!(free.pop((10, FLAGS)), Some('b'));
+        assert_eq!(free.pop((10, FLAGS)), Some('a'));
+    }
+
+    #[test]
+    fn free_lists_evict_the_least_recent() {
+        // Room for 30 elements.
+        let mut free = FreeLists::new(120);
+        free.push((10, FLAGS), 'a');
+        free.push((10, FLAGS), 'b');
+        free.push((10, FLAGS), 'c');
+        assert_eq!(free.push((15, FLAGS), 'd'), vec!['a', 'b']);
+        assert_eq!(free.bytes, 100);
+        assert_eq!(free.pop((10, FLAGS)), Some('c'));
+        assert_eq!(free.pop((15, FLAGS)), Some('d'));
+    }
+
+    #[test]
+    fn free_lists_evict_a_buffer_over_the_cap() {
+        let mut free = FreeLists::new(8);
+        assert_eq!(free.push((3, FLAGS), 'a'), vec!['a']);
+        assert_eq!(free.bytes, 0);
+    }
+
+    #[test]
+    fn free_lists_clear() {
+        let mut free = FreeLists::new(usize::MAX);
+        free.push((10, FLAGS), 'a');
+        free.push((5, FLAGS), 'b');
+        assert_eq!(free.clear(), vec!['a', 'b']);
+        assert_eq!(free.bytes, 0);
+        assert_eq!(free.pop((10, FLAGS)), None);
+    }
+
+    #[test]
+    fn repeated_sorts_stop_allocating() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input = crate::test_util::pseudo_random(1000, 7);
+        sorter.sort_radix(&input, None).unwrap();
+        let allocations = sorter.buffer_pool().allocations();
+        for _ in 0..10 {
+            sorter.sort_radix(&input, None).unwrap();
+        }
+        assert_eq!(sorter.buffer_pool().allocations(), allocations);
+        assert!(sorter.buffer_pool().pooled_bytes() > 0);
+        sorter.buffer_pool().clear();
+        assert_eq!(sorter.buffer_pool().pooled_bytes(), 0);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::buffer::{read_range, upload, write_i32};
+use crate::error::{Result, SortError};
+use crate::launch::SortKernel;
+use crate::radix::NO_FLIP;
+use crate::sorter::GpuSorter;
+use opencl3::event::Event;
+use opencl3::memory::{Buffer, CL_MEM_READ_WRITE};
+use opencl3::types::{cl_int, cl_uint};
+
+impl GpuSorter {
+    /// Reverses the first `n` elements of `buf` on the device, with each of
+    /// `n / 2` work-items swapping one pair. Blocks until the swap is done.
+    pub fn reverse_gpu(&self, buf: &Buffer<cl_int>, n: usize) -> Result<()> {
+        if n < 2 {
+            return Ok(());
+        }
+
+        self.enqueue_reverse(buf, n)?.wait()?;
+        Ok(())
+    }
+
+    /// Enqueues the reversal of [`GpuSorter::reverse_gpu`] without waiting
+    /// for it, returning its event. `n` must be at least 2.
+    pub(crate) fn enqueue_reverse(&self, buf: &Buffer<cl_int>, n: usize) -> Result<Event> {
+        SortKernel::new(&self.reverse_kernel, n / 2)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer_of(buf, n)?
+            .scalar(&(n as cl_uint))
+            .enqueue(&self.queue)
+    }
+
+    /// Stably partitions `input` around `pivot`: the elements less than
+    /// `pivot` come first, then the rest, each part in its original order.
+    /// Returns the rearranged array and the index where the `>= pivot` part
+    /// begins, which is 0 or `input.len()` when one part is empty.
+    ///
+    /// Each element is flagged, the flags are scanned and every element is
+    /// scattered to its position in one pass, with no atomics.
+    pub fn partition(&self, input: &[i32], pivot: i32) -> Result<(Vec<i32>, usize)> {
+        let n = input.len();
+        if n == 0 {
+            return Ok((Vec::new(), 0));
+        }
+
+        let source = upload(&self.context, input)?;
+        let flags = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+        let scratch = [
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+        ];
+        let result_buffer = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+
+        let flags_event = SortKernel::new(&self.pivot_flags_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&source)?
+            .buffer(&flags)?
+            .scalar(&pivot)
+            .enqueue(&self.queue)?;
+
+        let (scan, scan_event) = self.inclusive_scan(&flags, &scratch, n, &flags_event)?;
+
+        let scatter_event = SortKernel::new(&self.radix_scatter_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&source)?
+            .buffer(&flags)?
+            .buffer(scan)?
+            .buffer(&result_buffer)?
+            .scalar(&NO_FLIP)
+            .wait(&scan_event)
+            .enqueue(&self.queue)?;
+
+        let split = read_range(&self.queue, scan, n - 1, 1, &scan_event)?[0] as usize;
+        let result = self.read_result_range(&result_buffer, 0, n, &scatter_event)?;
+        for buffer in scratch.into_iter().chain([flags, result_buffer]) {
+            self.pool.give(n, CL_MEM_READ_WRITE, buffer);
+        }
+        Ok((result, split))
+    }
+
+    /// Returns the elements of `input` whose entry in `keep` is nonzero, in
+    /// their original order.
+    ///
+    /// This is the flag, scan and scatter that [`GpuSorter::sort_unique`]
+    /// uses, with the flags given: their inclusive scan gives each kept
+    /// element its output position. Returns [`SortError::InvalidInput`] if
+    /// `keep` and `input` differ in length.
+    pub fn compact(&self, input: &[i32], keep: &[u8]) -> Result<Vec<i32>> {
+        if keep.len() != input.len() {
+            return Err(SortError::InvalidInput(format!(
+                "{} keep flags for {} elements",
+                keep.len(),
+                input.len()
+            )));
+        }
+        let n = input.len();
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+
+        let source = upload(&self.context, input)?;
+        let mut flags = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+        let scratch = [
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+        ];
+        let result_buffer = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+
+        // The scan adds the flags up, so they must be 0 or 1.
+        let keep: Vec<i32> = keep.iter().map(|&flag| (flag != 0) as i32).collect();
+        let flags_event = write_i32(&self.queue, &mut flags, "keep flags", &keep)?;
+
+        let (scan, scan_event) = self.inclusive_scan(&flags, &scratch, n, &flags_event)?;
+
+        let compact_event = SortKernel::new(&self.compact_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&source)?
+            .buffer(&flags)?
+            .buffer(scan)?
+            .buffer(&result_buffer)?
+            .wait(&scan_event)
+            .enqueue(&self.queue)?;
+
+        let count = read_range(&self.queue, scan, n - 1, 1, &compact_event)?[0] as usize;
+        let result = self.read_result_range(&result_buffer, 0, count, &compact_event)?;
+        for buffer in scratch.into_iter().chain([flags, result_buffer]) {
+            self.pool.give(n, CL_MEM_READ_WRITE, buffer);
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::SortError;
+    use crate::test_util::{pseudo_random, test_sorter};
+
+    #[test]
+    fn partition_is_stable_around_any_pivot() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        // Even values, so that odd pivots are absent from the input.
+        let input: Vec<i32> = pseudo_random(1000, 71).iter().map(|v| v % 50 * 2).collect();
+        let (min, max) = (*input.iter().min().unwrap(), *input.iter().max().unwrap());
+        for pivot in [input[0], input[0] + 1, min, min - 1, max, max + 1] {
+            let mut expected: Vec<i32> = input.iter().copied().filter(|&v| v < pivot).collect();
+            let split = expected.len();
+            expected.extend(input.iter().filter(|&&v| v >= pivot));
+            assert_eq!(
+                sorter.partition(&input, pivot).unwrap(),
+                (expected, split),
+                "pivot {}",
+                pivot
+            );
+        }
+    }
+
+    #[test]
+    fn partition_of_short_inputs() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        assert_eq!(sorter.partition(&[], 3).unwrap(), (Vec::new(), 0));
+        assert_eq!(sorter.partition(&[5], 3).unwrap(), (vec![5], 0));
+        assert_eq!(sorter.partition(&[5], 9).unwrap(), (vec![5], 1));
+    }
+
+    #[test]
+    fn compact_keeps_the_flagged_elements_in_order() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input = pseudo_random(1000, 103);
+        // Any nonzero flag keeps its element.
+        let evens: Vec<u8> = input.iter().map(|v| (v % 2 == 0) as u8 * 3).collect();
+        let expected: Vec<i32> = input.iter().copied().filter(|v| v % 2 == 0).collect();
+        assert_eq!(sorter.compact(&input, &evens).unwrap(), expected);
+        assert!(sorter.compact(&input, &[0; 1000]).unwrap().is_empty());
+        assert_eq!(sorter.compact(&input, &[1; 1000]).unwrap(), input);
+        assert!(sorter.compact(&[], &[]).unwrap().is_empty());
+        assert!(matches!(
+            sorter.compact(&input, &[1; 999]),
+            Err(SortError::InvalidInput(_))
+        ));
+    }
+}
@@ -0,0 +1,351 @@
+use crate::error::{Result, SortError};
+use crate::multi::device_capability;
+use crate::sorter::find_devices;
+use opencl3::device::{Device, CL_DEVICE_TYPE_CPU, CL_DEVICE_TYPE_GPU};
+use opencl3::types::cl_device_id;
+use std::fmt;
+
+/// Identifies an OpenCL device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub vendor: String,
+    pub version: String,
+    pub driver_version: String,
+}
+
+impl DeviceInfo {
+    /// Queries `device`'s identifying strings.
+    pub fn of_device(device: &Device) -> Result<Self> {
+        Ok(DeviceInfo {
+            name: device.name()?,
+            vendor: device.vendor()?,
+            version: device.version()?,
+            driver_version: device.driver_version()?,
+        })
+    }
+
+    fn query(device_id: cl_device_id) -> Result<Self> {
+        Self::of_device(&Device::new(device_id))
+    }
+}
+
+/// A GPU sorting could run on, with the figures device selection weighs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceCandidate {
+    pub info: DeviceInfo,
+    pub compute_units: u32,
+    pub clock_mhz: u32,
+    pub global_mem_bytes: u64,
+    /// Whether the device shares memory with the host, as integrated GPUs
+    /// do.
+    pub integrated: bool,
+    /// Compute units times clock in MHz, the capability
+    /// [`GpuSorterBuilder::build_multi`](crate::GpuSorterBuilder::build_multi)
+    /// ranks devices by.
+    pub score: u64,
+}
+
+impl DeviceCandidate {
+    /// Queries what selection weighs about `device`.
+    pub fn of_device(device: &Device) -> Result<Self> {
+        Ok(DeviceCandidate {
+            info: DeviceInfo::of_device(device)?,
+            compute_units: device.max_compute_units()?,
+            clock_mhz: device.max_clock_frequency()?,
+            global_mem_bytes: device.global_mem_size()?,
+            integrated: device.host_unified_memory()?,
+            score: device_capability(device)?,
+        })
+    }
+}
+
+impl fmt::Display for DeviceCandidate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}): score {} = {} compute units x {} MHz, {}, {} MiB",
+            self.info.name,
+            self.info.vendor,
+            self.score,
+            self.compute_units,
+            self.clock_mhz,
+            if self.integrated {
+                "integrated"
+            } else {
+                "discrete"
+            },
+            self.global_mem_bytes >> 20
+        )
+    }
+}
+
+/// Every GPU, in driver order, which is the order
+/// [`GpuSorter::new`](crate::GpuSorter::new) picks the first of and device
+/// indices count in.
+pub fn device_candidates() -> Result<Vec<DeviceCandidate>> {
+    find_devices(CL_DEVICE_TYPE_GPU)?
+        .into_iter()
+        .map(|id| DeviceCandidate::of_device(&Device::new(id)))
+        .collect()
+}
+
+/// Where sorting would run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    /// A GPU, which [`GpuSorter::new`](crate::GpuSorter::new) picks.
+    Gpu(DeviceInfo),
+    /// No GPU, but an OpenCL CPU device, usable through
+    /// [`GpuSorter::from_device`](crate::GpuSorter::from_device).
+    CpuCl(DeviceInfo),
+    /// No usable OpenCL device, so sorting has to happen on the host.
+    CpuFallback,
+}
+
+/// Reports which backend sorting would use, by enumerating the devices
+/// only: no context is created and no kernel is built.
+pub fn probe_backend() -> Result<BackendKind> {
+    let gpus = match find_devices(CL_DEVICE_TYPE_GPU) {
+        Ok(gpus) => gpus,
+        Err(SortError::NoOpenCl) => return Ok(BackendKind::CpuFallback),
+        Err(e) => return Err(e),
+    };
+    if let Some(&gpu) = gpus.first() {
+        return Ok(BackendKind::Gpu(DeviceInfo::query(gpu)?));
+    }
+    match find_devices(CL_DEVICE_TYPE_CPU)?.first() {
+        Some(&cpu) => Ok(BackendKind::CpuCl(DeviceInfo::query(cpu)?)),
+        None => Ok(BackendKind::CpuFallback),
+    }
+}
+
+/// The built-in kernels `device` advertises whose names mention sorting.
+///
+/// Built-in kernels take vendor-defined arguments, so the crate can only
+/// report them; running one needs its vendor's documentation.
+pub fn builtin_sort_kernels(device: &Device) -> Result<Vec<String>> {
+    Ok(device
+        .built_in_kernels()?
+        .split(';')
+        .map(str::trim)
+        .filter(|name| name.to_ascii_lowercase().contains("sort"))
+        .map(str::to_string)
+        .collect())
+}
+
+/// An element type the crate has a sort for. Each has its own entry point:
+/// [`GpuSorter::sort_u8`](crate::GpuSorter::sort_u8),
+/// [`sort_i16`](crate::GpuSorter::sort_i16),
+/// [`sort`](crate::GpuSorter::sort), [`sort_u64`](crate::GpuSorter::sort_u64)
+/// and [`sort_f64`](crate::GpuSorter::sort_f64).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ElementType {
+    U8,
+    I16,
+    I32,
+    U64,
+    F64,
+}
+
+impl ElementType {
+    /// Every element type, in the order [`supported_types`] lists them.
+    pub const ALL: [ElementType; 5] = [
+        ElementType::U8,
+        ElementType::I16,
+        ElementType::I32,
+        ElementType::U64,
+        ElementType::F64,
+    ];
+
+    /// The device extension sorting this type needs, if any. The `u64` sort
+    /// counts its ranks with 32-bit atomics on `int`s, so it doesn't need
+    /// `cl_khr_int64_base_atomics`.
+    pub fn required_extension(self) -> Option<&'static str> {
+        match self {
+            ElementType::F64 => Some("cl_khr_fp64"),
+            _ => None,
+        }
+    }
+
+    /// Returns [`SortError::MissingExtension`] if `extensions`, a device's
+    /// space-separated `CL_DEVICE_EXTENSIONS`, lacks
+    /// [`ElementType::required_extension`].
+    pub(crate) fn check_extensions(self, extensions: &str) -> Result<()> {
+        match self.required_extension() {
+            Some(extension) if !lists_extension(extensions, extension) => {
+                Err(SortError::MissingExtension(extension))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for ElementType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ElementType::U8 => "u8",
+            ElementType::I16 => "i16",
+            ElementType::I32 => "i32",
+            ElementType::U64 => "u64",
+            ElementType::F64 => "f64",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Whether `extensions`, a space-separated `CL_DEVICE_EXTENSIONS` list,
+/// holds `extension`.
+fn lists_extension(extensions: &str, extension: &str) -> bool {
+    extensions.split_whitespace().any(|name| name == extension)
+}
+
+/// The element types `device` can sort, those whose
+/// [`ElementType::required_extension`] it reports, in the order of
+/// [`ElementType::ALL`].
+pub fn supported_types(device: &Device) -> Result<Vec<ElementType>> {
+    Ok(types_with_extensions(&device.extensions()?))
+}
+
+/// The element types a device reporting `extensions` can sort, for
+/// [`supported_types`].
+fn types_with_extensions(extensions: &str) -> Vec<ElementType> {
+    ElementType::ALL
+        .into_iter()
+        .filter(|element| element.check_extensions(extensions).is_ok())
+        .collect()
+}
+
+/// An OpenCL or OpenCL C version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct OpenClVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl OpenClVersion {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        OpenClVersion { major, minor }
+    }
+
+    /// Parses the version out of a string of the form the driver reports
+    /// for `CL_DEVICE_VERSION` or `CL_DEVICE_OPENCL_C_VERSION`, such as
+    /// `"OpenCL 3.0 CUDA 12.2.148"` or `"OpenCL C 1.2 "`: the first
+    /// `major.minor` token after the `OpenCL` or `OpenCL C` prefix.
+    pub fn parse(version: &str) -> Option<Self> {
+        let rest = version.strip_prefix("OpenCL")?.trim_start();
+        let rest = rest.strip_prefix("C ").unwrap_or(rest);
+        let (major, minor) = rest.split_whitespace().next()?.split_once('.')?;
+        Some(OpenClVersion::new(major.parse().ok()?, minor.parse().ok()?))
+    }
+
+    /// The OpenCL C version `device` compiles kernels for, which is what
+    /// kernel language features depend on.
+    pub fn of_device(device: &Device) -> Result<Self> {
+        let version = device.opencl_c_version()?;
+        OpenClVersion::parse(&version).ok_or_else(|| {
+            SortError::InvalidInput(format!(
+                "unrecognized OpenCL C version string {:?}",
+                version
+            ))
+        })
+    }
+}
+
+impl fmt::Display for OpenClVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn f64_needs_cl_khr_fp64() {
+        let without = "cl_khr_int64_base_atomics cl_khr_fp16 cl_khr_fp64x";
+        assert!(matches!(
+            ElementType::F64.check_extensions(without),
+            Err(SortError::MissingExtension("cl_khr_fp64"))
+        ));
+        assert!(ElementType::F64
+            .check_extensions("cl_khr_fp16  cl_khr_fp64 ")
+            .is_ok());
+        assert!(ElementType::I32.check_extensions("").is_ok());
+    }
+
+    #[test]
+    fn supported_types_leave_out_f64_without_fp64() {
+        let integers = [
+            ElementType::U8,
+            ElementType::I16,
+            ElementType::I32,
+            ElementType::U64,
+        ];
+        assert_eq!(types_with_extensions(""), integers);
+        assert_eq!(types_with_extensions("cl_khr_fp16 cl_khr_fp64x"), integers);
+        assert_eq!(
+            types_with_extensions("cl_khr_fp16 cl_khr_fp64"),
+            ElementType::ALL
+        );
+    }
+
+    #[test]
+    fn parses_device_versions() {
+        for (version, expected) in [
+            ("OpenCL 3.0 CUDA 12.2.148", (3, 0)),
+            ("OpenCL 1.2 ", (1, 2)),
+            ("OpenCL 2.1 AMD-APP (3513.0)", (2, 1)),
+            (
+                "OpenCL 3.0 PoCL 5.0+debian  Linux, None+Asserts, RELOC",
+                (3, 0),
+            ),
+            ("OpenCL 1.2 Apple", (1, 2)),
+        ] {
+            assert_eq!(
+                OpenClVersion::parse(version),
+                Some(OpenClVersion::new(expected.0, expected.1)),
+                "{:?}",
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn parses_opencl_c_versions() {
+        assert_eq!(
+            OpenClVersion::parse("OpenCL C 1.2 "),
+            Some(OpenClVersion::new(1, 2))
+        );
+        assert_eq!(
+            OpenClVersion::parse("OpenCL C 2.0"),
+            Some(OpenClVersion::new(2, 0))
+        );
+        assert_eq!(
+            OpenClVersion::parse("OpenCL C 3.0 PoCL"),
+            Some(OpenClVersion::new(3, 0))
+        );
+    }
+
+    #[test]
+    fn rejects_unrecognized_versions() {
+        for version in [
+            "",
+            "OpenCL",
+            "OpenCL C",
+            "CUDA 12.2",
+            "OpenCL 3",
+            "OpenCL x.y",
+        ] {
+            assert_eq!(OpenClVersion::parse(version), None, "{:?}", version);
+        }
+    }
+
+    #[test]
+    fn versions_order_by_major_then_minor() {
+        assert!(OpenClVersion::new(1, 2) < OpenClVersion::new(2, 0));
+        assert!(OpenClVersion::new(2, 0) < OpenClVersion::new(2, 1));
+        assert!(OpenClVersion::new(1, 10) > OpenClVersion::new(1, 2));
+        assert_eq!(OpenClVersion::new(2, 1).to_string(), "2.1");
+    }
+}
@@ -0,0 +1,104 @@
+use opencl3::event::Event;
+
+/// With the `tracing` feature, enters an info-level span named `$name`, with
+/// any fields after it, until the end of the enclosing block. The fields are
+/// only evaluated with the feature. Without it, this does nothing.
+macro_rules! phase_span {
+    ($name:literal $(, $($fields:tt)*)?) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!($name $(, $($fields)*)?).entered();
+    };
+}
+pub(crate) use phase_span;
+
+/// Timings of one sort, in nanoseconds.
+///
+/// `compare_ns` and `assign_ns` are the device times of the rank sort's two
+/// kernels, read from their profiling events; sorts without per-kernel
+/// profiling report their whole wall-clock time as `compare_ns`. Either is
+/// `None` when the driver couldn't report it, which doesn't affect the
+/// sorted result, and `assign_ns` is also `None` for sorts that run no
+/// assign kernel. `host_wall_ns` is measured on the host around everything
+/// from the first upload to the final read, so it also covers transfers and
+/// launch latency.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProfilingReport {
+    pub compare_ns: Option<u64>,
+    pub assign_ns: Option<u64>,
+    pub host_wall_ns: u64,
+}
+
+/// The report of a sort that ran no kernels.
+impl Default for ProfilingReport {
+    fn default() -> Self {
+        ProfilingReport {
+            compare_ns: Some(0),
+            assign_ns: None,
+            host_wall_ns: 0,
+        }
+    }
+}
+
+impl ProfilingReport {
+    /// Total device kernel time: the compare time, plus the assign time if
+    /// there is one. `None` if the compare time is unknown.
+    pub fn device_ns(&self) -> Option<u64> {
+        Some(self.compare_ns? + self.assign_ns.unwrap_or(0))
+    }
+
+    /// Host wall-clock time not spent in the kernels: uploads, reads, launch
+    /// and synchronisation overhead.
+    pub fn overhead_ns(&self) -> Option<u64> {
+        Some(self.host_wall_ns.saturating_sub(self.device_ns()?))
+    }
+
+    /// Whether the compare time is missing because profiling failed.
+    pub fn is_partial(&self) -> bool {
+        self.compare_ns.is_none()
+    }
+}
+
+/// How long the command behind `event` ran on the device, best effort: some
+/// drivers fail the profiling queries, or report zero, even on a profiling
+/// queue, and that gives `None` rather than an error.
+pub fn event_duration_ns(event: &Event) -> Option<u64> {
+    let start = event.profiling_command_start().ok()?;
+    let end = event.profiling_command_end().ok()?;
+    (start != 0 && end >= start).then(|| end - start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_without_an_assign_kernel_are_complete() {
+        let single = ProfilingReport {
+            compare_ns: Some(300),
+            assign_ns: None,
+            host_wall_ns: 1000,
+        };
+        assert_eq!(single.device_ns(), Some(300));
+        assert_eq!(single.overhead_ns(), Some(700));
+        assert!(!single.is_partial());
+        let rank = ProfilingReport {
+            assign_ns: Some(200),
+            ..single
+        };
+        assert_eq!(rank.device_ns(), Some(500));
+        assert_eq!(rank.overhead_ns(), Some(500));
+    }
+
+    #[test]
+    fn reports_without_a_compare_time_are_partial() {
+        let report = ProfilingReport {
+            compare_ns: None,
+            assign_ns: Some(200),
+            host_wall_ns: 1000,
+        };
+        assert!(report.is_partial());
+        assert_eq!(report.device_ns(), None);
+        assert_eq!(report.overhead_ns(), None);
+        assert!(!ProfilingReport::default().is_partial());
+    }
+}
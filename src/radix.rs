@@ -0,0 +1,302 @@
+use crate::buffer::write_i32;
+use crate::error::Result;
+use crate::launch::SortKernel;
+use crate::sorter::{check_window, GpuSorter};
+use opencl3::event::Event;
+use opencl3::memory::{Buffer, CL_MEM_READ_WRITE};
+use opencl3::types::{cl_int, cl_uint};
+use std::ops::Range;
+
+/// Number of passes the radix sort makes, one per key bit.
+pub const RADIX_PASSES: usize = 32;
+
+/// The sign bit, XORed into the keys to order them as unsigned integers.
+const SIGN_BIT: cl_uint = 0x8000_0000;
+
+/// A scatter `flip` that leaves the keys unchanged.
+pub(crate) const NO_FLIP: cl_uint = 0;
+
+impl GpuSorter {
+    /// Sorts `input` with an LSD radix sort of one bit per pass, each pass
+    /// splitting the keys on that bit with a flag, scan and scatter.
+    ///
+    /// Negative keys are handled by biasing: XORing the sign bit adds
+    /// `i32::MIN`, mapping the signed keys in order onto `0..=u32::MAX`, so
+    /// every pass splits on an unsigned bit. The first pass's scatter applies
+    /// the bias, which is safe because bit 0 doesn't change, and the last
+    /// pass's scatter removes it again.
+    ///
+    /// If given, `progress` is called on the host after each pass has been
+    /// enqueued with the number of passes done and the total.
+    ///
+    /// Returns [`SortError::Cancelled`](crate::SortError::Cancelled) if the
+    /// sorter's cancellation token is set before the last pass.
+    pub fn sort_radix(
+        &self,
+        input: &[i32],
+        progress: Option<&dyn Fn(usize, usize)>,
+    ) -> Result<Vec<i32>> {
+        self.sort_radix_window(input, 0..input.len(), progress)
+    }
+
+    /// Like [`GpuSorter::sort_radix`], but returns only `window` of the
+    /// sorted result, and only transfers that much of it from the device.
+    ///
+    /// Returns [`SortError::InvalidRange`](crate::SortError::InvalidRange)
+    /// if `window` doesn't fit in `input`.
+    pub fn sort_radix_window(
+        &self,
+        input: &[i32],
+        window: Range<usize>,
+        progress: Option<&dyn Fn(usize, usize)>,
+    ) -> Result<Vec<i32>> {
+        let n = input.len();
+        check_window(&window, n)?;
+        if n < 2 {
+            return Ok(input[window].to_vec());
+        }
+
+        let (sorted, event) = self.radix_sort_buffer(input, progress)?;
+        let mut result = vec![0; window.len()];
+        self.read_result(&sorted, window.start, &mut result, &event)?;
+        self.pool.give(n, CL_MEM_READ_WRITE, sorted);
+        Ok(result)
+    }
+
+    /// Like [`GpuSorter::sort_radix`], also calling `snapshot` after each
+    /// pass with the number of passes done and the keys as they stand. After
+    /// `p` passes the keys are sorted by their low `p` bits, so the snapshots
+    /// converge on the sorted result, which the last one is.
+    ///
+    /// Each snapshot is a blocking read of the whole key buffer that waits
+    /// for its pass, and the next pass is only enqueued once the read has
+    /// returned, so a snapshot never races the pass after it. The device
+    /// idles during each read, so this is slower than
+    /// [`GpuSorter::sort_radix`].
+    pub fn sort_radix_snapshots(
+        &self,
+        input: &[i32],
+        snapshot: &mut dyn FnMut(usize, Vec<i32>),
+    ) -> Result<Vec<i32>> {
+        let n = input.len();
+        if n < 2 {
+            return Ok(input.to_vec());
+        }
+
+        let mut keys = [
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+        ];
+        let event = write_i32(&self.queue, &mut keys[0], "radix keys", input)?;
+        let (sorted, event) = self.radix_sort_keys(keys, n, event, None, Some(snapshot))?;
+        let mut result = vec![0; n];
+        self.read_result(&sorted, 0, &mut result, &event)?;
+        self.pool.give(n, CL_MEM_READ_WRITE, sorted);
+        Ok(result)
+    }
+
+    /// Uploads and radix sorts `input`, leaving the result on the device.
+    /// Returns the sorted buffer, which came from the pool with
+    /// `CL_MEM_READ_WRITE` and should be given back once read, and the
+    /// event of the last pass. `input` must hold at least two elements.
+    pub(crate) fn radix_sort_buffer(
+        &self,
+        input: &[i32],
+        progress: Option<&dyn Fn(usize, usize)>,
+    ) -> Result<(Buffer<cl_int>, Event)> {
+        let n = input.len();
+        let mut keys = [
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+        ];
+        let event = write_i32(&self.queue, &mut keys[0], "radix keys", input)?;
+        self.radix_sort_keys(keys, n, event, progress, None)
+    }
+
+    /// Radix sorts the `n` keys in `keys[0]` once `event` has completed,
+    /// using `keys[1]` as the other half of each pass. Both buffers must
+    /// have come from the pool with `CL_MEM_READ_WRITE`, `n` elements long;
+    /// one is returned holding the result, as by
+    /// [`GpuSorter::radix_sort_buffer`], and the other is given back. If
+    /// given, `snapshot` is called as by [`GpuSorter::sort_radix_snapshots`].
+    pub(crate) fn radix_sort_keys(
+        &self,
+        keys: [Buffer<cl_int>; 2],
+        n: usize,
+        mut event: Event,
+        progress: Option<&dyn Fn(usize, usize)>,
+        mut snapshot: Option<&mut dyn FnMut(usize, Vec<i32>)>,
+    ) -> Result<(Buffer<cl_int>, Event)> {
+        let flags = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+        let scratch = [
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+        ];
+
+        let mut current = 0;
+        for bit in 0..RADIX_PASSES {
+            self.check_cancelled()?;
+            let flags_event = SortKernel::new(&self.radix_flags_kernel, n)
+                .checked(self.checked)
+                .round_to(self.work_group_size)
+                .buffer(&keys[current])?
+                .buffer(&flags)?
+                .scalar(&(bit as cl_uint))
+                .wait(&event)
+                .enqueue(&self.queue)?;
+
+            let (scan, scan_event) = self.inclusive_scan(&flags, &scratch, n, &flags_event)?;
+
+            let flip = if bit == 0 || bit == RADIX_PASSES - 1 {
+                SIGN_BIT
+            } else {
+                NO_FLIP
+            };
+            event = SortKernel::new(&self.radix_scatter_kernel, n)
+                .checked(self.checked)
+                .round_to(self.work_group_size)
+                .buffer(&keys[current])?
+                .buffer(&flags)?
+                .buffer(scan)?
+                .buffer(&keys[1 - current])?
+                .scalar(&flip)
+                .wait(&scan_event)
+                .enqueue(&self.queue)?;
+            current = 1 - current;
+
+            if let Some(snapshot) = snapshot.as_mut() {
+                // Until the last pass removes it, the keys carry the bias.
+                let bias = if bit == RADIX_PASSES - 1 {
+                    NO_FLIP
+                } else {
+                    SIGN_BIT
+                };
+                let keys = self.read_result_range(&keys[current], 0, n, &event)?;
+                snapshot(
+                    bit + 1,
+                    keys.into_iter()
+                        .map(|key| (key as cl_uint ^ bias) as i32)
+                        .collect(),
+                );
+            }
+
+            if let Some(report) = progress {
+                report(bit + 1, RADIX_PASSES);
+            }
+        }
+
+        // The queue is in order, so anything that takes these scratch
+        // buffers from the pool next runs after the last pass.
+        let [first, second] = keys;
+        let (sorted, spare) = if current == 0 {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        for buffer in scratch.into_iter().chain([flags, spare]) {
+            self.pool.give(n, CL_MEM_READ_WRITE, buffer);
+        }
+        Ok((sorted, event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RADIX_PASSES;
+    use crate::stable::SortOrder;
+    use crate::test_util::{assert_valid_sort, pseudo_random, test_sorter};
+    use std::cell::RefCell;
+
+    #[test]
+    fn radix_sorts_random_input() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        for n in [0, 1, 2, 1000] {
+            let input = pseudo_random(n, n as u64);
+            let output = sorter.sort_radix(&input, None).unwrap();
+            assert_valid_sort(&input, &output, SortOrder::Ascending);
+        }
+    }
+
+    #[test]
+    fn radix_reports_every_pass() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input = pseudo_random(300, 6);
+        let calls = RefCell::new(Vec::new());
+        let progress = |done, total| calls.borrow_mut().push((done, total));
+        let output = sorter.sort_radix(&input, Some(&progress)).unwrap();
+        assert_valid_sort(&input, &output, SortOrder::Ascending);
+        let expected: Vec<(usize, usize)> = (1..=RADIX_PASSES)
+            .map(|done| (done, RADIX_PASSES))
+            .collect();
+        assert_eq!(calls.into_inner(), expected);
+    }
+
+    #[test]
+    fn radix_snapshots_converge_on_the_sorted_keys() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input = pseudo_random(257, 9);
+        let mut expected = input.clone();
+        expected.sort_unstable();
+        let mut snapshots = Vec::new();
+        let output = sorter
+            .sort_radix_snapshots(&input, &mut |passes, keys| snapshots.push((passes, keys)))
+            .unwrap();
+        assert_eq!(output, expected);
+        assert_eq!(snapshots.len(), RADIX_PASSES);
+        for (index, (passes, keys)) in snapshots.iter().enumerate() {
+            assert_eq!(*passes, index + 1);
+            let mut sorted = keys.clone();
+            sorted.sort_unstable();
+            assert_eq!(sorted, expected, "after {} passes", passes);
+            // After p passes the keys are in order of their low p bits.
+            let mask = 1u32
+                .checked_shl(*passes as u32)
+                .map_or(u32::MAX, |bit| bit - 1);
+            assert!(
+                keys.windows(2)
+                    .all(|pair| pair[0] as u32 & mask <= pair[1] as u32 & mask),
+                "after {} passes",
+                passes
+            );
+        }
+        assert_eq!(snapshots.last().unwrap().1, expected);
+        assert_eq!(
+            sorter
+                .sort_radix_snapshots(&[3], &mut |_, _| panic!("no passes for one key"))
+                .unwrap(),
+            [3]
+        );
+    }
+
+    #[test]
+    fn radix_orders_signed_keys_across_zero() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        // Keys near both ends and zero, so the sign bias matters.
+        let input: Vec<i32> = (0..300)
+            .map(|i| match i % 5 {
+                0 => i32::MIN + i,
+                1 => -i,
+                2 => 0,
+                3 => i,
+                _ => i32::MAX - i,
+            })
+            .collect();
+        let mut expected = input.clone();
+        expected.sort_unstable();
+        assert_eq!(sorter.sort_radix(&input, None).unwrap(), expected);
+        assert_eq!(
+            sorter
+                .sort_radix(&[1, -1, i32::MAX, i32::MIN], None)
+                .unwrap(),
+            [i32::MIN, -1, 1, i32::MAX]
+        );
+    }
+}
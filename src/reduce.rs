@@ -0,0 +1,233 @@
+use crate::buffer::upload;
+use crate::error::Result;
+use crate::launch::SortKernel;
+use crate::sorter::GpuSorter;
+use crate::stable::SortOrder;
+use opencl3::event::Event;
+use opencl3::memory::{Buffer, CL_MEM_READ_WRITE};
+use opencl3::types::{cl_int, cl_uint};
+
+/// Operator of the reduce kernel, passed to it as an `int`.
+#[derive(Clone, Copy)]
+pub(crate) enum ReduceOp {
+    Min = 0,
+    Max = 1,
+    Sum = 2,
+}
+
+impl GpuSorter {
+    /// Whether `input` is already in ascending order, checked on the device
+    /// with one work-item per adjacent pair raising a shared flag on an
+    /// inversion. This costs one upload and a single pass, much less than a
+    /// sort.
+    pub fn is_sorted_gpu(&self, input: &[i32]) -> Result<bool> {
+        self.is_sorted_ordered_gpu(input, SortOrder::Ascending)
+    }
+
+    /// Like [`GpuSorter::is_sorted_gpu`], checking for `order`; equal
+    /// neighbours are in order either way.
+    pub fn is_sorted_ordered_gpu(&self, input: &[i32], order: SortOrder) -> Result<bool> {
+        let n = input.len();
+        if n < 2 {
+            return Ok(true);
+        }
+
+        let data = upload(&self.context, input)?;
+        let mut unsorted = self.pool.take_sized(&self.context, 1, CL_MEM_READ_WRITE)?;
+        let fill_event = unsorted.fill(&self.queue, 0)?;
+
+        let unsorted_event = SortKernel::new(&self.unsorted_kernel, n - 1)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&data)?
+            .arg(unsorted.buffer())
+            .scalar(&((order == SortOrder::Descending) as cl_int))
+            .wait(&fill_event)
+            .enqueue(&self.queue)?;
+
+        let flag = unsorted.read(&self.queue, &unsorted_event)?[0];
+        self.pool.give_sized(CL_MEM_READ_WRITE, unsorted);
+        Ok(flag == 0)
+    }
+
+    /// The smallest element of `input`, or `None` if it is empty.
+    pub fn reduce_min(&self, input: &[i32]) -> Result<Option<i32>> {
+        if input.is_empty() {
+            return Ok(None);
+        }
+        self.reduce(input, ReduceOp::Min).map(Some)
+    }
+
+    /// The largest element of `input`, or `None` if it is empty.
+    pub fn reduce_max(&self, input: &[i32]) -> Result<Option<i32>> {
+        if input.is_empty() {
+            return Ok(None);
+        }
+        self.reduce(input, ReduceOp::Max).map(Some)
+    }
+
+    /// Whether every element of `input` is equal, from
+    /// [`GpuSorter::reduce_min`] and [`GpuSorter::reduce_max`] agreeing.
+    /// Constant input is already sorted, so this is a cheaper check than
+    /// [`GpuSorter::is_sorted_gpu`] for callers that only expect constant
+    /// data to skip the sort: two reductions of O(log n) launches each.
+    pub fn is_constant_gpu(&self, input: &[i32]) -> Result<bool> {
+        Ok(self.reduce_min(input)? == self.reduce_max(input)?)
+    }
+
+    /// The sum of `input`, wrapping on overflow like `i32::wrapping_add`.
+    /// The sum of an empty slice is 0.
+    pub fn reduce_sum(&self, input: &[i32]) -> Result<i32> {
+        if input.is_empty() {
+            return Ok(0);
+        }
+        self.reduce(input, ReduceOp::Sum)
+    }
+
+    /// Tree reduction of a non-empty `input`: each launch combines adjacent
+    /// pairs, halving the length until one value is left.
+    fn reduce(&self, input: &[i32], op: ReduceOp) -> Result<i32> {
+        let n = input.len();
+        if n == 1 {
+            return Ok(input[0]);
+        }
+
+        let source = upload(&self.context, input)?;
+        self.reduce_buffer(&source, n, op, None)
+    }
+
+    /// [`GpuSorter::reduce`] of the first `n` elements of `source`, at least
+    /// two, once `wait` has completed, for data that is already on the
+    /// device. `source` itself is left unchanged.
+    pub(crate) fn reduce_buffer(
+        &self,
+        source: &Buffer<cl_int>,
+        n: usize,
+        op: ReduceOp,
+        wait: Option<&Event>,
+    ) -> Result<i32> {
+        let half = n.div_ceil(2);
+        let scratch = [
+            self.pool.take(&self.context, half, CL_MEM_READ_WRITE)?,
+            self.pool.take(&self.context, half, CL_MEM_READ_WRITE)?,
+        ];
+
+        let mut first = SortKernel::new(&self.reduce_kernel, half)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(source)?
+            .buffer(&scratch[0])?
+            .scalar(&(n as cl_uint))
+            .scalar(&(op as cl_int));
+        if let Some(wait) = wait {
+            first = first.wait(wait);
+        }
+        let mut event = first.enqueue(&self.queue)?;
+
+        let mut current = 0;
+        let mut len = half;
+        while len > 1 {
+            event = SortKernel::new(&self.reduce_kernel, len.div_ceil(2))
+                .checked(self.checked)
+                .round_to(self.work_group_size)
+                .buffer(&scratch[current])?
+                .buffer(&scratch[1 - current])?
+                .scalar(&(len as cl_uint))
+                .scalar(&(op as cl_int))
+                .wait(&event)
+                .enqueue(&self.queue)?;
+            current = 1 - current;
+            len = len.div_ceil(2);
+        }
+
+        let result = self.read_result_range(&scratch[current], 0, 1, &event)?[0];
+        for buffer in scratch {
+            self.pool.give(half, CL_MEM_READ_WRITE, buffer);
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stable::SortOrder;
+    use crate::test_util::{pseudo_random, test_sorter};
+
+    #[test]
+    fn reductions_match_the_host() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        for n in [2, 3, 255, 256, 1000, (1 << 16) + 3] {
+            let input = pseudo_random(n, n as u64);
+            assert_eq!(
+                sorter.reduce_min(&input).unwrap(),
+                input.iter().min().copied()
+            );
+            assert_eq!(
+                sorter.reduce_max(&input).unwrap(),
+                input.iter().max().copied()
+            );
+            let sum = input.iter().fold(0i32, |sum, &v| sum.wrapping_add(v));
+            assert_eq!(sorter.reduce_sum(&input).unwrap(), sum, "n = {}", n);
+        }
+    }
+
+    #[test]
+    fn reductions_of_short_inputs() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        assert_eq!(sorter.reduce_min(&[]).unwrap(), None);
+        assert_eq!(sorter.reduce_max(&[]).unwrap(), None);
+        assert_eq!(sorter.reduce_sum(&[]).unwrap(), 0);
+        assert_eq!(sorter.reduce_min(&[i32::MAX]).unwrap(), Some(i32::MAX));
+        assert_eq!(sorter.reduce_max(&[i32::MIN]).unwrap(), Some(i32::MIN));
+        assert_eq!(sorter.reduce_sum(&[-9]).unwrap(), -9);
+    }
+
+    #[test]
+    fn large_constant_input_is_detected() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let mut input = vec![7; 1 << 20];
+        assert!(sorter.is_constant_gpu(&input).unwrap());
+        *input.last_mut().unwrap() = 8;
+        assert!(!sorter.is_constant_gpu(&input).unwrap());
+        input[0] = i32::MIN;
+        assert!(!sorter.is_constant_gpu(&input).unwrap());
+    }
+
+    #[test]
+    fn short_inputs_are_constant() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        assert!(sorter.is_constant_gpu(&[]).unwrap());
+        assert!(sorter.is_constant_gpu(&[-4]).unwrap());
+    }
+
+    #[test]
+    fn is_sorted_gpu_tells_sorted_from_unsorted_input() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input = pseudo_random(257, 13);
+        let mut sorted = input.clone();
+        sorted.sort_unstable();
+        assert!(sorter.is_sorted_gpu(&sorted).unwrap());
+        assert!(!sorter.is_sorted_gpu(&input).unwrap());
+        sorted.reverse();
+        assert!(!sorter.is_sorted_gpu(&sorted).unwrap());
+        assert!(sorter
+            .is_sorted_ordered_gpu(&sorted, SortOrder::Descending)
+            .unwrap());
+        // Equal neighbours are in order either way.
+        assert!(sorter.is_sorted_gpu(&[4; 100]).unwrap());
+        assert!(sorter
+            .is_sorted_ordered_gpu(&[4; 100], SortOrder::Descending)
+            .unwrap());
+        assert!(sorter.is_sorted_gpu(&[]).unwrap());
+    }
+}
@@ -0,0 +1,46 @@
+use crate::error::Result;
+use crate::launch::SortKernel;
+use crate::sorter::GpuSorter;
+use opencl3::event::Event;
+use opencl3::memory::Buffer;
+use opencl3::types::{cl_int, cl_uint};
+
+impl GpuSorter {
+    /// Computes the inclusive prefix sum of the first `n` elements of
+    /// `source`, ping-ponging between the two `scratch` buffers with one
+    /// kernel per doubling of the offset. Returns the scratch buffer holding
+    /// the sums and the event of the last step.
+    pub(crate) fn inclusive_scan<'a>(
+        &self,
+        source: &Buffer<cl_int>,
+        scratch: &'a [Buffer<cl_int>; 2],
+        n: usize,
+        wait: &Event,
+    ) -> Result<(&'a Buffer<cl_int>, Event)> {
+        let mut event = SortKernel::new(&self.scan_step_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(source)?
+            .buffer(&scratch[0])?
+            .scalar(&(1 as cl_uint))
+            .wait(wait)
+            .enqueue(&self.queue)?;
+
+        let mut current = 0;
+        let mut offset = 2;
+        while offset < n {
+            event = SortKernel::new(&self.scan_step_kernel, n)
+                .checked(self.checked)
+                .round_to(self.work_group_size)
+                .buffer(&scratch[current])?
+                .buffer(&scratch[1 - current])?
+                .scalar(&(offset as cl_uint))
+                .wait(&event)
+                .enqueue(&self.queue)?;
+            current = 1 - current;
+            offset *= 2;
+        }
+
+        Ok((&scratch[current], event))
+    }
+}
@@ -0,0 +1,1531 @@
+use crate::buffer::{fill_i32, map_into, read_into, upload, write_i32};
+use crate::builder::{GpuSorterBuilder, RankStrategy, ReadStrategy};
+use crate::counting::DEFAULT_COUNTING_BIN_CAP;
+use crate::error::{Result, SortError};
+use crate::kernels::*;
+use crate::launch::SortKernel;
+use crate::occupancy::KernelOccupancy;
+use crate::pool::BufferPool;
+use crate::probe::OpenClVersion;
+use crate::profiling::{event_duration_ns, phase_span, ProfilingReport};
+use opencl3::command_queue::{CommandQueue, CL_QUEUE_PROFILING_ENABLE};
+use opencl3::context::Context;
+use opencl3::device::{Device, CL_DEVICE_TYPE_GPU};
+use opencl3::error_codes::{
+    ClError, CL_DEVICE_NOT_FOUND, CL_INVALID_KERNEL_NAME, CL_PLATFORM_NOT_FOUND_KHR,
+};
+use opencl3::event::Event;
+use opencl3::kernel::Kernel;
+use opencl3::memory::{Buffer, CL_MEM_READ_WRITE, CL_MEM_WRITE_ONLY};
+use opencl3::platform::{get_platforms, Platform};
+use opencl3::program::{get_program_info, Program, CL_PROGRAM_BINARIES};
+use opencl3::types::{cl_device_id, cl_device_type, cl_int, cl_uint};
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::env;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Holds an OpenCL context, command queue and the compiled kernels so that
+/// repeated sorts don't rebuild the programs.
+///
+/// Every method returns only once its work is complete: results read back to
+/// the host come from blocking reads, and the [`GpuVec`] operations wait for
+/// their last kernel before returning, so their output can be used straight
+/// away. Work enqueued directly on [`GpuSorter::queue`] is the caller's to
+/// wait for, with [`GpuSorter::finish`] or its events.
+///
+/// A sorter can be moved to another thread but not shared between threads:
+/// it is `Send` but not `Sync`, as its buffer pool, lazily built kernels and
+/// second queue live in unsynchronised cells, and an OpenCL kernel object's
+/// arguments can't be set from two threads at once. Sorting from several
+/// threads takes one sorter per thread, which
+/// [`GpuSorter::clone_for_thread`] makes with the same device and settings.
+///
+/// [`GpuVec`]: crate::GpuVec
+pub struct GpuSorter {
+    pub(crate) context: Context,
+    pub(crate) queue: CommandQueue,
+    compare_kernel: Kernel,
+    pub(crate) assign_kernel: Kernel,
+    pub(crate) rank_u64_kernel: Kernel,
+    pub(crate) scatter_u64_kernel: Kernel,
+    scatter_index_kernel: Kernel,
+    pub(crate) rank_i16_kernel: Kernel,
+    pub(crate) scatter_i16_kernel: Kernel,
+    pub(crate) histogram_u8_kernel: Kernel,
+    pub(crate) expand_u8_kernel: Kernel,
+    pub(crate) radix_flags_kernel: Kernel,
+    pub(crate) scan_step_kernel: Kernel,
+    pub(crate) radix_scatter_kernel: Kernel,
+    pub(crate) reverse_kernel: Kernel,
+    pub(crate) unique_flags_kernel: Kernel,
+    pub(crate) compact_kernel: Kernel,
+    pub(crate) rank_equal_kernel: Kernel,
+    pub(crate) scatter_pairs_kernel: Kernel,
+    pub(crate) segmented_argsort_kernel: Kernel,
+    pub(crate) reduce_kernel: Kernel,
+    pub(crate) kway_merge_kernel: Kernel,
+    compare_1d_kernel: Kernel,
+    compare_tiled_kernel: Kernel,
+    pub(crate) histogram_i32_kernel: Kernel,
+    pub(crate) expand_i32_kernel: Kernel,
+    pub(crate) odd_even_kernel: Kernel,
+    pub(crate) bitonic_step_kernel: Kernel,
+    pub(crate) unsorted_kernel: Kernel,
+    pub(crate) pivot_flags_kernel: Kernel,
+    pub(crate) run_ends_kernel: Kernel,
+    pub(crate) f64_kernels: OnceCell<(Kernel, Kernel)>,
+    pub(crate) delta_kernels: OnceCell<(Kernel, Kernel)>,
+    compare_counted_kernel: OnceCell<Kernel>,
+    pub(crate) assign_queue: OnceCell<CommandQueue>,
+    pub(crate) pool: BufferPool,
+    pub(crate) rank_strategy: RankStrategy,
+    pub(crate) counting_bin_cap: usize,
+    pub(crate) checked: bool,
+    pub(crate) skip_if_sorted: bool,
+    pub(crate) work_group_size: Option<usize>,
+    pub(crate) read_strategy: ReadStrategy,
+    pub(crate) cancel_token: Option<Arc<AtomicBool>>,
+    pub(crate) kernel_dir: Option<PathBuf>,
+}
+
+/// The outcome of [`GpuSorter::sort_traced`].
+pub struct RankTrace {
+    /// The requested window of the sorted result.
+    pub result: Vec<i32>,
+    /// Each element's rank, as [`GpuSorter::ranks`] returns them, if they
+    /// were asked for.
+    pub ranks: Option<Vec<i32>>,
+    /// The compare and assign kernels' device times and the host wall-clock
+    /// time from the upload to the result's read, not counting the ranks'.
+    pub report: ProfilingReport,
+    /// Every command the sort enqueued, in order, named by its phase, to lay
+    /// out as a timeline from their profiling information.
+    pub events: Vec<(&'static str, Event)>,
+}
+
+/// How many of the compare kernel's `atomic_inc`s fired, from
+/// [`GpuSorter::compare_atomic_count`]. Every pair of elements fires exactly
+/// one of them, so the two add up to `n(n - 1) / 2`; the split shows how much
+/// of the contention comes from duplicates.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompareAtomics {
+    /// Increments for an element with a smaller value.
+    pub smaller: u64,
+    /// Increments for an equal element at a lower index, breaking the tie.
+    pub ties: u64,
+}
+
+impl CompareAtomics {
+    /// Every increment that fired.
+    pub fn total(&self) -> u64 {
+        self.smaller + self.ties
+    }
+}
+
+/// A kernel's name and its program's binary for each device.
+type KernelBinaries = (&'static str, Vec<Vec<u8>>);
+
+/// Below this many elements [`RankStrategy::Auto`] considers the atomic
+/// rank, whose O(n²) comparisons overtake the radix sort's fixed pass count
+/// somewhere past it.
+pub(crate) const AUTO_ATOMIC_MAX_LEN: usize = 2048;
+
+/// The largest tile [`RankStrategy::Tiled`] picks on its own.
+const COMPARE_TILE_MAX: usize = 256;
+
+/// What a checked sorter fills the assign kernel's result slots with until
+/// an element claims them.
+pub(crate) const UNFILLED: i32 = -1;
+
+/// The index of a slot of the assign kernel's result, read from `offset` on
+/// into `out`, that no element claimed, if there is one. Input values equal
+/// to [`UNFILLED`] look the same as an empty slot, so the positions a
+/// complete sort puts them at are taken to be genuine.
+pub(crate) fn unfilled_slot(input: &[i32], offset: usize, out: &[i32]) -> Option<usize> {
+    let before = input.iter().filter(|&&value| value < UNFILLED).count();
+    let genuine = before..before + input.iter().filter(|&&value| value == UNFILLED).count();
+    (offset..)
+        .zip(out)
+        .find(|&(index, &value)| value == UNFILLED && !genuine.contains(&index))
+        .map(|(index, _)| index)
+}
+
+/// Builds the kernel `name` from `source`, or from its file in `kernel_dir`
+/// if there is one, see [`kernel_source`].
+pub(crate) fn build_kernel(
+    context: &Context,
+    kernel_dir: Option<&Path>,
+    source: &str,
+    name: &str,
+) -> Result<Kernel> {
+    phase_span!("build", kernel = name);
+    let source = kernel_source(kernel_dir, name, source)?;
+    let program = Program::create_and_build_from_source(context, &source, BUILD_OPTIONS)
+        .map_err(SortError::Build)?;
+    Kernel::create(&program, name).map_err(|e| match (e.0, &source) {
+        (CL_INVALID_KERNEL_NAME, Cow::Owned(_)) => SortError::Build(format!(
+            "the external source for {} does not define a kernel of that name",
+            name
+        )),
+        _ => SortError::Cl(e),
+    })
+}
+
+/// Lists the OpenCL platforms, returning [`SortError::NoOpenCl`] if there
+/// are none rather than the raw ICD loader error.
+pub(crate) fn find_platforms() -> Result<Vec<Platform>> {
+    platforms_found(get_platforms())
+}
+
+/// The platforms `clGetPlatformIDs` listed, or [`SortError::NoOpenCl`] if
+/// the ICD loader found none, whether it said so with
+/// `CL_PLATFORM_NOT_FOUND_KHR` or with an empty list.
+fn platforms_found(
+    platforms: std::result::Result<Vec<Platform>, ClError>,
+) -> Result<Vec<Platform>> {
+    let platforms = platforms.map_err(|e| match e.0 {
+        CL_PLATFORM_NOT_FOUND_KHR => SortError::NoOpenCl,
+        _ => SortError::Cl(e),
+    })?;
+    if platforms.is_empty() {
+        return Err(SortError::NoOpenCl);
+    }
+    Ok(platforms)
+}
+
+/// The platform at `index` in the ICD loader's enumeration order. Returns
+/// [`SortError::PlatformIndex`], listing every platform, if there is none.
+pub(crate) fn platform_at(index: usize) -> Result<Platform> {
+    let mut platforms = find_platforms()?;
+    if index < platforms.len() {
+        return Ok(platforms.swap_remove(index));
+    }
+    let available = platforms
+        .iter()
+        .map(Platform::name)
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Err(SortError::PlatformIndex { index, available })
+}
+
+/// Lists the devices of `device_type` on `platforms`, skipping platforms
+/// that have none of that type.
+pub(crate) fn devices_on(
+    platforms: &[Platform],
+    device_type: cl_device_type,
+) -> Result<Vec<cl_device_id>> {
+    let mut device_ids = Vec::new();
+    for platform in platforms {
+        match platform.get_devices(device_type) {
+            Ok(mut devices) => device_ids.append(&mut devices),
+            Err(e) if e.0 == CL_DEVICE_NOT_FOUND => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(device_ids)
+}
+
+/// Lists the devices of `device_type` on every platform.
+///
+/// Returns [`SortError::NoOpenCl`] if there are no platforms at all, and
+/// skips platforms that have no device of the requested type.
+pub fn find_devices(device_type: cl_device_type) -> Result<Vec<cl_device_id>> {
+    devices_on(&find_platforms()?, device_type)
+}
+
+/// Lists the devices of `device_type` on the platform at `platform_index`
+/// in enumeration order, returning [`SortError::PlatformIndex`] if there is
+/// no such platform.
+pub fn find_devices_on(
+    platform_index: usize,
+    device_type: cl_device_type,
+) -> Result<Vec<cl_device_id>> {
+    devices_on(&[platform_at(platform_index)?], device_type)
+}
+
+thread_local! {
+    /// The sorter [`gpu_sort`] and [`gpu_sort_bitonic`] reuse on this thread.
+    static DEFAULT_SORTER: OnceCell<GpuSorter> = const { OnceCell::new() };
+}
+
+/// Runs `f` on this thread's default sorter, creating it with
+/// [`GpuSorter::new`] on first use. A failed creation isn't cached, so the
+/// next call tries again.
+fn with_default_sorter<T>(f: impl FnOnce(&GpuSorter) -> Result<T>) -> Result<T> {
+    DEFAULT_SORTER.with(|cell| {
+        let sorter = match cell.get() {
+            Some(sorter) => sorter,
+            None => {
+                let sorter = GpuSorter::new()?;
+                cell.get_or_init(|| sorter)
+            }
+        };
+        f(sorter)
+    })
+}
+
+/// Sorts `input` ascending on the first GPU found, in one call.
+///
+/// The first call on a thread sets up a context and queue and builds every
+/// kernel, which costs far more than a small sort; later calls on the same
+/// thread reuse that sorter, with its kernels and buffer pool. It keeps the
+/// device and [`KERNEL_DIR_VAR`] it was created with. For any other settings,
+/// create a [`GpuSorter`] and call [`GpuSorter::sort`] on it. Empty and
+/// single-element inputs are returned straight away, without looking for a
+/// device.
+pub fn gpu_sort(input: &[i32]) -> Result<Vec<i32>> {
+    if input.len() < 2 {
+        return Ok(input.to_vec());
+    }
+    with_default_sorter(|sorter| sorter.sort(input))
+}
+
+/// Like [`gpu_sort`], but sorts with [`GpuSorter::sort_bitonic`], which
+/// scales to arrays the rank sort's O(n²) comparisons can't.
+pub fn gpu_sort_bitonic(input: &[i32]) -> Result<Vec<i32>> {
+    if input.len() < 2 {
+        return Ok(input.to_vec());
+    }
+    with_default_sorter(|sorter| sorter.sort_bitonic(input))
+}
+
+/// Returns [`SortError::InvalidRange`] unless `window` is a range of
+/// `0..len`, as the windowed sorts take.
+pub(crate) fn check_window(window: &Range<usize>, len: usize) -> Result<()> {
+    if window.start > window.end || window.end > len {
+        return Err(SortError::InvalidRange {
+            start: window.start,
+            end: window.end,
+            len,
+        });
+    }
+    Ok(())
+}
+
+impl GpuSorter {
+    /// Creates a sorter on the first GPU device found.
+    pub fn new() -> Result<Self> {
+        let device_id = *find_devices(CL_DEVICE_TYPE_GPU)?
+            .first()
+            .ok_or(SortError::NoDevice)?;
+        Self::from_device(&Device::new(device_id))
+    }
+
+    /// Returns a builder for configuring a sorter before creating it.
+    pub fn builder() -> GpuSorterBuilder {
+        GpuSorterBuilder::new()
+    }
+
+    /// Creates a sorter on `device`, building all of the kernels for it.
+    ///
+    /// If the [`KERNEL_DIR_VAR`] environment variable names a directory, the
+    /// kernels with a source file there are built from it instead of the
+    /// embedded source.
+    pub fn from_device(device: &Device) -> Result<Self> {
+        Self::with_kernel_dir(device, env::var_os(KERNEL_DIR_VAR).map(PathBuf::from))
+    }
+
+    /// Like [`GpuSorter::from_device`], but loads kernel sources from
+    /// `kernel_dir` rather than the environment.
+    pub(crate) fn with_kernel_dir(device: &Device, kernel_dir: Option<PathBuf>) -> Result<Self> {
+        let dir = kernel_dir.as_deref();
+        let context = Context::from_device(device)?;
+        let queue = CommandQueue::create(
+            &context,
+            context.default_device(),
+            CL_QUEUE_PROFILING_ENABLE,
+        )?;
+
+        let compare_kernel = build_kernel(&context, dir, COMPARE_KERNEL, COMPARE_KERNEL_NAME)?;
+        let assign_kernel = build_kernel(&context, dir, ASSIGN_KERNEL, ASSIGN_KERNEL_NAME)?;
+        let rank_u64_kernel = build_kernel(&context, dir, RANK_U64_KERNEL, RANK_U64_KERNEL_NAME)?;
+        let scatter_u64_kernel =
+            build_kernel(&context, dir, SCATTER_U64_KERNEL, SCATTER_U64_KERNEL_NAME)?;
+        let scatter_index_kernel = build_kernel(
+            &context,
+            dir,
+            SCATTER_INDEX_KERNEL,
+            SCATTER_INDEX_KERNEL_NAME,
+        )?;
+        let rank_i16_kernel = build_kernel(&context, dir, RANK_I16_KERNEL, RANK_I16_KERNEL_NAME)?;
+        let scatter_i16_kernel =
+            build_kernel(&context, dir, SCATTER_I16_KERNEL, SCATTER_I16_KERNEL_NAME)?;
+        let histogram_u8_kernel =
+            build_kernel(&context, dir, HISTOGRAM_U8_KERNEL, HISTOGRAM_U8_KERNEL_NAME)?;
+        let expand_u8_kernel =
+            build_kernel(&context, dir, EXPAND_U8_KERNEL, EXPAND_U8_KERNEL_NAME)?;
+        let radix_flags_kernel =
+            build_kernel(&context, dir, RADIX_FLAGS_KERNEL, RADIX_FLAGS_KERNEL_NAME)?;
+        let scan_step_kernel =
+            build_kernel(&context, dir, SCAN_STEP_KERNEL, SCAN_STEP_KERNEL_NAME)?;
+        let radix_scatter_kernel = build_kernel(
+            &context,
+            dir,
+            RADIX_SCATTER_KERNEL,
+            RADIX_SCATTER_KERNEL_NAME,
+        )?;
+        let reverse_kernel = build_kernel(&context, dir, REVERSE_KERNEL, REVERSE_KERNEL_NAME)?;
+        let unique_flags_kernel =
+            build_kernel(&context, dir, UNIQUE_FLAGS_KERNEL, UNIQUE_FLAGS_KERNEL_NAME)?;
+        let compact_kernel = build_kernel(&context, dir, COMPACT_KERNEL, COMPACT_KERNEL_NAME)?;
+        let rank_equal_kernel =
+            build_kernel(&context, dir, RANK_EQUAL_KERNEL, RANK_EQUAL_KERNEL_NAME)?;
+        let scatter_pairs_kernel = build_kernel(
+            &context,
+            dir,
+            SCATTER_PAIRS_KERNEL,
+            SCATTER_PAIRS_KERNEL_NAME,
+        )?;
+        let segmented_argsort_kernel = build_kernel(
+            &context,
+            dir,
+            SEGMENTED_ARGSORT_KERNEL,
+            SEGMENTED_ARGSORT_KERNEL_NAME,
+        )?;
+        let reduce_kernel = build_kernel(&context, dir, REDUCE_KERNEL, REDUCE_KERNEL_NAME)?;
+        let kway_merge_kernel =
+            build_kernel(&context, dir, KWAY_MERGE_KERNEL, KWAY_MERGE_KERNEL_NAME)?;
+        let compare_1d_kernel =
+            build_kernel(&context, dir, COMPARE_1D_KERNEL, COMPARE_1D_KERNEL_NAME)?;
+        let compare_tiled_kernel = build_kernel(
+            &context,
+            dir,
+            COMPARE_TILED_KERNEL,
+            COMPARE_TILED_KERNEL_NAME,
+        )?;
+        let histogram_i32_kernel = build_kernel(
+            &context,
+            dir,
+            HISTOGRAM_I32_KERNEL,
+            HISTOGRAM_I32_KERNEL_NAME,
+        )?;
+        let expand_i32_kernel =
+            build_kernel(&context, dir, EXPAND_I32_KERNEL, EXPAND_I32_KERNEL_NAME)?;
+        let odd_even_kernel = build_kernel(&context, dir, ODD_EVEN_KERNEL, ODD_EVEN_KERNEL_NAME)?;
+        let bitonic_step_kernel =
+            build_kernel(&context, dir, BITONIC_STEP_KERNEL, BITONIC_STEP_KERNEL_NAME)?;
+        let unsorted_kernel = build_kernel(&context, dir, UNSORTED_KERNEL, UNSORTED_KERNEL_NAME)?;
+        let pivot_flags_kernel =
+            build_kernel(&context, dir, PIVOT_FLAGS_KERNEL, PIVOT_FLAGS_KERNEL_NAME)?;
+        let run_ends_kernel = build_kernel(&context, dir, RUN_ENDS_KERNEL, RUN_ENDS_KERNEL_NAME)?;
+
+        Ok(GpuSorter {
+            context,
+            queue,
+            compare_kernel,
+            assign_kernel,
+            rank_u64_kernel,
+            scatter_u64_kernel,
+            scatter_index_kernel,
+            rank_i16_kernel,
+            scatter_i16_kernel,
+            histogram_u8_kernel,
+            expand_u8_kernel,
+            radix_flags_kernel,
+            scan_step_kernel,
+            radix_scatter_kernel,
+            reverse_kernel,
+            unique_flags_kernel,
+            compact_kernel,
+            rank_equal_kernel,
+            scatter_pairs_kernel,
+            segmented_argsort_kernel,
+            reduce_kernel,
+            kway_merge_kernel,
+            compare_1d_kernel,
+            compare_tiled_kernel,
+            histogram_i32_kernel,
+            expand_i32_kernel,
+            odd_even_kernel,
+            bitonic_step_kernel,
+            unsorted_kernel,
+            pivot_flags_kernel,
+            run_ends_kernel,
+            f64_kernels: OnceCell::new(),
+            delta_kernels: OnceCell::new(),
+            compare_counted_kernel: OnceCell::new(),
+            assign_queue: OnceCell::new(),
+            pool: BufferPool::new(),
+            rank_strategy: RankStrategy::default(),
+            counting_bin_cap: DEFAULT_COUNTING_BIN_CAP,
+            checked: true,
+            skip_if_sorted: false,
+            work_group_size: None,
+            read_strategy: ReadStrategy::default(),
+            kernel_dir,
+            cancel_token: None,
+        })
+    }
+
+    /// A new sorter on the same device with the same settings, to be moved to
+    /// another thread so that both can sort at once. It has its own context,
+    /// queue, kernels and buffer pool, as none of these can be shared
+    /// between threads, so buffers from one sorter's [`GpuSorter::context`]
+    /// can't be passed to the other. A cancel token is shared, so cancelling
+    /// stops both.
+    pub fn clone_for_thread(&self) -> Result<GpuSorter> {
+        let device = Device::new(self.context.default_device());
+        Ok(GpuSorter {
+            rank_strategy: self.rank_strategy,
+            counting_bin_cap: self.counting_bin_cap,
+            checked: self.checked,
+            skip_if_sorted: self.skip_if_sorted,
+            work_group_size: self.work_group_size,
+            read_strategy: self.read_strategy,
+            cancel_token: self.cancel_token.clone(),
+            pool: BufferPool::with_max_bytes(self.pool.max_bytes()),
+            ..Self::with_kernel_dir(&device, self.kernel_dir.clone())?
+        })
+    }
+
+    /// The context buffers passed to the on-device operations must belong to.
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// The queue the sorter's commands are enqueued on, all but the assign
+    /// kernels of [`GpuSorter::sort_batch_pipelined`].
+    pub fn queue(&self) -> &CommandQueue {
+        &self.queue
+    }
+
+    /// The pool that scratch buffers are recycled through between sorts.
+    pub fn buffer_pool(&self) -> &BufferPool {
+        &self.pool
+    }
+
+    /// The strategy [`GpuSorter::sort`] uses, set through the builder.
+    pub fn rank_strategy(&self) -> RankStrategy {
+        self.rank_strategy
+    }
+
+    /// The most bins [`GpuSorter::sort_counting`] allocates, set through the
+    /// builder.
+    pub fn counting_bin_cap(&self) -> usize {
+        self.counting_bin_cap
+    }
+
+    /// Whether kernel launches validate their buffers' sizes, set through
+    /// the builder.
+    pub fn checked(&self) -> bool {
+        self.checked
+    }
+
+    /// The work-group size every built-in kernel's global size is rounded up
+    /// to a multiple of, if one was set through the builder.
+    pub fn work_group_size(&self) -> Option<usize> {
+        self.work_group_size
+    }
+
+    /// How results are read back to the host, set through the builder.
+    pub fn read_strategy(&self) -> ReadStrategy {
+        self.read_strategy
+    }
+
+    /// Reads `out.len()` elements of `buf` starting at element `offset` into
+    /// `out` once `wait` has completed, with the sorter's [`ReadStrategy`].
+    /// Only that range is transferred from the device. Every result a sort
+    /// returns is read back through this. Returns the event of the read, or
+    /// of the map, or `None` if `out` is empty and nothing was read.
+    pub(crate) fn read_result<T: Copy>(
+        &self,
+        buf: &Buffer<T>,
+        offset: usize,
+        out: &mut [T],
+        wait: &Event,
+    ) -> Result<Option<Event>> {
+        match self.read_strategy {
+            ReadStrategy::Copy => read_into(&self.queue, buf, offset, out, wait),
+            ReadStrategy::Map => map_into(&self.queue, buf, offset, out, wait),
+        }
+    }
+
+    /// Like [`GpuSorter::read_result`], but reads `len` elements into a new
+    /// vector.
+    pub(crate) fn read_result_range<T: Copy + Default>(
+        &self,
+        buf: &Buffer<T>,
+        offset: usize,
+        len: usize,
+        wait: &Event,
+    ) -> Result<Vec<T>> {
+        let mut values = vec![T::default(); len];
+        self.read_result(buf, offset, &mut values, wait)?;
+        Ok(values)
+    }
+
+    /// Returns [`SortError::UnsupportedOpenClVersion`] unless the device
+    /// compiles OpenCL C `required` or newer. Check this before running
+    /// kernels that use newer features, such as replacements loaded from a
+    /// kernel directory, so they fail clearly rather than with a build log.
+    pub fn require_opencl_version(&self, required: OpenClVersion) -> Result<()> {
+        let found = OpenClVersion::of_device(&Device::new(self.context.default_device()))?;
+        if found < required {
+            return Err(SortError::UnsupportedOpenClVersion { required, found });
+        }
+        Ok(())
+    }
+
+    /// Blocks until every command enqueued on the sorter's queue has
+    /// completed. The sorter's own methods don't need this, but commands
+    /// enqueued on [`GpuSorter::queue`] by the caller might.
+    pub fn finish(&self) -> Result<()> {
+        Ok(self.queue.finish()?)
+    }
+
+    /// Returns [`SortError::Cancelled`], once the queue has drained, if the
+    /// cancellation token has been set.
+    pub(crate) fn check_cancelled(&self) -> Result<()> {
+        match &self.cancel_token {
+            Some(token) if token.load(Ordering::Relaxed) => {
+                self.queue.finish()?;
+                Err(SortError::Cancelled)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sorts `input` ascending on the device, using the sorter's
+    /// [`RankStrategy`].
+    pub fn sort(&self, input: &[i32]) -> Result<Vec<i32>> {
+        let n = input.len();
+        if n < 2 {
+            return Ok(input.to_vec());
+        }
+
+        let mut result = vec![0; n];
+        self.sort_into(input, 0, &mut result)?;
+        Ok(result)
+    }
+
+    /// Like [`GpuSorter::sort`], but returns only `window` of the sorted
+    /// result, and only transfers that much of it from the device.
+    ///
+    /// Returns [`SortError::InvalidRange`] if `window` doesn't fit in
+    /// `input`.
+    pub fn sort_window(&self, input: &[i32], window: Range<usize>) -> Result<Vec<i32>> {
+        check_window(&window, input.len())?;
+        if input.len() < 2 {
+            return Ok(input[window].to_vec());
+        }
+
+        let mut result = vec![0; window.len()];
+        self.sort_into(input, window.start, &mut result)?;
+        Ok(result)
+    }
+
+    /// Like [`GpuSorter::sort`], but writes the result into `out`, reusing
+    /// its capacity so repeated calls with similar sizes don't allocate.
+    /// Whatever `out` held before is discarded.
+    pub fn sort_collect_into(&self, input: &[i32], out: &mut Vec<i32>) -> Result<()> {
+        out.clear();
+        if input.len() < 2 {
+            out.extend_from_slice(input);
+            return Ok(());
+        }
+
+        out.resize(input.len(), 0);
+        self.sort_into(input, 0, out)
+    }
+
+    /// Sorts `input`, of at least two elements, writing the sorted result
+    /// from element `offset` on into `out`, which must fit within it.
+    fn sort_into(&self, input: &[i32], offset: usize, out: &mut [i32]) -> Result<()> {
+        let n = input.len();
+        if self.skip_if_sorted && self.is_sorted_gpu(input)? {
+            out.copy_from_slice(&input[offset..offset + out.len()]);
+            return Ok(());
+        }
+        match self.rank_strategy {
+            RankStrategy::Atomic | RankStrategy::Loop | RankStrategy::Tiled => {
+                self.sort_atomic(input, offset, out)
+            }
+            RankStrategy::Auto if n <= AUTO_ATOMIC_MAX_LEN => self.sort_atomic(input, offset, out),
+            RankStrategy::Scan | RankStrategy::Auto => {
+                let (sorted, event) = self.radix_sort_buffer(input, None)?;
+                self.read_result(&sorted, offset, out, &event)?;
+                self.pool.give(n, CL_MEM_READ_WRITE, sorted);
+                Ok(())
+            }
+        }
+    }
+
+    /// Enqueues the kernel that writes each element's rank to the zeroed
+    /// `count_buffer`: the n × n atomic compare, or with
+    /// [`RankStrategy::Loop`] the 1D kernel that counts in a register, or
+    /// with [`RankStrategy::Tiled`] its local-memory twin.
+    pub(crate) fn enqueue_compare(
+        &self,
+        input_buffer: &Buffer<cl_int>,
+        count_buffer: &Buffer<cl_int>,
+        n: usize,
+        wait: &Event,
+    ) -> Result<Event> {
+        let (launch, tile) = match self.rank_strategy {
+            RankStrategy::Loop => (SortKernel::new(&self.compare_1d_kernel, n), None),
+            RankStrategy::Tiled => (
+                SortKernel::new(&self.compare_tiled_kernel, n),
+                Some(self.compare_tile()?),
+            ),
+            _ => (SortKernel::pairs(&self.compare_kernel, n), None),
+        };
+        let mut launch = launch
+            .checked(self.checked)
+            .round_to(tile.or(self.work_group_size))
+            .buffer(input_buffer)?
+            .buffer(count_buffer)?;
+        if let Some(tile) = tile {
+            launch = launch.local_ints(tile);
+        }
+        launch.wait(wait).enqueue(&self.queue)
+    }
+
+    /// The work-group and tile size of the tiled compare kernel: the fixed
+    /// work-group size if there is one, and otherwise the largest the kernel
+    /// can run with on this device, up to [`COMPARE_TILE_MAX`].
+    fn compare_tile(&self) -> Result<usize> {
+        if let Some(local) = self.work_group_size {
+            return Ok(local);
+        }
+        let max = self
+            .compare_tiled_kernel
+            .get_work_group_size(self.queue.device()?)?;
+        Ok(max.clamp(1, COMPARE_TILE_MAX))
+    }
+
+    /// Rank sorts `input` with the compare and assign kernels, reading the
+    /// result from element `offset` on into `out`, which must fit within it.
+    ///
+    /// A checked sorter first fills the result with [`UNFILLED`] and returns
+    /// [`SortError::IncompleteSort`] if a slot is left holding it, which only
+    /// a compare kernel whose ranks aren't a permutation, such as one loaded
+    /// from a kernel directory, can cause.
+    pub(crate) fn sort_atomic(&self, input: &[i32], offset: usize, out: &mut [i32]) -> Result<()> {
+        let n = input.len();
+
+        let input_buffer = upload(&self.context, input)?;
+        let mut count_buffer = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+        let mut result_buffer = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+
+        let count_fill_event = fill_i32(&self.queue, &mut count_buffer, 0, n)?;
+        let result_fill_event = self
+            .checked
+            .then(|| fill_i32(&self.queue, &mut result_buffer, UNFILLED, n))
+            .transpose()?;
+
+        let compare_kernel_event =
+            self.enqueue_compare(&input_buffer, &count_buffer, n, &count_fill_event)?;
+
+        let mut assign = SortKernel::new(&self.assign_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&input_buffer)?
+            .buffer(&count_buffer)?
+            .buffer(&result_buffer)?
+            .wait(&compare_kernel_event);
+        if let Some(event) = &result_fill_event {
+            assign = assign.wait(event);
+        }
+        let assign_kernel_event = assign.enqueue(&self.queue)?;
+
+        self.read_result(&result_buffer, offset, out, &assign_kernel_event)?;
+
+        self.pool.give(n, CL_MEM_READ_WRITE, count_buffer);
+        self.pool.give(n, CL_MEM_READ_WRITE, result_buffer);
+        if self.checked {
+            if let Some(index) = unfilled_slot(input, offset, out) {
+                return Err(SortError::IncompleteSort { index });
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs only the compare kernel and returns the count buffer, which holds
+    /// each element's rank: the number of elements smaller than it plus the
+    /// number of equal ones before it, so the ranks are a permutation of
+    /// `0..n` giving each element's position in a stable sort.
+    pub fn ranks(&self, input: &[i32]) -> Result<Vec<i32>> {
+        Ok(self.ranks_profiled(input)?.0)
+    }
+
+    /// Like [`GpuSorter::ranks`], also returning the compare kernel's device
+    /// time as `compare_ns`, if the driver reports it, and the host
+    /// wall-clock time of the whole call. Every [`RankStrategy`] but
+    /// [`RankStrategy::Scan`] and [`RankStrategy::Auto`] has its own compare
+    /// kernel, and all of them fill the same counts, so this times them
+    /// against each other; the other two use the atomic one.
+    pub fn ranks_profiled(&self, input: &[i32]) -> Result<(Vec<i32>, ProfilingReport)> {
+        let n = input.len();
+        if n < 2 {
+            return Ok((vec![0; n], ProfilingReport::default()));
+        }
+
+        let start = SystemTime::now();
+        let input_buffer = upload(&self.context, input)?;
+        let mut count_buffer = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+
+        let count_fill_event = fill_i32(&self.queue, &mut count_buffer, 0, n)?;
+
+        let compare_kernel_event =
+            self.enqueue_compare(&input_buffer, &count_buffer, n, &count_fill_event)?;
+
+        let ranks = self.read_result_range(&count_buffer, 0, n, &compare_kernel_event)?;
+        let end = SystemTime::now();
+
+        self.pool.give(n, CL_MEM_READ_WRITE, count_buffer);
+        let report = ProfilingReport {
+            compare_ns: event_duration_ns(&compare_kernel_event),
+            assign_ns: None,
+            host_wall_ns: end.duration_since(start).unwrap_or_default().as_nanos() as u64,
+        };
+        Ok((ranks, report))
+    }
+
+    /// Rank sorts `input` like [`GpuSorter::sort`] with the
+    /// [`RankStrategy::Atomic`], [`RankStrategy::Loop`] or
+    /// [`RankStrategy::Tiled`] compare kernel, whichever the sorter uses
+    /// (the atomic one for the other strategies), keeping what a profiler
+    /// wants to see: the event of every command, with only `window` of the
+    /// result read back, and the ranks too if `keep_ranks` is set.
+    ///
+    /// Returns [`SortError::InvalidRange`] if `window` doesn't fit in
+    /// `input`. Empty and single-element inputs are returned without
+    /// touching the device, so with no events.
+    pub fn sort_traced(
+        &self,
+        input: &[i32],
+        window: Range<usize>,
+        keep_ranks: bool,
+    ) -> Result<RankTrace> {
+        let n = input.len();
+        check_window(&window, n)?;
+        if n < 2 {
+            return Ok(RankTrace {
+                result: input[window].to_vec(),
+                ranks: keep_ranks.then(|| vec![0; n]),
+                report: ProfilingReport::default(),
+                events: Vec::new(),
+            });
+        }
+
+        let mut buffers = [
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+        ];
+        // The buffers go back to the pool whether or not the sort finished.
+        let trace = self.rank_traced(input, &mut buffers, window, keep_ranks);
+        for buffer in buffers {
+            self.pool.give(n, CL_MEM_READ_WRITE, buffer);
+        }
+        trace
+    }
+
+    /// [`GpuSorter::sort_traced`] with its input, count and result buffers,
+    /// each `input.len()` elements long.
+    fn rank_traced(
+        &self,
+        input: &[i32],
+        [input_buffer, count_buffer, result_buffer]: &mut [Buffer<cl_int>; 3],
+        window: Range<usize>,
+        keep_ranks: bool,
+    ) -> Result<RankTrace> {
+        let n = input.len();
+        let start = SystemTime::now();
+        let input_event = write_i32(&self.queue, input_buffer, "input", input)?;
+        let count_event = fill_i32(&self.queue, count_buffer, 0, n)?;
+        let compare_event = self.enqueue_compare(input_buffer, count_buffer, n, &count_event)?;
+        let assign_event = SortKernel::new(&self.assign_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(input_buffer)?
+            .buffer(count_buffer)?
+            .buffer(result_buffer)?
+            .wait(&compare_event)
+            .enqueue(&self.queue)?;
+
+        // Only the window is transferred.
+        let mut result = vec![0; window.len()];
+        let read_event =
+            self.read_result(result_buffer, window.start, &mut result, &assign_event)?;
+        let end = SystemTime::now();
+
+        let report = ProfilingReport {
+            compare_ns: event_duration_ns(&compare_event),
+            assign_ns: event_duration_ns(&assign_event),
+            host_wall_ns: end.duration_since(start).unwrap_or_default().as_nanos() as u64,
+        };
+        let (ranks, ranks_event) = if keep_ranks {
+            let mut ranks = vec![0; n];
+            let ranks_event = self.read_result(count_buffer, 0, &mut ranks, &assign_event)?;
+            (Some(ranks), ranks_event)
+        } else {
+            (None, None)
+        };
+        let mut events = vec![
+            ("write input", input_event),
+            ("write count", count_event),
+            ("compare", compare_event),
+            ("assign", assign_event),
+        ];
+        events.extend(read_event.map(|event| ("read result", event)));
+        events.extend(ranks_event.map(|event| ("read ranks", event)));
+        Ok(RankTrace {
+            result,
+            ranks,
+            report,
+            events,
+        })
+    }
+
+    /// The compare kernel [`GpuSorter::sort`] launches for the sorter's
+    /// [`RankStrategy`] when it rank sorts, with its name.
+    fn rank_compare_kernel(&self) -> (&Kernel, &'static str) {
+        match self.rank_strategy {
+            RankStrategy::Loop => (&self.compare_1d_kernel, COMPARE_1D_KERNEL_NAME),
+            RankStrategy::Tiled => (&self.compare_tiled_kernel, COMPARE_TILED_KERNEL_NAME),
+            _ => (&self.compare_kernel, COMPARE_KERNEL_NAME),
+        }
+    }
+
+    /// The work-group limits on the sorter's device of the compare kernel
+    /// [`GpuSorter::sort_traced`] launches and of the assign kernel.
+    pub fn rank_occupancy(&self) -> Result<(KernelOccupancy, KernelOccupancy)> {
+        let device = Device::new(self.context.default_device());
+        Ok((
+            KernelOccupancy::query(self.rank_compare_kernel().0, &device)?,
+            KernelOccupancy::query(&self.assign_kernel, &device)?,
+        ))
+    }
+
+    /// The compiled programs of the compare kernel
+    /// [`GpuSorter::sort_traced`] launches and of the assign kernel, each
+    /// with its kernel's name and one binary per device of the context, in
+    /// the vendor's format.
+    pub fn rank_binaries(&self) -> Result<Vec<KernelBinaries>> {
+        let (compare_kernel, compare_name) = self.rank_compare_kernel();
+        [
+            (compare_kernel, compare_name),
+            (&self.assign_kernel, ASSIGN_KERNEL_NAME),
+        ]
+        .into_iter()
+        .map(|(kernel, name)| {
+            let binaries =
+                get_program_info(kernel.program()?, CL_PROGRAM_BINARIES).map_err(ClError)?;
+            Ok((name, binaries.into()))
+        })
+        .collect()
+    }
+
+    /// Runs the compare kernel's instrumented twin and returns how many
+    /// `atomic_inc`s on the count buffer fired, split into those for a
+    /// smaller value and those breaking a tie between equal values. This is
+    /// a diagnostic for atomic contention; the kernel is only built the
+    /// first time it is needed. The device counters are 32-bit, so each
+    /// wraps beyond `u32::MAX`.
+    pub fn compare_atomic_count(&self, input: &[i32]) -> Result<CompareAtomics> {
+        let n = input.len();
+        if n < 2 {
+            return Ok(CompareAtomics::default());
+        }
+
+        if self.compare_counted_kernel.get().is_none() {
+            let kernel = build_kernel(
+                &self.context,
+                self.kernel_dir.as_deref(),
+                COMPARE_COUNTED_KERNEL,
+                COMPARE_COUNTED_KERNEL_NAME,
+            )?;
+            let _ = self.compare_counted_kernel.set(kernel);
+        }
+        let kernel = self.compare_counted_kernel.get().unwrap();
+
+        let input_buffer = upload(&self.context, input)?;
+        let mut count_buffer = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+        let mut total_buffer = self.pool.take(&self.context, 2, CL_MEM_READ_WRITE)?;
+
+        fill_i32(&self.queue, &mut count_buffer, 0, n)?;
+        let total_fill_event = fill_i32(&self.queue, &mut total_buffer, 0, 2)?;
+
+        let compare_kernel_event = SortKernel::pairs(kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&input_buffer)?
+            .buffer(&count_buffer)?
+            .buffer_of(&total_buffer, 2)?
+            .wait(&total_fill_event)
+            .enqueue(&self.queue)?;
+
+        let totals = self.read_result_range(&total_buffer, 0, 2, &compare_kernel_event)?;
+        self.pool.give(n, CL_MEM_READ_WRITE, count_buffer);
+        self.pool.give(2, CL_MEM_READ_WRITE, total_buffer);
+        Ok(CompareAtomics {
+            smaller: totals[0] as u32 as u64,
+            ties: totals[1] as u32 as u64,
+        })
+    }
+
+    /// Sorts `data[range]` in place, leaving the elements outside the range
+    /// untouched. Only the subrange is transferred to the device.
+    pub fn sort_range_in_place(&self, data: &mut [i32], range: Range<usize>) -> Result<()> {
+        if range.start > range.end || range.end > data.len() {
+            return Err(SortError::InvalidRange {
+                start: range.start,
+                end: range.end,
+                len: data.len(),
+            });
+        }
+
+        let sorted = self.sort(&data[range.clone()])?;
+        data[range].copy_from_slice(&sorted);
+        Ok(())
+    }
+
+    /// Sorts the `count` elements of `data` at `start`, `start + stride`,
+    /// `start + 2 * stride` and so on, such as one column of a row-major
+    /// matrix. The elements are gathered on the host into a contiguous
+    /// upload, and returned sorted.
+    pub fn sort_strided(
+        &self,
+        data: &[i32],
+        start: usize,
+        stride: usize,
+        count: usize,
+    ) -> Result<Vec<i32>> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let last = (count - 1)
+            .checked_mul(stride)
+            .and_then(|offset| offset.checked_add(start));
+        if last.is_none_or(|last| last >= data.len()) {
+            return Err(SortError::InvalidRange {
+                start,
+                end: last.map_or(usize::MAX, |last| last.saturating_add(1)),
+                len: data.len(),
+            });
+        }
+
+        let gathered: Vec<i32> = (0..count).map(|i| data[start + i * stride]).collect();
+        self.sort(&gathered)
+    }
+
+    /// Returns the permutation that sorts `keys` ascending, so that
+    /// `keys[indices[0]] <= keys[indices[1]] <= ...`. Equal keys keep their
+    /// original relative order.
+    pub(crate) fn argsort_u64(&self, keys: &[u64]) -> Result<Vec<u32>> {
+        let n = keys.len();
+        if n < 2 {
+            return Ok((0..n as u32).collect());
+        }
+
+        let key_buffer = upload(&self.context, keys)?;
+        let mut rank_buffer = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+        let index_buffer =
+            Buffer::<cl_uint>::create(&self.context, CL_MEM_WRITE_ONLY, n, ptr::null_mut())?;
+
+        // Pooled rank buffers still hold the previous sort's ranks.
+        let rank_fill_event = fill_i32(&self.queue, &mut rank_buffer, 0, n)?;
+
+        let rank_kernel_event = SortKernel::pairs(&self.rank_u64_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&key_buffer)?
+            .buffer(&rank_buffer)?
+            .wait(&rank_fill_event)
+            .enqueue(&self.queue)?;
+
+        let scatter_kernel_event = SortKernel::new(&self.scatter_index_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&rank_buffer)?
+            .buffer(&index_buffer)?
+            .wait(&rank_kernel_event)
+            .enqueue(&self.queue)?;
+
+        let indices = self.read_result_range(&index_buffer, 0, n, &scatter_kernel_event)?;
+
+        self.pool.give(n, CL_MEM_READ_WRITE, rank_buffer);
+        Ok(indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{pseudo_random, temp_dir, test_sorter, test_sorter_with};
+    use std::fs;
+
+    /// The compare kernels that break ties by index.
+    const COMPARE_STRATEGIES: [RankStrategy; 3] = [
+        RankStrategy::Atomic,
+        RankStrategy::Loop,
+        RankStrategy::Tiled,
+    ];
+
+    /// Sorts `input` with every compare kernel, checking the result against
+    /// the host's sort and that the ranks are a permutation of `0..n`.
+    fn check_rank_sort(input: &[i32]) {
+        let mut expected = input.to_vec();
+        expected.sort_unstable();
+        for strategy in COMPARE_STRATEGIES {
+            let Some(sorter) = test_sorter_with(GpuSorter::builder().rank_strategy(strategy))
+            else {
+                return;
+            };
+            assert_eq!(sorter.sort(input).unwrap(), expected, "{:?}", strategy);
+
+            let mut ranks = sorter.ranks(input).unwrap();
+            ranks.sort_unstable();
+            let identity: Vec<i32> = (0..input.len() as i32).collect();
+            assert_eq!(ranks, identity, "{:?} gave two elements one rank", strategy);
+        }
+    }
+
+    #[test]
+    fn sort_with_duplicates() {
+        let input: Vec<i32> = pseudo_random(1000, 7).iter().map(|v| v % 10).collect();
+        check_rank_sort(&input);
+    }
+
+    #[test]
+    fn sort_all_equal() {
+        check_rank_sort(&[42; 513]);
+    }
+
+    #[test]
+    fn sort_extreme_values() {
+        let mut input = vec![
+            i32::MAX,
+            i32::MIN,
+            0,
+            -1,
+            1,
+            i32::MAX,
+            i32::MIN,
+            i32::MIN + 1,
+        ];
+        input.extend(pseudo_random(100, 3));
+        check_rank_sort(&input);
+    }
+
+    #[test]
+    fn unchecked_launches_give_the_checked_output() {
+        let (Some(checked), Some(unchecked)) = (
+            test_sorter(),
+            test_sorter_with(GpuSorter::builder().checked(false)),
+        ) else {
+            return;
+        };
+        assert!(checked.checked());
+        assert!(!unchecked.checked());
+        for n in [2, 3, 257, 1000] {
+            let input: Vec<i32> = pseudo_random(n, n as u64).iter().map(|v| v % 100).collect();
+            let wide: Vec<u64> = input.iter().map(|&x| x as u32 as u64).collect();
+            let run = |sorter: &GpuSorter| {
+                (
+                    sorter.sort(&input).unwrap(),
+                    sorter.sort_radix(&input, None).unwrap(),
+                    sorter.sort_bitonic(&input).unwrap(),
+                    sorter.ranks(&input).unwrap(),
+                    sorter.sort_stable(&input, &input).unwrap(),
+                    sorter.sort_u64(&wide).unwrap(),
+                    sorter.sort_unique(&input).unwrap(),
+                    sorter.reduce_sum(&input).unwrap(),
+                )
+            };
+            let expected = run(&checked);
+            let mut sorted = input.clone();
+            sorted.sort_unstable();
+            assert_eq!(expected.0, sorted);
+            assert_eq!(run(&unchecked), expected);
+        }
+    }
+
+    #[test]
+    fn compare_atomics_split_smaller_values_from_ties() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let increasing: Vec<i32> = (0..500).collect();
+        assert_eq!(
+            sorter.compare_atomic_count(&increasing).unwrap(),
+            CompareAtomics {
+                smaller: 500 * 499 / 2,
+                ties: 0
+            }
+        );
+        assert_eq!(
+            sorter.compare_atomic_count(&[7; 300]).unwrap(),
+            CompareAtomics {
+                smaller: 0,
+                ties: 300 * 299 / 2
+            }
+        );
+        // Each 5 follows the two 1s; the three 5s and the two 1s tie.
+        let mixed = sorter.compare_atomic_count(&[5, 1, 5, 1, 5]).unwrap();
+        assert_eq!(
+            mixed,
+            CompareAtomics {
+                smaller: 6,
+                ties: 4
+            }
+        );
+        assert_eq!(mixed.total(), 5 * 4 / 2);
+        assert_eq!(
+            sorter.compare_atomic_count(&[1]).unwrap(),
+            CompareAtomics::default()
+        );
+    }
+
+    #[test]
+    fn ranks_of_equal_values_follow_input_order() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        assert_eq!(sorter.ranks(&[5, 1, 5, 1, 5]).unwrap(), vec![2, 0, 3, 1, 4]);
+    }
+
+    #[test]
+    fn only_the_rank_sort_times_an_assign_kernel() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input = pseudo_random(300, 12);
+        // Ranking runs the compare kernel alone.
+        assert_eq!(sorter.ranks_profiled(&input).unwrap().1.assign_ns, None);
+        let trace = sorter.sort_traced(&input, 0..300, false).unwrap();
+        let assign = trace.events.iter().find(|(phase, _)| *phase == "assign");
+        assert_eq!(
+            trace.report.assign_ns,
+            assign.and_then(|(_, event)| event_duration_ns(event))
+        );
+    }
+
+    /// Records the name of every span created while it is the default.
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct SpanNames(Arc<std::sync::Mutex<Vec<&'static str>>>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for SpanNames {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut names = self.0.lock().unwrap();
+            names.push(span.metadata().name());
+            tracing::span::Id::from_u64(names.len() as u64)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {}
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn sort_spans_each_phase_in_order() {
+        let names = SpanNames::default();
+        let sorter = tracing::subscriber::with_default(names.clone(), || {
+            test_sorter_with(GpuSorter::builder().rank_strategy(RankStrategy::Atomic))
+        });
+        let Some(sorter) = sorter else {
+            return;
+        };
+        let built = names.0.lock().unwrap().len();
+        assert!(built > 0);
+        assert!(names.0.lock().unwrap().iter().all(|&name| name == "build"));
+
+        tracing::subscriber::with_default(names.clone(), || sorter.sort(&[3, 1, 2]).unwrap());
+        assert_eq!(
+            names.0.lock().unwrap()[built..],
+            ["upload", "kernel", "kernel", "readback"]
+        );
+    }
+
+    #[test]
+    fn unfilled_slots_are_told_from_genuine_sentinels() {
+        assert_eq!(unfilled_slot(&[3, 1, 2], 0, &[1, 2, 3]), None);
+        assert_eq!(unfilled_slot(&[3, 1, 1], 0, &[1, UNFILLED, 3]), Some(1));
+        // The input's own -1s sort to positions 1 and 2, after -5.
+        let input = [UNFILLED, 4, -5, UNFILLED];
+        assert_eq!(unfilled_slot(&input, 0, &[-5, UNFILLED, UNFILLED, 4]), None);
+        assert_eq!(
+            unfilled_slot(&input, 0, &[-5, UNFILLED, 4, UNFILLED]),
+            Some(3)
+        );
+        // A window is checked at its offset in the whole result.
+        assert_eq!(unfilled_slot(&input, 2, &[UNFILLED, 4]), None);
+        assert_eq!(unfilled_slot(&input, 2, &[4, UNFILLED]), Some(3));
+    }
+
+    #[test]
+    fn a_compare_kernel_without_the_tie_break_leaves_slots_unfilled() {
+        // The compare kernel from before ties were broken by index, which
+        // gives equal elements one rank, so the assign kernel writes them all
+        // to the same slot.
+        let dir = temp_dir("incomplete-sort");
+        let old = COMPARE_KERNEL.replace(" || (source[j] == source[i] && j < i)", "");
+        assert_ne!(old, COMPARE_KERNEL);
+        fs::write(dir.join("compare.cl"), old).unwrap();
+        let builder = || {
+            GpuSorter::builder()
+                .rank_strategy(RankStrategy::Atomic)
+                .kernel_dir(&dir)
+        };
+        let input: Vec<i32> = pseudo_random(500, 107).iter().map(|v| v & 7).collect();
+        if let Some(sorter) = test_sorter_with(builder()) {
+            assert!(matches!(
+                sorter.sort(&input),
+                Err(SortError::IncompleteSort { .. })
+            ));
+            assert!(matches!(
+                sorter.sort_batch_pipelined(&[vec![2, 1], input.clone()]),
+                Err(SortError::IncompleteSort { .. })
+            ));
+            // Distinct values still get distinct ranks.
+            let distinct: Vec<i32> = (0..500).rev().collect();
+            assert_eq!(
+                sorter.sort(&distinct).unwrap(),
+                (0..500).collect::<Vec<_>>()
+            );
+
+            let unchecked = test_sorter_with(builder().checked(false)).unwrap();
+            assert_eq!(unchecked.sort(&input).unwrap().len(), input.len());
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_platforms_mean_no_opencl() {
+        assert!(matches!(
+            platforms_found(Err(ClError(CL_PLATFORM_NOT_FOUND_KHR))),
+            Err(SortError::NoOpenCl)
+        ));
+        assert!(matches!(
+            platforms_found(Ok(Vec::new())),
+            Err(SortError::NoOpenCl)
+        ));
+        assert!(matches!(
+            platforms_found(Err(ClError(CL_DEVICE_NOT_FOUND))),
+            Err(SortError::Cl(ClError(CL_DEVICE_NOT_FOUND)))
+        ));
+    }
+
+    #[test]
+    fn an_out_of_range_platform_index_lists_every_platform() {
+        let error = SortError::PlatformIndex {
+            index: 2,
+            available: vec![
+                "NVIDIA CUDA".to_string(),
+                "Portable Computing Language".to_string(),
+            ],
+        };
+        assert_eq!(
+            error.to_string(),
+            "there is no platform 2; the platforms are:\n  0: NVIDIA CUDA\n  1: Portable Computing Language"
+        );
+
+        let platforms = match find_platforms() {
+            Ok(platforms) => platforms,
+            Err(SortError::NoOpenCl) => {
+                eprintln!("skipping: no OpenCL platform to test on");
+                return;
+            }
+            Err(e) => panic!("failed to list the platforms: {}", e),
+        };
+        assert!(platform_at(platforms.len() - 1).is_ok());
+        match platform_at(platforms.len()) {
+            Err(SortError::PlatformIndex { index, available }) => {
+                assert_eq!(index, platforms.len());
+                let names: Vec<String> = platforms.iter().map(|p| p.name().unwrap()).collect();
+                assert_eq!(available, names);
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("platform {} out of range was accepted", platforms.len()),
+        }
+        assert!(matches!(
+            find_devices_on(platforms.len(), CL_DEVICE_TYPE_GPU),
+            Err(SortError::PlatformIndex { .. })
+        ));
+    }
+
+    #[test]
+    fn every_compare_kernel_fills_the_same_count_buffer() {
+        let builders = [
+            GpuSorter::builder().rank_strategy(RankStrategy::Atomic),
+            GpuSorter::builder().rank_strategy(RankStrategy::Tiled),
+            GpuSorter::builder().rank_strategy(RankStrategy::Loop),
+            // 3 leaves a partial last tile.
+            GpuSorter::builder()
+                .rank_strategy(RankStrategy::Tiled)
+                .round_work_size(3),
+        ];
+        let mut sorters = Vec::new();
+        for builder in builders {
+            let Some(sorter) = test_sorter_with(builder) else {
+                return;
+            };
+            sorters.push(sorter);
+        }
+        let input = pseudo_random(1000, 61);
+        let duplicates: Vec<i32> = input.iter().map(|value| value % 5).collect();
+        for input in [input, duplicates, vec![2, 1]] {
+            // Each element's count is the smaller elements plus the equal
+            // ones before it.
+            let expected: Vec<i32> = (0..input.len())
+                .map(|i| {
+                    (0..input.len())
+                        .filter(|&j| input[j] < input[i] || (input[j] == input[i] && j < i))
+                        .count() as i32
+                })
+                .collect();
+            for sorter in &sorters {
+                assert_eq!(
+                    sorter.ranks(&input).unwrap(),
+                    expected,
+                    "{:?}",
+                    sorter.rank_strategy()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn clones_for_threads_sort_at_once() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input = pseudo_random(2000, 81);
+        let clones: Vec<GpuSorter> = (0..4).map(|_| sorter.clone_for_thread().unwrap()).collect();
+        std::thread::scope(|scope| {
+            for (index, clone) in clones.into_iter().enumerate() {
+                let input = &input;
+                scope.spawn(move || {
+                    let values: Vec<i32> = input
+                        .iter()
+                        .map(|value| value.rotate_left(8 * index as u32))
+                        .collect();
+                    let mut expected = values.clone();
+                    expected.sort_unstable();
+                    for _ in 0..8 {
+                        assert_eq!(clone.sort(&values).unwrap(), expected);
+                        assert_eq!(clone.sort_radix(&values, None).unwrap(), expected);
+                    }
+                });
+            }
+        });
+        // The original is still usable alongside its clones.
+        let mut expected = input.clone();
+        expected.sort_unstable();
+        assert_eq!(sorter.sort(&input).unwrap(), expected);
+    }
+
+    #[test]
+    fn finish_drains_commands_enqueued_by_the_caller() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input = pseudo_random(1000, 91);
+        let mut buffer = upload(&sorter.context, &input).unwrap();
+        let event = fill_i32(sorter.queue(), &mut buffer, 5, input.len()).unwrap();
+        sorter.finish().unwrap();
+        assert_eq!(
+            event.command_execution_status().unwrap().0,
+            opencl3::event::CL_COMPLETE
+        );
+        // The sorter keeps working on the drained queue.
+        sorter.finish().unwrap();
+        assert!(sorter.sort(&input).is_ok());
+    }
+
+    #[test]
+    fn gpu_sort_returns_short_inputs_without_a_device() {
+        assert!(gpu_sort(&[]).unwrap().is_empty());
+        assert_eq!(gpu_sort(&[3]).unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn gpu_sort_reuses_its_sorter() {
+        if test_sorter().is_none() {
+            return;
+        }
+        let input = pseudo_random(1000, 11);
+        let allocations =
+            || DEFAULT_SORTER.with(|cell| cell.get().map(|s| s.buffer_pool().allocations()));
+        let mut expected = input.clone();
+        expected.sort_unstable();
+        assert_eq!(gpu_sort(&input).unwrap(), expected);
+        let first = allocations();
+        assert!(first.is_some());
+        assert_eq!(gpu_sort(&input).unwrap(), expected);
+        assert_eq!(allocations(), first);
+        assert_eq!(gpu_sort_bitonic(&input).unwrap(), expected);
+        assert_eq!(gpu_sort_bitonic(&input).unwrap(), expected);
+        let after_bitonic = allocations();
+        assert_eq!(gpu_sort(&input).unwrap(), expected);
+        assert_eq!(allocations(), after_bitonic);
+    }
+
+    #[test]
+    fn both_read_strategies_read_back_every_result() {
+        let input = pseudo_random(1000, 23);
+        let wide: Vec<u64> = input
+            .iter()
+            .map(|&v| (v as u64) << 33 | v as u64 >> 7)
+            .collect();
+        let narrow: Vec<i16> = input.iter().map(|&v| v as i16).collect();
+        let mut expected = input.clone();
+        expected.sort_unstable();
+
+        let mut runs = Vec::new();
+        for strategy in [ReadStrategy::Copy, ReadStrategy::Map] {
+            let Some(sorter) = test_sorter_with(GpuSorter::builder().read_strategy(strategy))
+            else {
+                return;
+            };
+            let trace = sorter.sort_traced(&input, 100..300, true).unwrap();
+            assert_eq!(trace.result, expected[100..300], "{:?}", strategy);
+            assert_eq!(sorter.sort(&input).unwrap(), expected, "{:?}", strategy);
+            runs.push((
+                (
+                    sorter.sort_radix(&input, None).unwrap(),
+                    sorter.sort_bitonic(&input).unwrap(),
+                    sorter.ranks(&input).unwrap(),
+                    trace.ranks,
+                ),
+                (
+                    sorter.sort_stable(&input, &expected).unwrap(),
+                    sorter.argsort_i32(&input).unwrap(),
+                    sorter
+                        .argsort_batch(&[input.clone(), expected.clone()])
+                        .unwrap(),
+                ),
+                (
+                    sorter.sort_u64(&wide).unwrap(),
+                    sorter.sort_i16(&narrow).unwrap(),
+                    sorter.sort_unique(&input).unwrap(),
+                    sorter.top_k(&input, 10).unwrap(),
+                    sorter.sort_and_gaps(&input).unwrap(),
+                    sorter.reduce_min(&input).unwrap(),
+                ),
+            ));
+        }
+        assert_eq!(runs[0].0 .0, expected);
+        assert_eq!(runs[0], runs[1], "mapped reads differ from copied reads");
+    }
+}
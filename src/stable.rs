@@ -0,0 +1,371 @@
+use crate::buffer::{fill_i32, upload};
+use crate::error::{Result, SortError};
+use crate::gpu_vec::GpuVec;
+use crate::launch::SortKernel;
+use crate::sorter::GpuSorter;
+use opencl3::event::Event;
+use opencl3::memory::{Buffer, CL_MEM_READ_WRITE};
+use opencl3::types::cl_int;
+use std::ptr;
+
+/// Direction of a sort.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// Which of two elements with equal keys a stable sort places first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    /// The one earlier in the input, so equal keys keep ascending index
+    /// order.
+    #[default]
+    FirstIndexWins,
+    /// The one later in the input, so equal keys are in descending index
+    /// order.
+    LastIndexWins,
+}
+
+impl GpuSorter {
+    /// Sorts `keys` ascending, carrying `values` along, such that pairs with
+    /// equal keys keep their original relative order.
+    ///
+    /// Each element's position is the number of strictly smaller keys plus
+    /// the number of equal keys before it, both counted by one fused compare
+    /// kernel so the n² comparisons are only made once.
+    pub fn sort_stable(&self, keys: &[i32], values: &[i32]) -> Result<(Vec<i32>, Vec<i32>)> {
+        self.sort_stable_ordered(keys, values, SortOrder::Ascending)
+    }
+
+    /// Like [`GpuSorter::sort_stable`], in either direction.
+    ///
+    /// A descending sort is still stable: pairs with equal keys keep their
+    /// original, ascending index order, rather than the reversed order that
+    /// flipping an ascending stable sort would give. Only the key comparison
+    /// is flipped; the tie-break on index is not.
+    pub fn sort_stable_ordered(
+        &self,
+        keys: &[i32],
+        values: &[i32],
+        order: SortOrder,
+    ) -> Result<(Vec<i32>, Vec<i32>)> {
+        self.sort_stable_ties(keys, values, order, TieBreak::FirstIndexWins)
+    }
+
+    /// Like [`GpuSorter::sort_stable_ordered`], with `tie_break` choosing
+    /// whether pairs with equal keys keep ascending or descending index
+    /// order. The direction of the key comparison and of the tie-break are
+    /// independent.
+    pub fn sort_stable_ties(
+        &self,
+        keys: &[i32],
+        values: &[i32],
+        order: SortOrder,
+        tie_break: TieBreak,
+    ) -> Result<(Vec<i32>, Vec<i32>)> {
+        if keys.len() != values.len() {
+            return Err(SortError::InvalidInput(format!(
+                "{} keys but {} values",
+                keys.len(),
+                values.len()
+            )));
+        }
+        let n = keys.len();
+        if n < 2 {
+            return Ok((keys.to_vec(), values.to_vec()));
+        }
+
+        let key_buffer = upload(&self.context, keys)?;
+        let value_buffer = upload(&self.context, values)?;
+        let (key_result_buffer, value_result_buffer, scatter_kernel_event) =
+            self.rank_and_scatter(&key_buffer, &value_buffer, n, order, tie_break)?;
+
+        let sorted_keys =
+            self.read_result_range(&key_result_buffer, 0, n, &scatter_kernel_event)?;
+        let sorted_values =
+            self.read_result_range(&value_result_buffer, 0, n, &scatter_kernel_event)?;
+        Ok((sorted_keys, sorted_values))
+    }
+
+    /// Like [`GpuSorter::sort_stable`] on vectors already on the device, such
+    /// as keys computed by an earlier stage, returning the sorted keys and
+    /// values without reading anything back to the host.
+    ///
+    /// Returns [`SortError::InvalidInput`] if `keys` and `values` differ in
+    /// length.
+    pub fn sort_by_key_device(
+        &self,
+        keys: GpuVec<i32>,
+        values: GpuVec<i32>,
+    ) -> Result<(GpuVec<i32>, GpuVec<i32>)> {
+        if keys.len() != values.len() {
+            return Err(SortError::InvalidInput(format!(
+                "{} keys but {} values",
+                keys.len(),
+                values.len()
+            )));
+        }
+        let n = keys.len();
+        if n < 2 {
+            return Ok((keys, values));
+        }
+
+        let (key_result_buffer, value_result_buffer, scatter_kernel_event) = self
+            .rank_and_scatter(
+                keys.buffer(),
+                values.buffer(),
+                n,
+                SortOrder::Ascending,
+                TieBreak::FirstIndexWins,
+            )?;
+        scatter_kernel_event.wait()?;
+        Ok((
+            GpuVec::from_buffer(key_result_buffer, n),
+            GpuVec::from_buffer(value_result_buffer, n),
+        ))
+    }
+
+    /// Enqueues the fused rank computation and the scatter of the first `n`
+    /// pairs of `key_buffer` and `value_buffer` into new result buffers,
+    /// returning them with the scatter's event.
+    fn rank_and_scatter(
+        &self,
+        key_buffer: &Buffer<cl_int>,
+        value_buffer: &Buffer<cl_int>,
+        n: usize,
+        order: SortOrder,
+        tie_break: TieBreak,
+    ) -> Result<(Buffer<cl_int>, Buffer<cl_int>, Event)> {
+        let key_result_buffer =
+            Buffer::<cl_int>::create(&self.context, CL_MEM_READ_WRITE, n, ptr::null_mut())?;
+        let value_result_buffer =
+            Buffer::<cl_int>::create(&self.context, CL_MEM_READ_WRITE, n, ptr::null_mut())?;
+
+        let (smaller_buffer, equal_buffer, rank_kernel_event) =
+            self.enqueue_rank_equal(key_buffer, n, order, tie_break)?;
+
+        let scatter_kernel_event = SortKernel::new(&self.scatter_pairs_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(key_buffer)?
+            .buffer(value_buffer)?
+            .buffer(&smaller_buffer)?
+            .buffer(&equal_buffer)?
+            .buffer(&key_result_buffer)?
+            .buffer(&value_result_buffer)?
+            .wait(&rank_kernel_event)
+            .enqueue(&self.queue)?;
+
+        // The queue is in order, so anything that takes these count buffers
+        // from the pool next runs after the scatter.
+        self.pool.give(n, CL_MEM_READ_WRITE, smaller_buffer);
+        self.pool.give(n, CL_MEM_READ_WRITE, equal_buffer);
+        Ok((key_result_buffer, value_result_buffer, scatter_kernel_event))
+    }
+
+    /// Enqueues the fused compare that counts, for each of the first `n`
+    /// keys of `key_buffer`, the keys that go before it and the equal keys
+    /// that win the tie against it. Returns the two count buffers, taken from
+    /// the pool with `CL_MEM_READ_WRITE`, and the compare's event.
+    fn enqueue_rank_equal(
+        &self,
+        key_buffer: &Buffer<cl_int>,
+        n: usize,
+        order: SortOrder,
+        tie_break: TieBreak,
+    ) -> Result<(Buffer<cl_int>, Buffer<cl_int>, Event)> {
+        let mut smaller_buffer = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+        let mut equal_buffer = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+
+        let smaller_fill_event = fill_i32(&self.queue, &mut smaller_buffer, 0, n)?;
+        let equal_fill_event = fill_i32(&self.queue, &mut equal_buffer, 0, n)?;
+
+        let rank_kernel_event = SortKernel::pairs(&self.rank_equal_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(key_buffer)?
+            .buffer(&smaller_buffer)?
+            .buffer(&equal_buffer)?
+            .scalar(&((order == SortOrder::Descending) as cl_int))
+            .scalar(&((tie_break == TieBreak::LastIndexWins) as cl_int))
+            .wait(&smaller_fill_event)
+            .wait(&equal_fill_event)
+            .enqueue(&self.queue)?;
+        Ok((smaller_buffer, equal_buffer, rank_kernel_event))
+    }
+
+    /// Returns, for each element of `input`, the position it lands at in an
+    /// ascending stable sort: the number of smaller elements plus the number
+    /// of equal ones before it. This is the inverse of the permutation
+    /// [`GpuSorter::argsort_i32`] returns, computed by the same fused compare
+    /// as [`GpuSorter::sort_stable`] without the scatter.
+    pub fn sorted_positions(&self, input: &[i32]) -> Result<Vec<u32>> {
+        let n = input.len();
+        if n < 2 {
+            return Ok(vec![0; n]);
+        }
+
+        let key_buffer = upload(&self.context, input)?;
+        let (smaller_buffer, equal_buffer, rank_kernel_event) = self.enqueue_rank_equal(
+            &key_buffer,
+            n,
+            SortOrder::Ascending,
+            TieBreak::FirstIndexWins,
+        )?;
+        let smaller = self.read_result_range(&smaller_buffer, 0, n, &rank_kernel_event)?;
+        let equal = self.read_result_range(&equal_buffer, 0, n, &rank_kernel_event)?;
+        self.pool.give(n, CL_MEM_READ_WRITE, smaller_buffer);
+        self.pool.give(n, CL_MEM_READ_WRITE, equal_buffer);
+        Ok(smaller
+            .iter()
+            .zip(&equal)
+            .map(|(&smaller, &equal)| (smaller + equal) as u32)
+            .collect())
+    }
+
+    /// Sorts `input` ascending and returns, alongside each sorted value, its
+    /// index in `input`, with equal values in their original order. The
+    /// indices ride along with the values through the same rank computation,
+    /// so this costs one sort rather than a sort plus an argsort.
+    pub fn sort_with_indices(&self, input: &[i32]) -> Result<(Vec<i32>, Vec<u32>)> {
+        let indices: Vec<i32> = (0..input.len() as i32).collect();
+        let (sorted, indices) = self.sort_stable(input, &indices)?;
+        Ok((sorted, indices.into_iter().map(|i| i as u32).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{assert_stable, assert_valid_sort, pseudo_random, test_sorter};
+
+    /// Keys with many duplicates, so there are ties to keep in order.
+    fn duplicate_keys(n: usize) -> Vec<i32> {
+        pseudo_random(n, 11).iter().map(|v| v % 16).collect()
+    }
+
+    #[test]
+    fn sort_with_indices_is_stable() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let keys = duplicate_keys(500);
+        let (sorted, indices) = sorter.sort_with_indices(&keys).unwrap();
+        assert_stable(&keys, &indices);
+        assert_valid_sort(&keys, &sorted, SortOrder::Ascending);
+    }
+
+    #[test]
+    fn descending_sort_stable_is_stable() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let keys = duplicate_keys(300);
+        let indices: Vec<i32> = (0..keys.len() as i32).collect();
+        let (sorted, values) = sorter
+            .sort_stable_ordered(&keys, &indices, SortOrder::Descending)
+            .unwrap();
+        assert_valid_sort(&keys, &sorted, SortOrder::Descending);
+        let values: Vec<u32> = values.into_iter().map(|i| i as u32).collect();
+        assert_stable(&keys, &values);
+    }
+
+    #[test]
+    fn sorted_positions_are_stable() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let keys = duplicate_keys(400);
+        let positions = sorter.sorted_positions(&keys).unwrap();
+        // The positions are the inverse of the sorting permutation.
+        let mut indices = vec![u32::MAX; keys.len()];
+        for (index, &position) in positions.iter().enumerate() {
+            indices[position as usize] = index as u32;
+        }
+        assert_stable(&keys, &indices);
+    }
+
+    #[test]
+    fn tie_breaks_order_equal_keys_oppositely() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let keys = duplicate_keys(300);
+        let indices: Vec<i32> = (0..keys.len() as i32).collect();
+        let ties = |tie_break| -> Vec<u32> {
+            let (sorted, indices) = sorter
+                .sort_stable_ties(&keys, &indices, SortOrder::Ascending, tie_break)
+                .unwrap();
+            assert_valid_sort(&keys, &sorted, SortOrder::Ascending);
+            indices.into_iter().map(|i| i as u32).collect()
+        };
+        let first = ties(TieBreak::FirstIndexWins);
+        let last = ties(TieBreak::LastIndexWins);
+        assert_ne!(first, last);
+        assert_stable(&keys, &first);
+        // Within each run of equal keys, last-index-wins is the reverse of
+        // first-index-wins.
+        let mut start = 0;
+        while start < first.len() {
+            let key = keys[first[start] as usize];
+            let end = start
+                + first[start..]
+                    .iter()
+                    .take_while(|&&i| keys[i as usize] == key)
+                    .count();
+            let mut run = last[start..end].to_vec();
+            run.reverse();
+            assert_eq!(run, first[start..end], "run of key {}", key);
+            start = end;
+        }
+    }
+
+    #[test]
+    fn sorted_positions_invert_the_argsort() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        for n in [0, 1, 2, 300] {
+            let keys = duplicate_keys(n);
+            let order = sorter.argsort_i32(&keys).unwrap();
+            let positions = sorter.sorted_positions(&keys).unwrap();
+            assert_eq!(positions.len(), n);
+            for (position, &index) in order.iter().enumerate() {
+                assert_eq!(positions[index as usize], position as u32);
+            }
+        }
+    }
+
+    #[test]
+    fn sort_by_key_device_keeps_equal_keys_in_order() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let keys = duplicate_keys(257);
+        let indices: Vec<i32> = (0..keys.len() as i32).collect();
+        let (sorted, order) = sorter
+            .sort_by_key_device(
+                sorter.to_device(&keys).unwrap(),
+                sorter.to_device(&indices).unwrap(),
+            )
+            .unwrap();
+        let sorted = sorter.to_host(&sorted).unwrap();
+        let order: Vec<u32> = sorter
+            .to_host(&order)
+            .unwrap()
+            .into_iter()
+            .map(|i| i as u32)
+            .collect();
+        assert_valid_sort(&keys, &sorted, SortOrder::Ascending);
+        assert_stable(&keys, &order);
+        assert!(matches!(
+            sorter.sort_by_key_device(
+                sorter.to_device(&keys).unwrap(),
+                sorter.to_device(&indices[1..]).unwrap(),
+            ),
+            Err(SortError::InvalidInput(_))
+        ));
+    }
+}
@@ -0,0 +1,49 @@
+use crate::error::{Result, SortError};
+use crate::sorter::GpuSorter;
+
+/// Order statistics of an array. Percentiles use the nearest-rank method: the
+/// `p`th percentile is the smallest element that at least `p`% of the array
+/// is less than or equal to. The median is the lower median.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OrderStats {
+    pub min: i32,
+    pub max: i32,
+    pub median: i32,
+    pub p25: i32,
+    pub p75: i32,
+    pub p90: i32,
+    pub p99: i32,
+}
+
+impl OrderStats {
+    /// Reads the statistics off a non-empty sorted slice.
+    fn from_sorted(sorted: &[i32]) -> Self {
+        let n = sorted.len();
+        let percentile = |p: usize| sorted[(p * n).div_ceil(100).max(1) - 1];
+        OrderStats {
+            min: sorted[0],
+            max: sorted[n - 1],
+            median: sorted[(n - 1) / 2],
+            p25: percentile(25),
+            p75: percentile(75),
+            p90: percentile(90),
+            p99: percentile(99),
+        }
+    }
+}
+
+impl GpuSorter {
+    /// Sorts `input` and returns its order statistics along with it, taken by
+    /// indexing the sorted result rather than another pass over the data.
+    /// Returns [`SortError::InvalidInput`] if `input` is empty.
+    pub fn sort_with_stats(&self, input: &[i32]) -> Result<(Vec<i32>, OrderStats)> {
+        if input.is_empty() {
+            return Err(SortError::InvalidInput(
+                "order statistics of an empty array are undefined".to_string(),
+            ));
+        }
+        let sorted = self.sort(input)?;
+        let stats = OrderStats::from_sorted(&sorted);
+        Ok((sorted, stats))
+    }
+}
@@ -0,0 +1,139 @@
+use crate::error::Result;
+use crate::sorter::GpuSorter;
+use opencl3::event::Event;
+use opencl3::memory::{Buffer, CL_MEM_READ_WRITE};
+use opencl3::types::cl_int;
+use std::collections::VecDeque;
+
+/// How many chunks [`GpuSorter::sort_stream`] keeps enqueued by default.
+pub const DEFAULT_MAX_INFLIGHT: usize = 2;
+
+/// A chunk whose sort has been enqueued but not read back.
+enum Pending {
+    /// Too short to need the device.
+    Host(Vec<i32>),
+    /// Radix sorting into `buffer`, `len` elements long, until `event`.
+    Device {
+        buffer: Buffer<cl_int>,
+        event: Event,
+        len: usize,
+    },
+}
+
+/// An iterator over sorted chunks, returned by [`GpuSorter::sort_stream`].
+pub struct SortStream<'a, I> {
+    sorter: &'a GpuSorter,
+    chunks: I,
+    max_inflight: usize,
+    inflight: VecDeque<Pending>,
+}
+
+impl GpuSorter {
+    /// Radix sorts each chunk of `chunks` on its own, yielding the sorted
+    /// chunks in order. Up to [`SortStream::max_inflight`] chunks, by default
+    /// [`DEFAULT_MAX_INFLIGHT`], are uploaded and enqueued before the oldest
+    /// is read back, so the device has the next chunk's work queued as soon
+    /// as it finishes one, instead of idling through each read and upload.
+    pub fn sort_stream<I>(&self, chunks: I) -> SortStream<'_, I::IntoIter>
+    where
+        I: IntoIterator<Item = Vec<i32>>,
+    {
+        SortStream {
+            sorter: self,
+            chunks: chunks.into_iter(),
+            max_inflight: DEFAULT_MAX_INFLIGHT,
+            inflight: VecDeque::new(),
+        }
+    }
+}
+
+impl<I> SortStream<'_, I> {
+    /// Bounds how many chunks are enqueued before waiting on the oldest,
+    /// which gives the stream backpressure: a chunk is only taken from the
+    /// source once there is room for it. Each in-flight chunk holds a sorted
+    /// buffer of its length on the device until it is read back, and adds
+    /// its passes to the driver's queue, so a higher bound trades device
+    /// memory and queue depth for fewer stalls between chunks; the scratch
+    /// buffers are shared between chunks through the pool. With 1 the chunks
+    /// are sorted one after another, exactly like calling
+    /// [`GpuSorter::sort_radix`] on each. A bound of 0 is taken as 1.
+    pub fn max_inflight(mut self, max_inflight: usize) -> Self {
+        self.max_inflight = max_inflight.max(1);
+        self
+    }
+}
+
+impl<I: Iterator<Item = Vec<i32>>> Iterator for SortStream<'_, I> {
+    type Item = Result<Vec<i32>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.inflight.len() < self.max_inflight {
+            let Some(chunk) = self.chunks.next() else {
+                break;
+            };
+            let pending = if chunk.len() < 2 {
+                Pending::Host(chunk)
+            } else {
+                match self.sorter.radix_sort_buffer(&chunk, None) {
+                    Ok((buffer, event)) => Pending::Device {
+                        buffer,
+                        event,
+                        len: chunk.len(),
+                    },
+                    Err(e) => return Some(Err(e)),
+                }
+            };
+            self.inflight.push_back(pending);
+        }
+
+        Some(match self.inflight.pop_front()? {
+            Pending::Host(chunk) => Ok(chunk),
+            Pending::Device { buffer, event, len } => {
+                let sorted = self.sorter.read_result_range(&buffer, 0, len, &event);
+                self.sorter.pool.give(len, CL_MEM_READ_WRITE, buffer);
+                sorted
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Result;
+    use crate::test_util::{pseudo_random, test_sorter};
+
+    #[test]
+    fn max_inflight_one_and_two_yield_the_same_chunks() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        // Chunks of 60 with a short tail, and a 1-element chunk the device
+        // doesn't see, in between.
+        let mut chunks: Vec<Vec<i32>> = pseudo_random(1000, 31)
+            .chunks(60)
+            .map(<[i32]>::to_vec)
+            .collect();
+        chunks.insert(3, vec![42]);
+        chunks.insert(5, Vec::new());
+        let expected: Vec<Vec<i32>> = chunks
+            .iter()
+            .map(|chunk| {
+                let mut sorted = chunk.clone();
+                sorted.sort_unstable();
+                sorted
+            })
+            .collect();
+        let stream = |max_inflight| -> Vec<Vec<i32>> {
+            sorter
+                .sort_stream(chunks.clone())
+                .max_inflight(max_inflight)
+                .collect::<Result<_>>()
+                .unwrap()
+        };
+        let serial = stream(1);
+        assert_eq!(serial, expected);
+        assert_eq!(stream(2), serial);
+        assert_eq!(stream(0), serial);
+        assert_eq!(stream(chunks.len() + 1), serial);
+    }
+}
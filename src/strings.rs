@@ -0,0 +1,191 @@
+use crate::error::{Result, SortError};
+use crate::sorter::GpuSorter;
+
+/// How [`GpuSorter::sort_strings_with`] turns string keys into sort keys.
+///
+/// Keys are only ever compared byte by byte; there is no Unicode collation,
+/// so non-ASCII characters sort by their UTF-8 bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StringKeyOptions {
+    /// How many leading bytes of each key are compared, at most 8.
+    pub width: usize,
+    /// Fold ASCII letters to lower case before packing, so keys that differ
+    /// only in ASCII case compare equal and keep their original order.
+    pub case_insensitive: bool,
+    /// Return an error for keys longer than `width` bytes instead of
+    /// truncating them, and, when folding case, for keys with non-ASCII
+    /// bytes, which folding would otherwise pass through unchanged.
+    pub strict: bool,
+}
+
+impl Default for StringKeyOptions {
+    fn default() -> Self {
+        StringKeyOptions {
+            width: 8,
+            case_insensitive: false,
+            strict: false,
+        }
+    }
+}
+
+/// Packs the first `width` bytes of `key` into a big-endian `u64`, padding
+/// short keys with zero bytes. Comparing the packed values compares the keys
+/// byte-wise, so a shorter key sorts before any longer key it is a prefix of.
+fn pack_key(key: &[u8], width: usize, case_insensitive: bool) -> u64 {
+    let mut bytes = [0u8; 8];
+    let len = key.len().min(width);
+    bytes[..len].copy_from_slice(&key[..len]);
+    if case_insensitive {
+        bytes.make_ascii_lowercase();
+    }
+    u64::from_be_bytes(bytes)
+}
+
+impl GpuSorter {
+    /// Sorts short string keys by packing each one (truncated or padded to
+    /// `width` bytes, at most 8) into a `u64` and sorting the packed keys on
+    /// the device. Keys that are equal within the first `width` bytes keep
+    /// their original order.
+    pub fn sort_strings(&self, keys: &[String], width: usize) -> Result<Vec<String>> {
+        self.sort_strings_with(
+            keys,
+            StringKeyOptions {
+                width,
+                ..StringKeyOptions::default()
+            },
+        )
+    }
+
+    /// Like [`GpuSorter::sort_strings`], but returns
+    /// [`SortError::KeyTooLong`] instead of truncating keys longer than
+    /// `width` bytes.
+    pub fn sort_strings_strict(&self, keys: &[String], width: usize) -> Result<Vec<String>> {
+        self.sort_strings_with(
+            keys,
+            StringKeyOptions {
+                width,
+                strict: true,
+                ..StringKeyOptions::default()
+            },
+        )
+    }
+
+    /// Like [`GpuSorter::sort_strings`], with `options` choosing the width,
+    /// whether ASCII case is ignored and whether over-long or, when ignoring
+    /// case, non-ASCII keys are errors. In strict mode a key with a
+    /// non-ASCII byte gives [`SortError::NonAsciiKey`].
+    pub fn sort_strings_with(
+        &self,
+        keys: &[String],
+        options: StringKeyOptions,
+    ) -> Result<Vec<String>> {
+        let StringKeyOptions {
+            width,
+            case_insensitive,
+            strict,
+        } = options;
+        if width == 0 || width > 8 {
+            return Err(SortError::InvalidWidth(width));
+        }
+
+        let mut packed = Vec::with_capacity(keys.len());
+        for (index, key) in keys.iter().enumerate() {
+            if strict && key.len() > width {
+                return Err(SortError::KeyTooLong {
+                    index,
+                    len: key.len(),
+                    width,
+                });
+            }
+            if strict && case_insensitive {
+                if let Some(offset) = key.bytes().position(|byte| !byte.is_ascii()) {
+                    return Err(SortError::NonAsciiKey { index, offset });
+                }
+            }
+            packed.push(pack_key(key.as_bytes(), width, case_insensitive));
+        }
+
+        let indices = self.argsort_u64(&packed)?;
+        Ok(indices.iter().map(|&i| keys[i as usize].clone()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_sorter;
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn packed_keys_compare_like_their_bytes() {
+        let pack = |key: &str| pack_key(key.as_bytes(), 8, false);
+        assert!(pack("fig") < pack("figs"));
+        assert!(pack("date") < pack("fig"));
+        assert!(pack("Zoo") < pack("apple"));
+        assert_eq!(pack_key(b"kiwis", 4, false), pack("kiwi"));
+    }
+
+    #[test]
+    fn sort_strings_orders_short_words() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input = words(&[
+            "pear", "fig", "apple", "kiwi", "date", "plum", "lime", "yam", "figs", "melon", "pea",
+            "lemon",
+        ]);
+        let mut expected = input.clone();
+        expected.sort();
+        assert_eq!(sorter.sort_strings(&input, 8).unwrap(), expected);
+        assert_eq!(sorter.sort_strings(&input, 5).unwrap(), expected);
+    }
+
+    #[test]
+    fn sort_strings_rejects_bad_widths() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input = words(&["fig", "pear"]);
+        assert!(matches!(
+            sorter.sort_strings(&input, 0),
+            Err(SortError::InvalidWidth(0))
+        ));
+        assert!(matches!(
+            sorter.sort_strings(&input, 9),
+            Err(SortError::InvalidWidth(9))
+        ));
+        assert!(matches!(
+            sorter.sort_strings_strict(&input, 3),
+            Err(SortError::KeyTooLong {
+                index: 1,
+                len: 4,
+                width: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn case_insensitive_keys_put_apple_next_to_apple() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input = words(&["apple", "Banana", "Apple", "banana", "APPLE", "cherry"]);
+        let folded = StringKeyOptions {
+            case_insensitive: true,
+            ..StringKeyOptions::default()
+        };
+        // Folded keys compare equal, so they keep their input order.
+        assert_eq!(
+            sorter.sort_strings_with(&input, folded).unwrap(),
+            ["apple", "Apple", "APPLE", "Banana", "banana", "cherry"]
+        );
+        // Byte-wise, upper case sorts before all of lower case.
+        assert_eq!(
+            sorter.sort_strings(&input, 8).unwrap(),
+            ["APPLE", "Apple", "Banana", "apple", "banana", "cherry"]
+        );
+    }
+}
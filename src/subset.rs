@@ -0,0 +1,332 @@
+use crate::buffer::read_range;
+use crate::error::Result;
+use crate::launch::SortKernel;
+use crate::sorter::GpuSorter;
+use opencl3::memory::CL_MEM_READ_WRITE;
+
+impl GpuSorter {
+    /// Returns the `k` largest elements of `input`, largest first. `k` is
+    /// clamped to the input length.
+    ///
+    /// Only the last `k` elements of the sorted buffer are read back.
+    pub fn top_k(&self, input: &[i32], k: usize) -> Result<Vec<i32>> {
+        let n = input.len();
+        let k = k.min(n);
+        if n < 2 {
+            return Ok(input[..k].to_vec());
+        }
+
+        let (sorted, event) = self.radix_sort_buffer(input, None)?;
+        let mut result = self.read_result_range(&sorted, n - k, k, &event)?;
+        self.pool.give(n, CL_MEM_READ_WRITE, sorted);
+        result.reverse();
+        Ok(result)
+    }
+
+    /// Sorts `input` once and returns it both ascending and descending.
+    ///
+    /// After the ascending result is read back, the sorted buffer is
+    /// reversed in place on the device and read again, so the second order
+    /// costs one kernel and a transfer rather than another sort.
+    pub fn sort_both(&self, input: &[i32]) -> Result<(Vec<i32>, Vec<i32>)> {
+        let n = input.len();
+        if n < 2 {
+            return Ok((input.to_vec(), input.to_vec()));
+        }
+
+        let (sorted, event) = self.radix_sort_buffer(input, None)?;
+        let ascending = self.read_result_range(&sorted, 0, n, &event)?;
+        self.reverse_gpu(&sorted, n)?;
+        let descending = self.read_result_range(&sorted, 0, n, &event)?;
+        self.pool.give(n, CL_MEM_READ_WRITE, sorted);
+        Ok((ascending, descending))
+    }
+
+    /// Sorts `input` and also returns the index of the first element
+    /// `>= threshold`, which is `input.len()` if there is none.
+    ///
+    /// The sorted buffer is flagged against `threshold` on the device and
+    /// the flags are scanned, as in [`GpuSorter::partition`], so the split
+    /// costs no search on the host and only one extra element is read back.
+    pub fn sort_and_split(&self, input: &[i32], threshold: i32) -> Result<(Vec<i32>, usize)> {
+        let n = input.len();
+        if n < 2 {
+            let split = input.iter().filter(|&&value| value < threshold).count();
+            return Ok((input.to_vec(), split));
+        }
+
+        let (sorted, sort_event) = self.radix_sort_buffer(input, None)?;
+        let flags = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+        let scratch = [
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+        ];
+
+        let flags_event = SortKernel::new(&self.pivot_flags_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&sorted)?
+            .buffer(&flags)?
+            .scalar(&threshold)
+            .wait(&sort_event)
+            .enqueue(&self.queue)?;
+
+        let (scan, scan_event) = self.inclusive_scan(&flags, &scratch, n, &flags_event)?;
+
+        let split = read_range(&self.queue, scan, n - 1, 1, &scan_event)?[0] as usize;
+        let result = self.read_result_range(&sorted, 0, n, &sort_event)?;
+        for buffer in scratch.into_iter().chain([flags, sorted]) {
+            self.pool.give(n, CL_MEM_READ_WRITE, buffer);
+        }
+        Ok((result, split))
+    }
+
+    /// Returns the distinct elements of `input` in ascending order.
+    ///
+    /// Duplicates are compacted out on the device, and only the number of
+    /// distinct elements, followed by that many values, is read back.
+    pub fn sort_unique(&self, input: &[i32]) -> Result<Vec<i32>> {
+        let n = input.len();
+        if n < 2 {
+            return Ok(input.to_vec());
+        }
+
+        let (sorted, sort_event) = self.radix_sort_buffer(input, None)?;
+        let flags = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+        let scratch = [
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+        ];
+        let unique = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+
+        let flags_event = SortKernel::new(&self.unique_flags_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&sorted)?
+            .buffer(&flags)?
+            .wait(&sort_event)
+            .enqueue(&self.queue)?;
+
+        let (scan, scan_event) = self.inclusive_scan(&flags, &scratch, n, &flags_event)?;
+
+        let compact_event = SortKernel::new(&self.compact_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&sorted)?
+            .buffer(&flags)?
+            .buffer(scan)?
+            .buffer(&unique)?
+            .wait(&scan_event)
+            .enqueue(&self.queue)?;
+
+        // The last element of the inclusive scan is the number of flags set.
+        let count = read_range(&self.queue, scan, n - 1, 1, &compact_event)?[0] as usize;
+        let result = self.read_result_range(&unique, 0, count, &compact_event)?;
+
+        for buffer in scratch.into_iter().chain([sorted, flags, unique]) {
+            self.pool.give(n, CL_MEM_READ_WRITE, buffer);
+        }
+        Ok(result)
+    }
+
+    /// Like [`GpuSorter::sort_unique`], also returning how many duplicates
+    /// were collapsed, `input.len()` minus the number of distinct values.
+    /// Use [`GpuSorter::sort_rle`] for each value's own multiplicity.
+    pub fn sort_unique_counted(&self, input: &[i32]) -> Result<(Vec<i32>, usize)> {
+        let unique = self.sort_unique(input)?;
+        let collapsed = input.len() - unique.len();
+        Ok((unique, collapsed))
+    }
+
+    /// Sorts `input` and run-length encodes it, returning each distinct
+    /// value in ascending order with the number of times it occurs.
+    ///
+    /// Run starts are flagged and scanned on the device, giving each element
+    /// its run's index; the values are compacted and each run's end is
+    /// scattered, so only two arrays as long as the number of runs are read
+    /// back. The counts are the differences between consecutive ends.
+    pub fn sort_rle(&self, input: &[i32]) -> Result<Vec<(i32, u32)>> {
+        let n = input.len();
+        if n < 2 {
+            return Ok(input.iter().map(|&value| (value, 1)).collect());
+        }
+
+        let (sorted, sort_event) = self.radix_sort_buffer(input, None)?;
+        let flags = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+        let scratch = [
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+            self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?,
+        ];
+        let values = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+        let ends = self.pool.take(&self.context, n, CL_MEM_READ_WRITE)?;
+
+        let flags_event = SortKernel::new(&self.unique_flags_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&sorted)?
+            .buffer(&flags)?
+            .wait(&sort_event)
+            .enqueue(&self.queue)?;
+
+        let (scan, scan_event) = self.inclusive_scan(&flags, &scratch, n, &flags_event)?;
+
+        let compact_event = SortKernel::new(&self.compact_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&sorted)?
+            .buffer(&flags)?
+            .buffer(scan)?
+            .buffer(&values)?
+            .wait(&scan_event)
+            .enqueue(&self.queue)?;
+
+        let ends_event = SortKernel::new(&self.run_ends_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&sorted)?
+            .buffer(scan)?
+            .buffer(&ends)?
+            .wait(&compact_event)
+            .enqueue(&self.queue)?;
+
+        let runs = read_range(&self.queue, scan, n - 1, 1, &ends_event)?[0] as usize;
+        let run_values = self.read_result_range(&values, 0, runs, &ends_event)?;
+        let run_ends = self.read_result_range(&ends, 0, runs, &ends_event)?;
+
+        for buffer in scratch.into_iter().chain([sorted, flags, values, ends]) {
+            self.pool.give(n, CL_MEM_READ_WRITE, buffer);
+        }
+        let mut start = 0;
+        Ok(run_values
+            .into_iter()
+            .zip(run_ends)
+            .map(|(value, end)| {
+                let count = (end - start) as u32;
+                start = end;
+                (value, count)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{pseudo_random, test_sorter};
+
+    #[test]
+    fn top_k_reads_back_only_the_largest_k() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input = pseudo_random(500, 3);
+        let mut descending = input.clone();
+        descending.sort_unstable_by(|a, b| b.cmp(a));
+        for k in [0, 1, 7, 500, 600] {
+            assert_eq!(sorter.top_k(&input, k).unwrap(), descending[..k.min(500)]);
+        }
+        assert_eq!(sorter.top_k(&[4], 3).unwrap(), [4]);
+    }
+
+    #[test]
+    fn sort_unique_reads_back_only_the_distinct_prefix() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input: Vec<i32> = pseudo_random(500, 4).iter().map(|v| v % 10).collect();
+        let mut distinct = input.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        // The result is exactly the distinct values, with nothing of the
+        // buffer's tail after them.
+        assert_eq!(sorter.sort_unique(&input).unwrap(), distinct);
+        assert_eq!(sorter.sort_unique(&[5; 40]).unwrap(), [5]);
+        assert!(sorter.sort_unique(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn sort_both_returns_the_exact_reverse() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        for n in [2, 3, 256, 257, 1000, 1001] {
+            let input = pseudo_random(n, n as u64);
+            let mut expected = input.clone();
+            expected.sort_unstable();
+            let (ascending, descending) = sorter.sort_both(&input).unwrap();
+            assert_eq!(ascending, expected, "n = {}", n);
+            expected.reverse();
+            assert_eq!(descending, expected, "n = {}", n);
+        }
+        assert_eq!(sorter.sort_both(&[6]).unwrap(), (vec![6], vec![6]));
+    }
+
+    #[test]
+    fn sort_rle_counts_every_element_once() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input: Vec<i32> = pseudo_random(1000, 73).iter().map(|v| v % 16).collect();
+        let runs = sorter.sort_rle(&input).unwrap();
+        let total: usize = runs.iter().map(|&(_, count)| count as usize).sum();
+        assert_eq!(total, input.len());
+        for &(value, count) in &runs {
+            let occurrences = input.iter().filter(|&&v| v == value).count();
+            assert_eq!(count as usize, occurrences, "value {}", value);
+        }
+        assert!(runs.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        assert_eq!(sorter.sort_rle(&[3; 50]).unwrap(), [(3, 50)]);
+        assert_eq!(sorter.sort_rle(&[8]).unwrap(), [(8, 1)]);
+        assert!(sorter.sort_rle(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn sort_and_split_finds_the_first_element_at_or_above_the_threshold() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        // Even values only, so an odd threshold falls between elements.
+        let input: Vec<i32> = pseudo_random(500, 47)
+            .iter()
+            .map(|v| v % 1000 * 2)
+            .collect();
+        let (min, max) = (input.iter().min().unwrap(), input.iter().max().unwrap());
+        let mut expected = input.clone();
+        expected.sort_unstable();
+        for (threshold, split) in [
+            // Present: the split lands on its first copy...
+            (input[0], expected.partition_point(|&v| v < input[0])),
+            // ...and absent, on the first larger element.
+            (input[0] + 1, expected.partition_point(|&v| v <= input[0])),
+            (min - 1, 0),
+            (*min, 0),
+            (max + 1, input.len()),
+        ] {
+            assert_eq!(
+                sorter.sort_and_split(&input, threshold).unwrap(),
+                (expected.clone(), split),
+                "threshold {}",
+                threshold
+            );
+        }
+        assert_eq!(sorter.sort_and_split(&[], 3).unwrap(), (vec![], 0));
+        assert_eq!(sorter.sort_and_split(&[5], 3).unwrap(), (vec![5], 0));
+        assert_eq!(sorter.sort_and_split(&[5], 6).unwrap(), (vec![5], 1));
+    }
+
+    #[test]
+    fn sort_unique_counted_counts_the_collapsed_duplicates() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let input: Vec<i32> = pseudo_random(257, 14).iter().map(|v| v % 16).collect();
+        let mut distinct = input.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        assert_eq!(
+            sorter.sort_unique_counted(&input).unwrap(),
+            (distinct.clone(), input.len() - distinct.len())
+        );
+        assert_eq!(sorter.sort_unique_counted(&[]).unwrap(), (vec![], 0));
+    }
+}
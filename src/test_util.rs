@@ -0,0 +1,163 @@
+//! Helpers shared by the device tests.
+//!
+//! The device tests need an OpenCL GPU. Without one they return early,
+//! passing, after noting that they were skipped.
+
+use crate::builder::GpuSorterBuilder;
+use crate::error::SortError;
+use crate::sorter::GpuSorter;
+use crate::stable::SortOrder;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::{env, fs, process};
+
+/// A sorter on the first GPU, or `None` if there is no OpenCL GPU to test
+/// on.
+pub(crate) fn test_sorter() -> Option<GpuSorter> {
+    test_sorter_with(GpuSorter::builder())
+}
+
+/// Like [`test_sorter`], with `builder`'s settings.
+pub(crate) fn test_sorter_with(builder: GpuSorterBuilder) -> Option<GpuSorter> {
+    match builder.build() {
+        Ok(sorter) => Some(sorter),
+        Err(SortError::NoOpenCl | SortError::NoDevice) => {
+            eprintln!("skipping: no OpenCL GPU to test on");
+            None
+        }
+        Err(e) => panic!("failed to create a sorter: {}", e),
+    }
+}
+
+/// An empty directory under the system's temporary directory for the test
+/// `name`, emptied first if an earlier run left it behind.
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("ocl-sort-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// `n` pseudo-random integers spread over the whole `i32` range, the same
+/// for the same `seed`, without depending on the `rand` feature.
+pub(crate) fn pseudo_random(n: usize, seed: u64) -> Vec<i32> {
+    // xorshift64*, which only needs a non-zero state.
+    let mut state = seed | 1;
+    (0..n)
+        .map(|_| {
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            (state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32) as i32
+        })
+        .collect()
+}
+
+/// Asserts that `result_indices`, the input index of each output element of
+/// a sort of `keys`, is a permutation of the input that keeps every pair of
+/// equal keys in their input order.
+pub(crate) fn assert_stable(keys: &[i32], result_indices: &[u32]) {
+    assert_eq!(
+        result_indices.len(),
+        keys.len(),
+        "lost or duplicated elements"
+    );
+    let mut seen = vec![false; keys.len()];
+    let mut last_index: HashMap<i32, u32> = HashMap::new();
+    for (position, &index) in result_indices.iter().enumerate() {
+        let slot = seen
+            .get_mut(index as usize)
+            .unwrap_or_else(|| panic!("index {} at {} is out of range", index, position));
+        assert!(!*slot, "index {} appears twice", index);
+        *slot = true;
+
+        let key = keys[index as usize];
+        if let Some(previous) = last_index.insert(key, index) {
+            assert!(
+                previous < index,
+                "key {} from index {} was placed after its equal from index {}",
+                key,
+                previous,
+                index
+            );
+        }
+    }
+}
+
+/// Asserts that `output` is `input` sorted in `order`: as long as it, in
+/// order, and holding each of its values as many times.
+pub(crate) fn assert_valid_sort(input: &[i32], output: &[i32], order: SortOrder) {
+    assert_eq!(output.len(), input.len(), "the length changed");
+    if let Some(position) = output.windows(2).position(|pair| match order {
+        SortOrder::Ascending => pair[0] > pair[1],
+        SortOrder::Descending => pair[0] < pair[1],
+    }) {
+        panic!(
+            "out of {:?} order at {}: {} then {}",
+            order,
+            position,
+            output[position],
+            output[position + 1]
+        );
+    }
+    let mut counts: HashMap<i32, i64> = HashMap::new();
+    for &value in input {
+        *counts.entry(value).or_default() += 1;
+    }
+    for &value in output {
+        *counts.entry(value).or_default() -= 1;
+    }
+    counts.retain(|_, count| *count != 0);
+    assert!(
+        counts.is_empty(),
+        "not a permutation of the input; surplus per value: {:?}",
+        counts
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assert_valid_sort_accepts_sorted_permutations() {
+        assert_valid_sort(&[3, 1, 2, 1], &[1, 1, 2, 3], SortOrder::Ascending);
+        assert_valid_sort(&[3, 1, 2, 1], &[3, 2, 1, 1], SortOrder::Descending);
+        assert_valid_sort(&[], &[], SortOrder::Ascending);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of Ascending order")]
+    fn assert_valid_sort_rejects_unsorted_output() {
+        assert_valid_sort(&[1, 2], &[2, 1], SortOrder::Ascending);
+    }
+
+    #[test]
+    #[should_panic(expected = "not a permutation")]
+    fn assert_valid_sort_rejects_replaced_values() {
+        assert_valid_sort(&[1, 1, 2], &[1, 2, 2], SortOrder::Ascending);
+    }
+
+    #[test]
+    #[should_panic(expected = "the length changed")]
+    fn assert_valid_sort_rejects_lost_values() {
+        assert_valid_sort(&[1, 2], &[1], SortOrder::Ascending);
+    }
+
+    #[test]
+    fn assert_stable_accepts_ties_in_input_order() {
+        assert_stable(&[2, 1, 2, 1], &[1, 3, 0, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "placed after its equal")]
+    fn assert_stable_rejects_swapped_ties() {
+        assert_stable(&[2, 1, 2, 1], &[3, 1, 0, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "appears twice")]
+    fn assert_stable_rejects_repeated_indices() {
+        assert_stable(&[2, 1], &[1, 1]);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::buffer::{upload, SizedBuffer};
+use crate::error::{Result, SortError};
+use crate::launch::SortKernel;
+use crate::sorter::GpuSorter;
+use opencl3::memory::{CL_MEM_READ_WRITE, CL_MEM_WRITE_ONLY};
+use opencl3::types::cl_ulong;
+
+impl GpuSorter {
+    /// Sorts 64-bit unsigned integers with the rank sort, transferring them
+    /// as `ulong`s. The ranks are counted with 32-bit atomics on `int`s, so
+    /// the device doesn't need `cl_khr_int64_base_atomics`.
+    pub fn sort_u64(&self, input: &[u64]) -> Result<Vec<u64>> {
+        let n = input.len();
+        if n < 2 {
+            return Ok(input.to_vec());
+        }
+
+        let input_buffer = upload(&self.context, input)?;
+        let mut rank_buffer = self.pool.take_sized(&self.context, n, CL_MEM_READ_WRITE)?;
+        let result_buffer = SizedBuffer::<cl_ulong>::create(&self.context, CL_MEM_WRITE_ONLY, n)?;
+
+        let rank_fill_event = rank_buffer.fill(&self.queue, 0)?;
+
+        let rank_kernel_event = SortKernel::pairs(&self.rank_u64_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&input_buffer)?
+            .buffer(rank_buffer.buffer())?
+            .wait(&rank_fill_event)
+            .enqueue(&self.queue)?;
+
+        let scatter_kernel_event = SortKernel::new(&self.scatter_u64_kernel, n)
+            .checked(self.checked)
+            .round_to(self.work_group_size)
+            .buffer(&input_buffer)?
+            .buffer(rank_buffer.buffer())?
+            .buffer(result_buffer.buffer())?
+            .wait(&rank_kernel_event)
+            .enqueue(&self.queue)?;
+
+        let result = self.read_result_range(
+            result_buffer.buffer(),
+            0,
+            result_buffer.len(),
+            &scatter_kernel_event,
+        )?;
+
+        self.pool.give_sized(CL_MEM_READ_WRITE, rank_buffer);
+        Ok(result)
+    }
+
+    /// Returns the permutation that orders records by `primary` and then by
+    /// `secondary`, with records equal on both keeping their original order.
+    ///
+    /// Two `i32` keys always fit in 64 bits, so each record is packed into a
+    /// `u64`, primary in the high half and with the sign bits flipped so
+    /// unsigned order matches signed order, and argsorted in one pass.
+    /// Returns [`SortError::InvalidInput`] if the key slices' lengths differ.
+    pub fn sort_multikey(&self, primary: &[i32], secondary: &[i32]) -> Result<Vec<u32>> {
+        if primary.len() != secondary.len() {
+            return Err(SortError::InvalidInput(format!(
+                "{} primary keys but {} secondary keys",
+                primary.len(),
+                secondary.len()
+            )));
+        }
+        let flip = |key: i32| (key as u32 ^ 0x8000_0000) as u64;
+        let packed: Vec<u64> = primary
+            .iter()
+            .zip(secondary)
+            .map(|(&primary, &secondary)| flip(primary) << 32 | flip(secondary))
+            .collect();
+        self.argsort_u64(&packed)
+    }
+
+    /// Returns the permutation that sorts `keys` ascending. Ties are broken
+    /// by index, ranking each key after the strictly smaller keys and the
+    /// equal keys before it, so equal keys get consecutive ranks in their
+    /// original order: `[5, 1, 5, 1]` gives `[1, 3, 0, 2]`.
+    ///
+    /// The keys are widened to `u64`, with the sign bit flipped so unsigned
+    /// order matches signed order, and argsorted with the 64-bit rank kernel.
+    pub fn argsort_i32(&self, keys: &[i32]) -> Result<Vec<u32>> {
+        let widened: Vec<u64> = keys
+            .iter()
+            .map(|&key| (key as u32 ^ 0x8000_0000) as u64)
+            .collect();
+        self.argsort_u64(&widened)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::SortError;
+    use crate::test_util::{assert_stable, pseudo_random, test_sorter};
+
+    #[test]
+    fn sort_u64_orders_keys_past_32_bits() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let low = pseudo_random(500, 41);
+        let high = pseudo_random(500, 43);
+        let mut input: Vec<u64> = low
+            .iter()
+            .zip(&high)
+            .map(|(&low, &high)| (high as u32 as u64) << 32 | low as u32 as u64)
+            .collect();
+        // Keys equal in their low half, differing only above 2^32, and the
+        // extremes of the range.
+        input.extend([
+            1 << 32,
+            (1 << 32) + 1,
+            1,
+            u64::MAX,
+            1 << 63,
+            (1 << 63) - 1,
+            0,
+        ]);
+        assert!(input.iter().any(|&key| key >> 63 == 1));
+
+        let mut expected = input.clone();
+        expected.sort_unstable();
+        assert_eq!(sorter.sort_u64(&input).unwrap(), expected);
+    }
+
+    #[test]
+    fn argsort_i32_keeps_equal_keys_in_input_order() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        assert_eq!(sorter.argsort_i32(&[5, 1, 5, 1]).unwrap(), [1, 3, 0, 2]);
+        assert_eq!(
+            sorter.argsort_i32(&[0, i32::MIN, -1, i32::MAX, 0]).unwrap(),
+            [1, 2, 0, 4, 3]
+        );
+        let keys: Vec<i32> = pseudo_random(700, 101).iter().map(|v| v % 9).collect();
+        let indices = sorter.argsort_i32(&keys).unwrap();
+        assert_stable(&keys, &indices);
+        assert!(indices
+            .windows(2)
+            .all(|pair| keys[pair[0] as usize] <= keys[pair[1] as usize]));
+        assert!(sorter.argsort_i32(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn sort_multikey_breaks_primary_ties_by_the_secondary_key() {
+        let Some(sorter) = test_sorter() else {
+            return;
+        };
+        let primary = [2, 1, 2, -1, 1, 2, 1];
+        let secondary = [7, i32::MAX, -5, 0, i32::MIN, 7, 3];
+        // Equal on both keys, records 0 and 5 keep their order.
+        assert_eq!(
+            sorter.sort_multikey(&primary, &secondary).unwrap(),
+            [3, 4, 6, 1, 2, 0, 5]
+        );
+
+        let primary: Vec<i32> = pseudo_random(800, 79).iter().map(|v| v % 4).collect();
+        let secondary: Vec<i32> = pseudo_random(800, 83).iter().map(|v| v % 8).collect();
+        let mut expected: Vec<u32> = (0..800).collect();
+        expected.sort_by_key(|&i| (primary[i as usize], secondary[i as usize]));
+        assert_eq!(
+            sorter.sort_multikey(&primary, &secondary).unwrap(),
+            expected
+        );
+
+        assert!(matches!(
+            sorter.sort_multikey(&[1, 2], &[1]),
+            Err(SortError::InvalidInput(_))
+        ));
+    }
+}